@@ -26,6 +26,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .compile_protos(
             &[
                 "./proto/opentelemetry/common.proto",
+                "./proto/opentelemetry/logs.proto",
+                "./proto/opentelemetry/logs_service.proto",
+                "./proto/opentelemetry/metrics.proto",
+                "./proto/opentelemetry/metrics_service.proto",
                 "./proto/opentelemetry/resource.proto",
                 "./proto/opentelemetry/trace.proto",
                 "./proto/opentelemetry/trace_service.proto",
@@ -0,0 +1,62 @@
+use actix_web::{http::header::RETRY_AFTER, post, web, HttpRequest, HttpResponse};
+use bytes::Bytes;
+use prost::Message;
+
+use crate::{
+    cache::Cache,
+    db::{project_api_keys::ProjectApiKey, DB},
+    features::{is_feature_enabled, Feature},
+    opentelemetry::opentelemetry::proto::collector::logs::v1::ExportLogsServiceRequest,
+    routes::types::ResponseResult,
+    traces::{
+        limits::get_workspace_limit_exceeded_by_project_id,
+        logs::process_logs as process_otel_logs,
+        rate_limit::{count_log_records, RateLimiter},
+    },
+};
+
+#[post("logs")]
+pub async fn process_logs(
+    req: HttpRequest,
+    body: Bytes,
+    project_api_key: ProjectApiKey,
+    db: web::Data<DB>,
+    cache: web::Data<Cache>,
+    rate_limiter: web::Data<RateLimiter>,
+) -> ResponseResult {
+    let db = db.into_inner();
+    let request = ExportLogsServiceRequest::decode(body).map_err(|e| {
+        anyhow::anyhow!("Failed to decode ExportLogsServiceRequest from bytes. {e}")
+    })?;
+
+    if let Err(rate_limited) = rate_limiter.check(&project_api_key, count_log_records(&request)) {
+        return Ok(HttpResponse::TooManyRequests()
+            .insert_header((RETRY_AFTER, rate_limited.retry_after_secs().to_string()))
+            .json("Rate limit exceeded"));
+    }
+
+    if is_feature_enabled(Feature::UsageLimit) {
+        let limits_exceeded = get_workspace_limit_exceeded_by_project_id(
+            db.clone(),
+            cache.into_inner(),
+            project_api_key.project_id,
+        )
+        .await?;
+
+        // Logs belong to spans, so they are not accepted once spans are not
+        if limits_exceeded.spans {
+            return Ok(HttpResponse::Forbidden().json("Workspace span limit exceeded"));
+        }
+    }
+
+    process_otel_logs(request, project_api_key.project_id, db).await?;
+
+    let keep_alive = req.headers().get("connection").map_or(false, |v| {
+        v.to_str().unwrap_or_default().trim().to_lowercase() == "keep-alive"
+    });
+    if keep_alive {
+        Ok(HttpResponse::Ok().keep_alive().finish())
+    } else {
+        Ok(HttpResponse::Ok().finish())
+    }
+}
@@ -1,12 +1,35 @@
-use actix_web::{post, HttpRequest, HttpResponse};
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use bytes::Bytes;
+use prost::Message;
 
-use crate::routes::types::ResponseResult;
+use crate::{
+    ch::metrics::{insert_metrics, CHMetric, CumulativeToDelta},
+    db::project_api_keys::ProjectApiKey,
+    features::{is_feature_enabled, Feature},
+    opentelemetry::opentelemetry::proto::collector::metrics::v1::ExportMetricsServiceRequest,
+    routes::types::ResponseResult,
+};
 
 #[post("metrics")]
-pub async fn process_metrics(req: HttpRequest) -> ResponseResult {
-    // This is a placeholder that just returns ok, so that client otel exporters
-    // don't fail when trying to send metrics to the server.
-    // TODO: Implement metrics processing
+pub async fn process_metrics(
+    req: HttpRequest,
+    body: Bytes,
+    project_api_key: ProjectApiKey,
+    clickhouse: web::Data<clickhouse::Client>,
+    cumulative_to_delta: web::Data<CumulativeToDelta>,
+) -> ResponseResult {
+    let request = ExportMetricsServiceRequest::decode(body).map_err(|e| {
+        anyhow::anyhow!("Failed to decode ExportMetricsServiceRequest from bytes. {e}")
+    })?;
+
+    // Metrics are only stored in Clickhouse, so in lite mode we just accept them,
+    // so that client otel exporters don't fail when trying to send metrics to the server.
+    if is_feature_enabled(Feature::FullBuild) {
+        let metrics =
+            CHMetric::from_otel_request(request, project_api_key.project_id, &cumulative_to_delta);
+        insert_metrics(clickhouse.into_inner().as_ref().clone(), metrics).await?;
+    }
+
     let keep_alive = req.headers().get("connection").map_or(false, |v| {
         v.to_str().unwrap_or_default().trim().to_lowercase() == "keep-alive"
     });
@@ -1,5 +1,6 @@
 pub mod datasets;
 pub mod evaluations;
+pub mod logs;
 pub mod metrics;
 pub mod pipelines;
 pub mod traces;
@@ -1,8 +1,7 @@
 use std::sync::Arc;
 
-use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use actix_web::{get, http::header::RETRY_AFTER, post, web, HttpRequest, HttpResponse};
 use bytes::Bytes;
-use lapin::Connection;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -10,14 +9,20 @@ use crate::{
     db::{
         events::{self, EventObservation},
         project_api_keys::ProjectApiKey,
+        span_events::SpanEvent,
         spans::Span,
         DB,
     },
     features::{is_feature_enabled, Feature},
+    mq::MessageQueue,
     opentelemetry::opentelemetry::proto::collector::trace::v1::ExportTraceServiceRequest,
     routes::types::ResponseResult,
     storage::Storage,
-    traces::{limits::get_workspace_limit_exceeded_by_project_id, producer::push_spans_to_queue},
+    traces::{
+        limits::get_workspace_limit_exceeded_by_project_id,
+        producer::push_spans_to_queue,
+        rate_limit::{count_spans, RateLimiter},
+    },
 };
 use prost::Message;
 
@@ -26,6 +31,9 @@ pub struct RabbitMqSpanMessage {
     pub project_id: Uuid,
     pub span: Span,
     pub events: Vec<EventObservation>,
+    /// OTel span events, including exceptions
+    #[serde(default)]
+    pub span_events: Vec<SpanEvent>,
 }
 
 #[post("traces")]
@@ -33,17 +41,24 @@ pub async fn process_traces(
     req: HttpRequest,
     body: Bytes,
     project_api_key: ProjectApiKey,
-    rabbitmq_connection: web::Data<Option<Arc<Connection>>>,
+    queue: web::Data<Arc<dyn MessageQueue>>,
     db: web::Data<DB>,
     cache: web::Data<crate::cache::Cache>,
     storage: web::Data<dyn Storage>,
+    rate_limiter: web::Data<RateLimiter>,
 ) -> ResponseResult {
     let db = db.into_inner();
     let cache = cache.into_inner();
     let request = ExportTraceServiceRequest::decode(body).map_err(|e| {
         anyhow::anyhow!("Failed to decode ExportTraceServiceRequest from bytes. {e}")
     })?;
-    let rabbitmq_connection = rabbitmq_connection.as_ref().clone();
+
+    if let Err(rate_limited) = rate_limiter.check(&project_api_key, count_spans(&request)) {
+        return Ok(HttpResponse::TooManyRequests()
+            .insert_header((RETRY_AFTER, rate_limited.retry_after_secs().to_string()))
+            .json("Rate limit exceeded"));
+    }
+    let queue = queue.as_ref().clone();
     let storage = storage.into_inner();
 
     if is_feature_enabled(Feature::UsageLimit) {
@@ -63,23 +78,29 @@ pub async fn process_traces(
     let response = push_spans_to_queue(
         request,
         project_api_key.project_id,
-        rabbitmq_connection,
+        queue,
         storage,
         db,
         cache,
     )
     .await?;
-    if response.partial_success.is_some() {
-        return Err(anyhow::anyhow!("There has been an error during trace processing.").into());
-    }
 
     let keep_alive = req.headers().get("connection").map_or(false, |v| {
         v.to_str().unwrap_or_default().trim().to_lowercase() == "keep-alive"
     });
+    let mut http_response = HttpResponse::Ok();
     if keep_alive {
-        Ok(HttpResponse::Ok().keep_alive().finish())
+        http_response.keep_alive();
+    }
+
+    // Per OTLP/HTTP spec, partially accepted requests still succeed and report
+    // the rejected spans in the protobuf-encoded response body
+    if response.partial_success.is_some() {
+        Ok(http_response
+            .content_type("application/x-protobuf")
+            .body(response.encode_to_vec()))
     } else {
-        Ok(HttpResponse::Ok().finish())
+        Ok(http_response.finish())
     }
 }
 
@@ -5,7 +5,7 @@ use uuid::Uuid;
 
 use crate::evaluations::utils::EvaluationDatapointResult;
 
-use super::utils::{execute_query, validate_string_against_injection};
+use super::{query_builder::QueryBuilder, utils::execute_query};
 
 /// Evaluation score
 #[derive(Row, Serialize)]
@@ -95,16 +95,17 @@ pub async fn get_average_evaluation_score(
     evaluation_id: Uuid,
     name: String,
 ) -> Result<f64> {
-    validate_string_against_injection(&name)?;
-
-    let query = format!(
-        "SELECT avg(value) as average_value FROM evaluation_scores WHERE project_id = '{}' AND evaluation_id = '{}' AND name = '{}'",
-        project_id,
-        evaluation_id,
-        name
+    let mut query = QueryBuilder::new(
+        "SELECT avg(value) as average_value FROM evaluation_scores WHERE project_id = ",
     );
-
-    let rows: Vec<AverageEvaluationScore> = execute_query(&clickhouse, &query).await?;
+    query
+        .push_bind(project_id)
+        .push(" AND evaluation_id = ")
+        .push_bind(evaluation_id)
+        .push(" AND name = ")
+        .push_bind(name);
+
+    let rows: Vec<AverageEvaluationScore> = execute_query(&clickhouse, query).await?;
     Ok(rows[0].average_value)
 }
 
@@ -124,24 +125,27 @@ pub async fn get_evaluation_score_buckets_based_on_bounds(
     upper_bound: f64,
     bucket_count: u64,
 ) -> Result<Vec<EvaluationScoreBucket>> {
-    validate_string_against_injection(&name)?;
-
     let step_size = (upper_bound - lower_bound) / bucket_count as f64;
-    let interval_nums = (1..=bucket_count)
-        .map(|num| num.to_string())
-        .collect::<Vec<String>>()
-        .join(",");
 
-    // This query uses {:?} with the purpose to render floats like 1.0 as 1.0 instead of 1
-    let query = format!(
-        "
-WITH intervals AS (
+    // Bounds are cast explicitly, so that floats like 1.0 are not inferred as integers
+    let mut query = QueryBuilder::new("\nWITH toFloat64(");
+    query
+        .push_bind(lower_bound)
+        .push(") AS min_bound,\n    toFloat64(")
+        .push_bind(upper_bound)
+        .push(") AS max_bound,\n    toFloat64(")
+        .push_bind(step_size)
+        .push(") AS step_size,\n    toUInt64(")
+        .push_bind(bucket_count)
+        .push(
+            ") AS bucket_count,
+intervals AS (
     SELECT
-        arrayJoin([{}]) AS interval_num,
-        {:?} + ((interval_num - 1) * {:?}) AS lower_bound,
+        arrayJoin(range(1, bucket_count + 1)) AS interval_num,
+        min_bound + ((interval_num - 1) * step_size) AS lower_bound,
         CASE
-            WHEN interval_num = {} THEN {:?}
-            ELSE {:?} + (interval_num * {:?})
+            WHEN interval_num = bucket_count THEN max_bound
+            ELSE min_bound + (interval_num * step_size)
         END AS upper_bound
 )
 SELECT
@@ -149,20 +153,25 @@ SELECT
     intervals.upper_bound,
     COUNT(CASE
         WHEN value >= intervals.lower_bound AND value < intervals.upper_bound THEN 1
-        WHEN intervals.interval_num = {} AND value >= intervals.lower_bound AND value <= intervals.upper_bound THEN 1
+        WHEN intervals.interval_num = bucket_count AND value >= intervals.lower_bound AND value <= intervals.upper_bound THEN 1
         ELSE NULL
     END) AS height
 FROM evaluation_scores
 JOIN intervals ON 1 = 1
-WHERE project_id = '{}'
-AND evaluation_id = '{}'
-AND name = '{}'
+WHERE project_id = ",
+        )
+        .push_bind(project_id)
+        .push("\nAND evaluation_id = ")
+        .push_bind(evaluation_id)
+        .push("\nAND name = ")
+        .push_bind(name)
+        .push(
+            "
 GROUP BY intervals.lower_bound, intervals.upper_bound, intervals.interval_num
 ORDER BY intervals.interval_num",
-        interval_nums, lower_bound, step_size, bucket_count, upper_bound, lower_bound, step_size, bucket_count, project_id, evaluation_id, name
-    );
+        );
 
-    let rows: Vec<EvaluationScoreBucket> = execute_query(&clickhouse, &query).await?;
+    let rows: Vec<EvaluationScoreBucket> = execute_query(&clickhouse, query).await?;
 
     Ok(rows)
 }
@@ -178,25 +187,20 @@ pub async fn get_global_evaluation_scores_bounds(
     evaluation_ids: &Vec<Uuid>,
     name: String,
 ) -> Result<ComparedEvaluationScoresBounds> {
-    validate_string_against_injection(&name)?;
-
-    let evaluation_ids_str = evaluation_ids
-        .iter()
-        .map(|id| format!("'{}'", id))
-        .collect::<Vec<String>>()
-        .join(",");
-
-    let query = format!(
+    let mut query = QueryBuilder::new(
         "
 SELECT
     MAX(value) AS upper_bound
 FROM evaluation_scores
-WHERE project_id = '{}'
-  AND evaluation_id IN ({})
-  AND name = '{}'",
-        project_id, evaluation_ids_str, name
+WHERE project_id = ",
     );
-
-    let rows: Vec<ComparedEvaluationScoresBounds> = execute_query(&clickhouse, &query).await?;
+    query
+        .push_bind(project_id)
+        .push("\n  AND evaluation_id IN ")
+        .push_bind(evaluation_ids.clone())
+        .push("\n  AND name = ")
+        .push_bind(name);
+
+    let rows: Vec<ComparedEvaluationScoresBounds> = execute_query(&clickhouse, query).await?;
     Ok(rows[0].clone())
 }
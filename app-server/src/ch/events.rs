@@ -9,9 +9,10 @@ use crate::db::{self, event_templates::EventTemplate};
 
 use super::{
     modifiers::GroupByInterval,
+    query_builder::QueryBuilder,
     utils::{
-        chrono_to_nanoseconds, execute_query, group_by_time_absolute_statement,
-        group_by_time_relative_statement,
+        chrono_to_nanoseconds, execute_query, push_absolute_time_condition,
+        push_group_by_time_absolute, push_group_by_time_relative, push_relative_time_condition,
     },
     MetricTimeValue,
 };
@@ -123,23 +124,11 @@ pub async fn get_total_event_count_metrics_relative(
     template_id: Uuid,
     past_hours: i64,
 ) -> Result<Vec<MetricTimeValue<i64>>> {
-    let ch_round_time = group_by_interval.to_ch_truncate_time();
+    let mut query = event_count_query(group_by_interval, project_id, template_id);
+    push_relative_time_condition(&mut query, "timestamp", past_hours);
+    push_group_by_time_relative(&mut query, past_hours, group_by_interval);
 
-    let query_string = format!(
-        "
-    SELECT
-        {ch_round_time}(timestamp) AS time,
-        COUNT(DISTINCT id) AS value
-    FROM events
-    WHERE
-        project_id = '{project_id}'
-        AND template_id = '{template_id}'
-        AND timestamp >= now() - INTERVAL {past_hours} HOUR
-    {}",
-        group_by_time_relative_statement(past_hours, group_by_interval),
-    );
-
-    execute_query(&clickhouse, &query_string).await
+    execute_query(&clickhouse, query).await
 }
 
 pub async fn get_total_event_count_metrics_absolute(
@@ -150,24 +139,33 @@ pub async fn get_total_event_count_metrics_absolute(
     start_time: DateTime<Utc>,
     end_time: DateTime<Utc>,
 ) -> Result<Vec<MetricTimeValue<i64>>> {
+    let mut query = event_count_query(group_by_interval, project_id, template_id);
+    push_absolute_time_condition(&mut query, "timestamp", start_time, end_time);
+    push_group_by_time_absolute(&mut query, start_time, end_time, group_by_interval);
+
+    execute_query(&clickhouse, query).await
+}
+
+/// Count query up to the time condition
+fn event_count_query(
+    group_by_interval: GroupByInterval,
+    project_id: Uuid,
+    template_id: Uuid,
+) -> QueryBuilder {
     let ch_round_time = group_by_interval.to_ch_truncate_time();
-    let ch_start_time = start_time.timestamp();
-    let ch_end_time = end_time.timestamp();
 
-    let query_string = format!(
+    let mut query = QueryBuilder::new(format!(
         "
     SELECT
         {ch_round_time}(timestamp) AS time,
         COUNT(DISTINCT id) AS value
     FROM events
     WHERE
-        project_id = '{project_id}'
-        AND template_id = '{template_id}'
-        AND timestamp >= fromUnixTimestamp({ch_start_time})
-        AND timestamp <= fromUnixTimestamp({ch_end_time})
-    {}",
-        group_by_time_absolute_statement(start_time, end_time, group_by_interval)
-    );
-
-    execute_query(&clickhouse, &query_string).await
+        project_id = "
+    ));
+    query
+        .push_bind(project_id)
+        .push(" AND template_id = ")
+        .push_bind(template_id);
+    query
 }
@@ -0,0 +1,562 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use clickhouse::Row;
+use serde::Serialize;
+use serde_json::Value;
+use serde_repr::Serialize_repr;
+use uuid::Uuid;
+
+use crate::{
+    db::utils::convert_any_value_to_json_value,
+    opentelemetry::{
+        opentelemetry::proto::collector::metrics::v1::ExportMetricsServiceRequest,
+        opentelemetry_proto_common_v1::KeyValue,
+        opentelemetry_proto_metrics_v1::{
+            metric, number_data_point, AggregationTemporality, Metric, NumberDataPoint,
+        },
+    },
+};
+
+use super::{
+    modifiers::GroupByInterval,
+    query_builder::QueryBuilder,
+    utils::{
+        execute_query, push_absolute_time_condition, push_group_by_time_absolute,
+        push_group_by_time_relative, push_relative_time_condition,
+    },
+    Aggregation, MetricTimeValue,
+};
+
+#[derive(Debug, Clone, Copy, Serialize_repr)]
+#[repr(u8)]
+#[allow(non_camel_case_types)]
+pub enum MetricType {
+    GAUGE = 0,
+    SUM = 1,
+    HISTOGRAM = 2,
+    EXPONENTIAL_HISTOGRAM = 3,
+    SUMMARY = 4,
+}
+
+/// OTel aggregation temporality, which gauges do not have
+#[derive(Debug, Clone, Copy, PartialEq, Serialize_repr)]
+#[repr(u8)]
+#[allow(non_camel_case_types)]
+pub enum Temporality {
+    UNSPECIFIED = 0,
+    DELTA = 1,
+    CUMULATIVE = 2,
+}
+
+impl From<i32> for Temporality {
+    fn from(value: i32) -> Self {
+        match AggregationTemporality::try_from(value) {
+            Ok(AggregationTemporality::Delta) => Temporality::DELTA,
+            Ok(AggregationTemporality::Cumulative) => Temporality::CUMULATIVE,
+            _ => Temporality::UNSPECIFIED,
+        }
+    }
+}
+
+/// Series which were not exported for this long are forgotten
+const CUMULATIVE_SERIES_TTL: Duration = Duration::from_secs(60 * 60);
+const MAX_CUMULATIVE_SERIES: u64 = 100_000;
+
+/// Last point of a cumulative series
+#[derive(Clone)]
+struct CumulativePoint {
+    start_time_unix_nano: u64,
+    value: f64,
+    count: u64,
+}
+
+/// Converts cumulative points to deltas from the previous point of the same series, so that
+/// the stored values of a time range can be summed.
+///
+/// The last point of each series is kept in memory, so each series must be exported to the
+/// same instance. The first point of a series is only stored if the series started after the
+/// instance, otherwise its value since the start may have been stored before a restart.
+pub struct CumulativeToDelta {
+    last_points: moka::sync::Cache<String, CumulativePoint>,
+    started_at_unix_nano: u64,
+}
+
+impl CumulativeToDelta {
+    pub fn new() -> Self {
+        Self {
+            last_points: moka::sync::Cache::builder()
+                .max_capacity(MAX_CUMULATIVE_SERIES)
+                .time_to_idle(CUMULATIVE_SERIES_TTL)
+                .build(),
+            started_at_unix_nano: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos() as u64,
+        }
+    }
+
+    /// Returns the value and count since the previous point of the series, or `None` if the
+    /// point must not be stored
+    fn to_delta(
+        &self,
+        series: String,
+        start_time_unix_nano: u64,
+        value: f64,
+        count: u64,
+        is_monotonic: bool,
+    ) -> Option<(f64, u64)> {
+        let point = CumulativePoint {
+            start_time_unix_nano,
+            value,
+            count,
+        };
+        let mut delta = None;
+        self.last_points
+            .entry(series)
+            .and_compute_with(|last_point| {
+                let last_point = last_point.map(|entry| entry.into_value());
+                let is_reset = |last: &CumulativePoint| {
+                    last.start_time_unix_nano != start_time_unix_nano
+                        || count < last.count
+                        || (is_monotonic && value < last.value)
+                };
+                delta = match last_point {
+                    Some(last) if !is_reset(&last) => {
+                        Some((value - last.value, count - last.count))
+                    }
+                    // The series was restarted, so the point is the value since the new start
+                    Some(_) => Some((value, count)),
+                    None if start_time_unix_nano >= self.started_at_unix_nano => {
+                        Some((value, count))
+                    }
+                    None => None,
+                };
+                moka::ops::compute::Op::Put(point)
+            });
+        delta
+    }
+}
+
+/// A single OTel data point, flattened for storage.
+///
+/// For gauges and sums `value` is the point value and `count` is 1. For histograms
+/// and summaries `value` is the sum of the observed population and `count` is its size,
+/// so that `SUM(value) / SUM(count)` is the average across all metric types.
+///
+/// Cumulative points are stored as deltas from the previous point of the series, so that
+/// `SUM(value)` is the total of a time range regardless of the temporality.
+#[derive(Row, Serialize)]
+pub struct CHMetric {
+    #[serde(with = "clickhouse::serde::uuid")]
+    pub project_id: Uuid,
+    pub name: String,
+    pub description: String,
+    pub unit: String,
+    pub metric_type: MetricType,
+    /// Temporality the point was exported with
+    pub temporality: Temporality,
+    pub is_monotonic: bool,
+    /// Timestamp in nanoseconds
+    pub timestamp: i64,
+    pub value: f64,
+    pub count: u64,
+    /// JSON-encoded data point attributes
+    pub attributes: String,
+}
+
+impl CHMetric {
+    pub fn from_otel_request(
+        request: ExportMetricsServiceRequest,
+        project_id: Uuid,
+        cumulative_to_delta: &CumulativeToDelta,
+    ) -> Vec<Self> {
+        let mut metrics = Vec::new();
+        for resource_metrics in request.resource_metrics {
+            // Identifies the process which exported the series, e.g. by `service.instance.id`
+            let resource = attributes_to_json_string(
+                resource_metrics
+                    .resource
+                    .map(|resource| resource.attributes)
+                    .unwrap_or_default(),
+            );
+            for metric in resource_metrics
+                .scope_metrics
+                .into_iter()
+                .flat_map(|scope_metrics| scope_metrics.metrics)
+            {
+                metrics.extend(Self::from_otel_metric(
+                    metric,
+                    project_id,
+                    &resource,
+                    cumulative_to_delta,
+                ));
+            }
+        }
+        metrics
+    }
+
+    fn from_otel_metric(
+        metric: Metric,
+        project_id: Uuid,
+        resource: &str,
+        cumulative_to_delta: &CumulativeToDelta,
+    ) -> Vec<Self> {
+        let Some(data) = metric.data else {
+            return Vec::new();
+        };
+
+        // Data point of any metric type, before the conversion of cumulative points
+        struct Point {
+            start_time_unix_nano: u64,
+            time_unix_nano: u64,
+            value: f64,
+            count: u64,
+            attributes: Vec<KeyValue>,
+        }
+
+        let (metric_type, temporality, is_monotonic, points) = match data {
+            metric::Data::Gauge(gauge) => (
+                MetricType::GAUGE,
+                Temporality::UNSPECIFIED,
+                false,
+                gauge
+                    .data_points
+                    .into_iter()
+                    .map(|point| Point {
+                        start_time_unix_nano: point.start_time_unix_nano,
+                        time_unix_nano: point.time_unix_nano,
+                        value: number_data_point_value(&point),
+                        count: 1,
+                        attributes: point.attributes,
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+            metric::Data::Sum(sum) => (
+                MetricType::SUM,
+                Temporality::from(sum.aggregation_temporality),
+                sum.is_monotonic,
+                sum.data_points
+                    .into_iter()
+                    .map(|point| Point {
+                        start_time_unix_nano: point.start_time_unix_nano,
+                        time_unix_nano: point.time_unix_nano,
+                        value: number_data_point_value(&point),
+                        count: 1,
+                        attributes: point.attributes,
+                    })
+                    .collect(),
+            ),
+            metric::Data::Histogram(histogram) => (
+                MetricType::HISTOGRAM,
+                Temporality::from(histogram.aggregation_temporality),
+                false,
+                histogram
+                    .data_points
+                    .into_iter()
+                    .map(|point| Point {
+                        start_time_unix_nano: point.start_time_unix_nano,
+                        time_unix_nano: point.time_unix_nano,
+                        value: point.sum.unwrap_or_default(),
+                        count: point.count,
+                        attributes: point.attributes,
+                    })
+                    .collect(),
+            ),
+            metric::Data::ExponentialHistogram(histogram) => (
+                MetricType::EXPONENTIAL_HISTOGRAM,
+                Temporality::from(histogram.aggregation_temporality),
+                false,
+                histogram
+                    .data_points
+                    .into_iter()
+                    .map(|point| Point {
+                        start_time_unix_nano: point.start_time_unix_nano,
+                        time_unix_nano: point.time_unix_nano,
+                        value: point.sum.unwrap_or_default(),
+                        count: point.count,
+                        attributes: point.attributes,
+                    })
+                    .collect(),
+            ),
+            // Summaries are always cumulative
+            metric::Data::Summary(summary) => (
+                MetricType::SUMMARY,
+                Temporality::CUMULATIVE,
+                false,
+                summary
+                    .data_points
+                    .into_iter()
+                    .map(|point| Point {
+                        start_time_unix_nano: point.start_time_unix_nano,
+                        time_unix_nano: point.time_unix_nano,
+                        value: point.sum,
+                        count: point.count,
+                        attributes: point.attributes,
+                    })
+                    .collect(),
+            ),
+        };
+
+        points
+            .into_iter()
+            .filter_map(|point| {
+                let attributes = attributes_to_json_string(point.attributes);
+                let (value, count) = if temporality == Temporality::CUMULATIVE {
+                    let series = format!("{project_id}/{resource}/{}/{attributes}", metric.name);
+                    cumulative_to_delta.to_delta(
+                        series,
+                        point.start_time_unix_nano,
+                        point.value,
+                        point.count,
+                        is_monotonic,
+                    )?
+                } else {
+                    (point.value, point.count)
+                };
+
+                Some(CHMetric {
+                    project_id,
+                    name: metric.name.clone(),
+                    description: metric.description.clone(),
+                    unit: metric.unit.clone(),
+                    metric_type,
+                    temporality,
+                    is_monotonic,
+                    timestamp: point.time_unix_nano as i64,
+                    value,
+                    // Sums have one value per point, not a population
+                    count: if matches!(metric_type, MetricType::SUM) {
+                        1
+                    } else {
+                        count
+                    },
+                    attributes,
+                })
+            })
+            .collect()
+    }
+}
+
+fn number_data_point_value(point: &NumberDataPoint) -> f64 {
+    match point.value {
+        Some(number_data_point::Value::AsDouble(value)) => value,
+        Some(number_data_point::Value::AsInt(value)) => value as f64,
+        None => 0.0,
+    }
+}
+
+fn attributes_to_json_string(attributes: Vec<KeyValue>) -> String {
+    let attributes = attributes
+        .into_iter()
+        .filter(|kv| kv.value.as_ref().is_some_and(|v| v.value.is_some()))
+        .map(|kv| (kv.key, convert_any_value_to_json_value(kv.value)))
+        .collect::<serde_json::Map<String, Value>>();
+    Value::Object(attributes).to_string()
+}
+
+pub async fn insert_metrics(clickhouse: clickhouse::Client, metrics: Vec<CHMetric>) -> Result<()> {
+    if metrics.is_empty() {
+        return Ok(());
+    }
+
+    let ch_insert = clickhouse.insert("metrics");
+    match ch_insert {
+        Ok(mut ch_insert) => {
+            for metric in metrics {
+                ch_insert.write(&metric).await?;
+            }
+            let ch_insert_end_res = ch_insert.end().await;
+            match ch_insert_end_res {
+                Ok(_) => Ok(()),
+                Err(e) => Err(anyhow::anyhow!(
+                    "Clickhouse metrics insertion failed: {:?}",
+                    e
+                )),
+            }
+        }
+        Err(e) => {
+            return Err(anyhow::anyhow!(
+                "Failed to insert metrics into Clickhouse: {:?}",
+                e
+            ));
+        }
+    }
+}
+
+/// Histograms and summaries carry a population per data point, so the average
+/// is weighted by `count` rather than taken over the stored rows.
+/// Min, max and quantiles are taken over the average of each data point.
+fn metric_value_expression(aggregation: &Aggregation) -> String {
+    match aggregation {
+        Aggregation::Total => String::from("SUM(value)"),
+        Aggregation::Average => String::from("if(SUM(count) = 0, 0, SUM(value) / SUM(count))"),
+        _ => format!(
+            "{}(if(count = 0, 0, value / count))",
+            aggregation.to_ch_agg_function()
+        ),
+    }
+}
+
+pub async fn get_metric_values_relative(
+    clickhouse: clickhouse::Client,
+    group_by_interval: GroupByInterval,
+    project_id: Uuid,
+    name: &str,
+    past_hours: i64,
+    aggregation: Aggregation,
+) -> Result<Vec<MetricTimeValue<f64>>> {
+    let mut query = metric_values_query(group_by_interval, project_id, name, &aggregation);
+    push_relative_time_condition(&mut query, "timestamp", past_hours);
+    push_group_by_time_relative(&mut query, past_hours, group_by_interval);
+
+    execute_query(&clickhouse, query).await
+}
+
+pub async fn get_metric_values_absolute(
+    clickhouse: clickhouse::Client,
+    group_by_interval: GroupByInterval,
+    project_id: Uuid,
+    name: &str,
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+    aggregation: Aggregation,
+) -> Result<Vec<MetricTimeValue<f64>>> {
+    let mut query = metric_values_query(group_by_interval, project_id, name, &aggregation);
+    push_absolute_time_condition(&mut query, "timestamp", start_time, end_time);
+    push_group_by_time_absolute(&mut query, start_time, end_time, group_by_interval);
+
+    execute_query(&clickhouse, query).await
+}
+
+/// Metric values query up to the time condition
+fn metric_values_query(
+    group_by_interval: GroupByInterval,
+    project_id: Uuid,
+    name: &str,
+    aggregation: &Aggregation,
+) -> QueryBuilder {
+    let ch_round_time = group_by_interval.to_ch_truncate_time();
+    let ch_value = metric_value_expression(aggregation);
+
+    let mut query = QueryBuilder::new(format!(
+        "
+    SELECT
+        {ch_round_time}(timestamp) AS time,
+        {ch_value} AS value
+    FROM metrics
+    WHERE
+        project_id = "
+    ));
+    query
+        .push_bind(project_id)
+        .push(" AND name = ")
+        .push_bind(name);
+    query
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::opentelemetry::opentelemetry_proto_metrics_v1::{
+        HistogramDataPoint, ResourceMetrics, ScopeMetrics, Sum,
+    };
+
+    use super::*;
+
+    fn cumulative_sum(start_time_unix_nano: u64, values: &[i64]) -> ExportMetricsServiceRequest {
+        let data_points = values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| NumberDataPoint {
+                start_time_unix_nano,
+                time_unix_nano: start_time_unix_nano + i as u64 + 1,
+                value: Some(number_data_point::Value::AsInt(*value)),
+                ..Default::default()
+            })
+            .collect();
+        ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                scope_metrics: vec![ScopeMetrics {
+                    metrics: vec![Metric {
+                        name: String::from("requests"),
+                        data: Some(metric::Data::Sum(Sum {
+                            data_points,
+                            aggregation_temporality: AggregationTemporality::Cumulative as i32,
+                            is_monotonic: true,
+                        })),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        }
+    }
+
+    #[test]
+    fn test_cumulative_sums_are_stored_as_deltas() {
+        let cumulative_to_delta = CumulativeToDelta::new();
+        let start_time = cumulative_to_delta.started_at_unix_nano + 1;
+
+        let metrics = CHMetric::from_otel_request(
+            cumulative_sum(start_time, &[3, 5, 5, 9]),
+            Uuid::nil(),
+            &cumulative_to_delta,
+        );
+        let values = metrics.iter().map(|m| m.value).collect::<Vec<_>>();
+        assert_eq!(values, vec![3.0, 2.0, 0.0, 4.0]);
+        assert!(metrics.iter().all(|m| m.count == 1));
+        assert_eq!(metrics[0].temporality, Temporality::CUMULATIVE);
+
+        // The counter went down, so the process restarted and counts from zero again
+        let metrics = CHMetric::from_otel_request(
+            cumulative_sum(start_time, &[2]),
+            Uuid::nil(),
+            &cumulative_to_delta,
+        );
+        assert_eq!(metrics[0].value, 2.0);
+    }
+
+    #[test]
+    fn test_first_point_of_series_started_before_instance_is_dropped() {
+        let cumulative_to_delta = CumulativeToDelta::new();
+
+        let metrics = CHMetric::from_otel_request(
+            cumulative_sum(1, &[100, 104]),
+            Uuid::nil(),
+            &cumulative_to_delta,
+        );
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(metrics[0].value, 4.0);
+    }
+
+    #[test]
+    fn test_cumulative_histograms_are_stored_as_deltas() {
+        let cumulative_to_delta = CumulativeToDelta::new();
+        let start_time = cumulative_to_delta.started_at_unix_nano;
+        let point = |sum: f64, count: u64| HistogramDataPoint {
+            start_time_unix_nano: start_time,
+            sum: Some(sum),
+            count,
+            ..Default::default()
+        };
+        let metric = Metric {
+            name: String::from("latency"),
+            data: Some(metric::Data::Histogram(
+                crate::opentelemetry::opentelemetry_proto_metrics_v1::Histogram {
+                    data_points: vec![point(10.0, 4), point(16.0, 6)],
+                    aggregation_temporality: AggregationTemporality::Cumulative as i32,
+                },
+            )),
+            ..Default::default()
+        };
+
+        let metrics = CHMetric::from_otel_metric(metric, Uuid::nil(), "{}", &cumulative_to_delta);
+        let values = metrics
+            .iter()
+            .map(|m| (m.value, m.count))
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec![(10.0, 4), (6.0, 2)]);
+    }
+}
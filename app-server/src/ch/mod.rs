@@ -3,7 +3,10 @@ use serde::{Deserialize, Serialize};
 
 pub mod evaluation_scores;
 pub mod events;
+pub mod metrics;
 pub mod modifiers;
+pub mod query_builder;
+pub mod retention;
 pub mod spans;
 pub mod utils;
 
@@ -11,6 +14,12 @@ pub mod utils;
 pub enum Aggregation {
     Total,
     Average,
+    Min,
+    Max,
+    P50,
+    P90,
+    P95,
+    P99,
 }
 
 impl Aggregation {
@@ -18,6 +27,12 @@ impl Aggregation {
         match self {
             Aggregation::Total => "SUM",
             Aggregation::Average => "AVG",
+            Aggregation::Min => "MIN",
+            Aggregation::Max => "MAX",
+            Aggregation::P50 => "quantile(0.5)",
+            Aggregation::P90 => "quantile(0.9)",
+            Aggregation::P95 => "quantile(0.95)",
+            Aggregation::P99 => "quantile(0.99)",
         }
     }
 }
@@ -27,3 +42,35 @@ pub struct MetricTimeValue<T> {
     pub time: u32,
     pub value: T,
 }
+
+#[derive(Deserialize, Row, Serialize)]
+pub struct GroupedMetricTimeValue<T> {
+    pub group_value: String,
+    pub time: u32,
+    pub value: T,
+}
+
+#[derive(Serialize)]
+pub struct MetricSeries<T> {
+    pub group: String,
+    pub values: Vec<MetricTimeValue<T>>,
+}
+
+/// Splits rows ordered by group into one series per group
+pub fn group_into_series<T>(rows: Vec<GroupedMetricTimeValue<T>>) -> Vec<MetricSeries<T>> {
+    let mut series: Vec<MetricSeries<T>> = Vec::new();
+    for row in rows {
+        let value = MetricTimeValue {
+            time: row.time,
+            value: row.value,
+        };
+        match series.last_mut() {
+            Some(last) if last.group == row.group_value => last.values.push(value),
+            _ => series.push(MetricSeries {
+                group: row.group_value,
+                values: vec![value],
+            }),
+        }
+    }
+    series
+}
@@ -1,5 +1,9 @@
 use serde::Deserialize;
 
+use crate::traces::span_attributes::{
+    DEPLOYMENT_ENVIRONMENT, HOST_NAME, SERVICE_NAME, TELEMETRY_SDK_VERSION,
+};
+
 #[derive(Deserialize, Clone, Copy)]
 #[serde(rename_all = "camelCase")]
 pub enum GroupByInterval {
@@ -39,3 +43,54 @@ impl GroupByInterval {
         }
     }
 }
+
+/// Dimension to split metrics by, one series per value
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum GroupByDimension {
+    Model,
+    Provider,
+    Path,
+    UserId,
+    SessionId,
+    /// Span name
+    Name,
+    ServiceName,
+    Environment,
+    HostName,
+    SdkVersion,
+    ScopeName,
+}
+
+impl GroupByDimension {
+    pub fn to_ch_expression(&self) -> String {
+        match self {
+            GroupByDimension::Model => String::from("model"),
+            GroupByDimension::Provider => String::from("provider"),
+            GroupByDimension::Path => String::from("path"),
+            GroupByDimension::UserId => String::from("user_id"),
+            GroupByDimension::SessionId => String::from("session_id"),
+            GroupByDimension::Name => String::from("name"),
+            GroupByDimension::ServiceName => resource_attribute_expression(SERVICE_NAME),
+            GroupByDimension::Environment => resource_attribute_expression(DEPLOYMENT_ENVIRONMENT),
+            GroupByDimension::HostName => resource_attribute_expression(HOST_NAME),
+            GroupByDimension::SdkVersion => resource_attribute_expression(TELEMETRY_SDK_VERSION),
+            GroupByDimension::ScopeName => String::from("scope_name"),
+        }
+    }
+}
+
+/// Split of a metric into one series per value of `dimension`
+#[derive(Clone, Copy)]
+pub struct GroupBy {
+    pub dimension: GroupByDimension,
+    /// Maximum number of series
+    pub limit: u32,
+    /// Whether spans without a value, e.g. the spans which are not LLM calls when grouping
+    /// by model, form a series of their own
+    pub include_missing: bool,
+}
+
+fn resource_attribute_expression(attribute: &str) -> String {
+    format!("JSONExtractString(resource_attributes, '{attribute}')")
+}
@@ -0,0 +1,151 @@
+//! Builds Clickhouse queries with bound parameters, in the same way as `sqlx::QueryBuilder`.
+//!
+//! Values are sent separately from the SQL text and are escaped by the client, so anything
+//! coming from a request must be added with `push_bind`. `push` is only for trusted SQL, such
+//! as column names and expressions defined in the code.
+
+use serde::Serialize;
+use uuid::Uuid;
+
+/// Value bound to a `?` placeholder
+#[derive(Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum QueryParam {
+    String(String),
+    Int(i64),
+    Float(f64),
+    StringArray(Vec<String>),
+    /// Bound as an array of strings, which Clickhouse converts to UUIDs when used
+    /// with `IN` on a UUID column
+    UuidArray(Vec<Uuid>),
+}
+
+impl From<&str> for QueryParam {
+    fn from(value: &str) -> Self {
+        QueryParam::String(value.to_string())
+    }
+}
+
+impl From<String> for QueryParam {
+    fn from(value: String) -> Self {
+        QueryParam::String(value)
+    }
+}
+
+/// Clickhouse converts the string to UUID when comparing with a UUID column
+impl From<Uuid> for QueryParam {
+    fn from(value: Uuid) -> Self {
+        QueryParam::String(value.to_string())
+    }
+}
+
+impl From<i64> for QueryParam {
+    fn from(value: i64) -> Self {
+        QueryParam::Int(value)
+    }
+}
+
+impl From<u64> for QueryParam {
+    fn from(value: u64) -> Self {
+        QueryParam::Int(value as i64)
+    }
+}
+
+impl From<u8> for QueryParam {
+    fn from(value: u8) -> Self {
+        QueryParam::Int(value as i64)
+    }
+}
+
+impl From<f64> for QueryParam {
+    fn from(value: f64) -> Self {
+        QueryParam::Float(value)
+    }
+}
+
+impl From<Vec<String>> for QueryParam {
+    fn from(value: Vec<String>) -> Self {
+        QueryParam::StringArray(value)
+    }
+}
+
+impl From<Vec<Uuid>> for QueryParam {
+    fn from(value: Vec<Uuid>) -> Self {
+        QueryParam::UuidArray(value)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct QueryBuilder {
+    sql: String,
+    params: Vec<QueryParam>,
+}
+
+impl QueryBuilder {
+    pub fn new(sql: impl AsRef<str>) -> Self {
+        let mut query = Self::default();
+        query.push(sql);
+        query
+    }
+
+    /// Appends trusted SQL, which must not contain `?`
+    pub fn push(&mut self, sql: impl AsRef<str>) -> &mut Self {
+        let sql = sql.as_ref();
+        debug_assert!(
+            !sql.contains('?'),
+            "`?` is a placeholder for bound parameters, use push_bind instead"
+        );
+        self.sql.push_str(sql);
+        self
+    }
+
+    /// Appends a placeholder and binds the value to it
+    pub fn push_bind(&mut self, value: impl Into<QueryParam>) -> &mut Self {
+        self.sql.push('?');
+        self.params.push(value.into());
+        self
+    }
+
+    pub fn build(self, clickhouse: &clickhouse::Client) -> clickhouse::query::Query {
+        self.params
+            .into_iter()
+            .fold(clickhouse.query(&self.sql), |query, param| {
+                query.bind(param)
+            })
+    }
+}
+
+#[cfg(test)]
+impl QueryBuilder {
+    pub fn sql(&self) -> &str {
+        &self.sql
+    }
+
+    pub fn params(&self) -> &[QueryParam] {
+        &self.params
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_values_are_bound_in_order() {
+        let mut query = QueryBuilder::new("SELECT * FROM scores WHERE name = ");
+        query
+            .push_bind("'; DROP TABLE scores --")
+            .push(" AND value > ")
+            .push_bind(1.5);
+
+        assert_eq!(
+            query.sql,
+            "SELECT * FROM scores WHERE name = ? AND value > ?"
+        );
+        assert_eq!(query.params.len(), 2);
+        assert!(
+            matches!(&query.params[0], QueryParam::String(s) if s == "'; DROP TABLE scores --")
+        );
+        assert!(matches!(query.params[1], QueryParam::Float(v) if v == 1.5));
+    }
+}
@@ -0,0 +1,70 @@
+//! Emulates a TTL per project, because the TTL of a Clickhouse table is the same for all rows
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use clickhouse::Row;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use super::query_builder::QueryBuilder;
+
+/// Tables with project data, and the column with the time of each row
+const PROJECT_TABLES: [(&str, &str); 4] = [
+    ("spans", "start_time"),
+    ("events", "timestamp"),
+    ("evaluation_scores", "timestamp"),
+    ("metrics", "timestamp"),
+];
+
+#[derive(Row, Deserialize)]
+struct MatchingRows {
+    count: u64,
+}
+
+/// Deletes the rows of the project older than `cutoff`. Lightweight deletes hide the rows
+/// right away, and they are removed from disk when the parts are merged.
+///
+/// Every delete is a mutation of the table, so the tables without old rows are skipped.
+pub async fn delete_project_data_before(
+    clickhouse: &clickhouse::Client,
+    project_id: Uuid,
+    cutoff: DateTime<Utc>,
+) -> Result<()> {
+    for (table, time_column) in PROJECT_TABLES {
+        if !has_rows_before(clickhouse, table, time_column, project_id, cutoff).await? {
+            continue;
+        }
+
+        let mut query = QueryBuilder::new(format!("DELETE FROM {table} WHERE project_id = "));
+        query
+            .push_bind(project_id)
+            .push(format!(" AND {time_column} < fromUnixTimestamp("))
+            .push_bind(cutoff.timestamp())
+            .push(")");
+        query.build(clickhouse).execute().await?;
+    }
+
+    Ok(())
+}
+
+async fn has_rows_before(
+    clickhouse: &clickhouse::Client,
+    table: &str,
+    time_column: &str,
+    project_id: Uuid,
+    cutoff: DateTime<Utc>,
+) -> Result<bool> {
+    let mut query = QueryBuilder::new(format!(
+        "SELECT count() AS count FROM (SELECT 1 FROM {table} WHERE project_id = "
+    ));
+    query
+        .push_bind(project_id)
+        .push(format!(" AND {time_column} < fromUnixTimestamp("))
+        .push_bind(cutoff.timestamp())
+        .push(") LIMIT 1)");
+
+    let mut cursor = query.build(clickhouse).fetch::<MatchingRows>()?;
+    let matching_rows = cursor.next().await?;
+
+    Ok(matching_rows.is_some_and(|rows| rows.count > 0))
+}
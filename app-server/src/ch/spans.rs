@@ -5,19 +5,111 @@ use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::{
-    db::spans::{Span, SpanType},
+    db::spans::{Span, SpanStatus, SpanType},
     traces::spans::SpanUsage,
 };
 
 use super::{
-    modifiers::GroupByInterval,
+    modifiers::{GroupBy, GroupByInterval},
+    query_builder::QueryBuilder,
     utils::{
-        chrono_to_nanoseconds, execute_query, group_by_time_absolute_statement,
-        group_by_time_relative_statement,
+        chrono_to_nanoseconds, execute_query, push_absolute_time_condition,
+        push_group_by_columns_and_time_absolute, push_group_by_columns_and_time_relative,
+        push_group_by_time_absolute, push_group_by_time_relative, push_relative_time_condition,
     },
-    Aggregation, MetricTimeValue,
+    Aggregation, GroupedMetricTimeValue, MetricTimeValue,
 };
 
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum TraceMetric {
+    TraceCount,
+    TraceLatencySeconds,
+    /// Share of traces with at least one errored span
+    TraceErrorRate,
+    TotalTokenCount,
+    CostUsd,
+}
+
+impl TraceMetric {
+    /// Expression computing the metric of a single trace from its spans
+    pub fn to_ch_expression(&self) -> &'static str {
+        match self {
+            TraceMetric::TraceCount => "1",
+            TraceMetric::TraceLatencySeconds => {
+                "(toUnixTimestamp64Nano(MAX(end_time)) - toUnixTimestamp64Nano(MIN(start_time))) / 1e9"
+            }
+            TraceMetric::TraceErrorRate => "toFloat64(MAX(has_error))",
+            TraceMetric::TotalTokenCount => "SUM(total_tokens)",
+            TraceMetric::CostUsd => "SUM(total_cost)",
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum SpanMetric {
+    SpanCount,
+    SpanLatencySeconds,
+    InputTokenCount,
+    OutputTokenCount,
+    TotalTokenCount,
+    CostUsd,
+    /// Share of spans with an error status or exception
+    SpanErrorRate,
+}
+
+impl SpanMetric {
+    /// Expression computing the metric of a single span
+    pub fn to_ch_expression(&self) -> &'static str {
+        match self {
+            SpanMetric::SpanCount => "1",
+            SpanMetric::SpanLatencySeconds => {
+                "(toUnixTimestamp64Nano(end_time) - toUnixTimestamp64Nano(start_time)) / 1e9"
+            }
+            SpanMetric::InputTokenCount => "input_tokens",
+            SpanMetric::OutputTokenCount => "output_tokens",
+            SpanMetric::TotalTokenCount => "total_tokens",
+            SpanMetric::CostUsd => "total_cost",
+            SpanMetric::SpanErrorRate => "toFloat64(has_error)",
+        }
+    }
+}
+
+/// Restricts span metrics to the spans matching all of the set fields
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SpanMetricFilters {
+    #[serde(default)]
+    pub span_type: Option<SpanType>,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub provider: Option<String>,
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+impl SpanMetricFilters {
+    fn push_conditions(&self, query: &mut QueryBuilder) {
+        if let Some(span_type) = &self.span_type {
+            let span_type: u8 = span_type.clone().into();
+            query.push(" AND span_type = ").push_bind(span_type);
+        }
+        for (column, value) in [
+            ("model", &self.model),
+            ("provider", &self.provider),
+            ("path", &self.path),
+        ] {
+            if let Some(value) = value {
+                query
+                    .push(format!(" AND {column} = "))
+                    .push_bind(value.as_str());
+            }
+        }
+    }
+}
+
 /// for inserting into clickhouse
 ///
 /// Don't change the order of the fields or their values
@@ -34,6 +126,17 @@ impl Into<u8> for SpanType {
     }
 }
 
+/// Same values as OTel status codes
+impl Into<u8> for SpanStatus {
+    fn into(self) -> u8 {
+        match self {
+            SpanStatus::UNSET => 0,
+            SpanStatus::OK => 1,
+            SpanStatus::ERROR => 2,
+        }
+    }
+}
+
 #[derive(Row, Serialize, Deserialize)]
 pub struct CHSpan {
     #[serde(with = "clickhouse::serde::uuid")]
@@ -60,6 +163,13 @@ pub struct CHSpan {
     pub user_id: String,
     // Default value is <null>  backwards compatibility or if path attribute is not present
     pub path: String,
+    pub status_code: u8,
+    pub status_message: String,
+    pub has_error: bool,
+    /// OTel resource attributes as a JSON object string
+    pub resource_attributes: String,
+    pub scope_name: String,
+    pub scope_version: String,
 }
 
 impl CHSpan {
@@ -90,26 +200,41 @@ impl CHSpan {
             provider: usage.provider_name.unwrap_or(String::from("<null>")),
             user_id: span_attributes.user_id().unwrap_or(String::from("<null>")),
             path: span_attributes.path().unwrap_or(String::from("<null>")),
+            status_code: span.status.clone().into(),
+            status_message: span.status_message.clone().unwrap_or_default(),
+            has_error: span.has_error,
+            resource_attributes: span.resource_attributes.to_string(),
+            scope_name: span.scope_name.clone().unwrap_or_default(),
+            scope_version: span.scope_version.clone().unwrap_or_default(),
         }
     }
 }
 
-pub async fn insert_span(clickhouse: clickhouse::Client, span: &CHSpan) -> Result<()> {
+pub async fn insert_spans(clickhouse: clickhouse::Client, spans: &[CHSpan]) -> Result<()> {
+    if spans.is_empty() {
+        return Ok(());
+    }
+
     let ch_insert = clickhouse.insert("spans");
     match ch_insert {
         Ok(mut ch_insert) => {
-            ch_insert.write(span).await?;
+            for span in spans {
+                ch_insert.write(span).await?;
+            }
             let ch_insert_end_res = ch_insert.end().await;
             match ch_insert_end_res {
                 Ok(_) => Ok(()),
                 Err(e) => {
-                    return Err(anyhow::anyhow!("Clickhouse span insertion failed: {:?}", e));
+                    return Err(anyhow::anyhow!(
+                        "Clickhouse spans insertion failed: {:?}",
+                        e
+                    ));
                 }
             }
         }
         Err(e) => {
             return Err(anyhow::anyhow!(
-                "Failed to insert span into Clickhouse: {:?}",
+                "Failed to insert spans into Clickhouse: {:?}",
                 e
             ));
         }
@@ -122,30 +247,16 @@ pub async fn get_total_trace_count_metrics_relative(
     project_id: Uuid,
     past_hours: i64,
 ) -> Result<Vec<MetricTimeValue<i64>>> {
-    let ch_round_time = group_by_interval.to_ch_truncate_time();
-
-    let query_string = format!(
-        "
-    WITH traces AS (
-        SELECT
-            trace_id,
-            project_id,
-            {ch_round_time}(MIN(start_time)) as time
-        FROM spans
-        GROUP BY project_id, trace_id
-    )
-    SELECT
-        time,
-        COUNT(DISTINCT(trace_id)) as value
-    FROM traces
-    WHERE
-        project_id = '{project_id}'
-        AND time >= now() - INTERVAL {past_hours} HOUR
-    {}",
-        group_by_time_relative_statement(past_hours, group_by_interval)
+    let mut query = trace_metric_query(
+        project_id,
+        group_by_interval,
+        "1",
+        "COUNT(DISTINCT(trace_id))",
     );
+    push_relative_time_condition(&mut query, "time", past_hours);
+    push_group_by_time_relative(&mut query, past_hours, group_by_interval);
 
-    execute_query(&clickhouse, &query_string).await
+    execute_query(&clickhouse, query).await
 }
 
 pub async fn get_total_trace_count_metrics_absolute(
@@ -155,72 +266,59 @@ pub async fn get_total_trace_count_metrics_absolute(
     start_time: DateTime<Utc>,
     end_time: DateTime<Utc>,
 ) -> Result<Vec<MetricTimeValue<i64>>> {
-    let ch_round_time = group_by_interval.to_ch_truncate_time();
-    let ch_start_time = start_time.timestamp();
-    let ch_end_time = end_time.timestamp();
-
-    let query_string = format!(
-        "
-    WITH traces AS (
-    SELECT
-        trace_id,
+    let mut query = trace_metric_query(
         project_id,
-        {ch_round_time}(MIN(start_time)) as time,
-        SUM(total_tokens) as value
-    FROM spans
-    GROUP BY project_id, trace_id
-    )
-    SELECT
-        time,
-        COUNT(DISTINCT(trace_id)) as value
-    FROM traces
-    WHERE
-        project_id = '{project_id}'
-        AND time >= fromUnixTimestamp({ch_start_time})
-        AND time <= fromUnixTimestamp({ch_end_time})
-    {}",
-        group_by_time_absolute_statement(start_time, end_time, group_by_interval)
+        group_by_interval,
+        "1",
+        "COUNT(DISTINCT(trace_id))",
     );
+    push_absolute_time_condition(&mut query, "time", start_time, end_time);
+    push_group_by_time_absolute(&mut query, start_time, end_time, group_by_interval);
 
-    execute_query(&clickhouse, &query_string).await
+    execute_query(&clickhouse, query).await
 }
 
-pub async fn get_trace_latency_seconds_metrics_relative(
+/// Any trace metric with any aggregation, as floats
+pub async fn get_trace_metric_relative(
     clickhouse: clickhouse::Client,
     group_by_interval: GroupByInterval,
     project_id: Uuid,
     past_hours: i64,
     aggregation: Aggregation,
+    metric: TraceMetric,
 ) -> Result<Vec<MetricTimeValue<f64>>> {
-    let query_string = span_metric_query_relative(
+    let mut query = trace_metric_query(
         project_id,
         group_by_interval,
-        past_hours,
-        aggregation,
-        "(toUnixTimestamp64Nano(MAX(end_time)) - toUnixTimestamp64Nano(MIN(start_time))) / 1e9",
+        &format!("toFloat64({})", metric.to_ch_expression()),
+        &format!("{}(value)", aggregation.to_ch_agg_function()),
     );
+    push_relative_time_condition(&mut query, "time", past_hours);
+    push_group_by_time_relative(&mut query, past_hours, group_by_interval);
 
-    execute_query(&clickhouse, &query_string).await
+    execute_query(&clickhouse, query).await
 }
 
-pub async fn get_trace_latency_seconds_metrics_absolute(
+/// Any trace metric with any aggregation, as floats
+pub async fn get_trace_metric_absolute(
     clickhouse: clickhouse::Client,
     group_by_interval: GroupByInterval,
     project_id: Uuid,
     start_time: DateTime<Utc>,
     end_time: DateTime<Utc>,
     aggregation: Aggregation,
+    metric: TraceMetric,
 ) -> Result<Vec<MetricTimeValue<f64>>> {
-    let query_string = span_metric_query_absolute(
+    let mut query = trace_metric_query(
         project_id,
         group_by_interval,
-        start_time,
-        end_time,
-        aggregation,
-        "(toUnixTimestamp64Nano(MAX(end_time)) - toUnixTimestamp64Nano(MIN(start_time))) / 1e9",
+        &format!("toFloat64({})", metric.to_ch_expression()),
+        &format!("{}(value)", aggregation.to_ch_agg_function()),
     );
+    push_absolute_time_condition(&mut query, "time", start_time, end_time);
+    push_group_by_time_absolute(&mut query, start_time, end_time, group_by_interval);
 
-    execute_query(&clickhouse, &query_string).await
+    execute_query(&clickhouse, query).await
 }
 
 pub async fn get_total_token_count_metrics_relative(
@@ -230,15 +328,16 @@ pub async fn get_total_token_count_metrics_relative(
     past_hours: i64,
     aggregation: Aggregation,
 ) -> Result<Vec<MetricTimeValue<i64>>> {
-    let query_string = span_metric_query_relative(
+    let mut query = trace_metric_query(
         project_id,
         group_by_interval,
-        past_hours,
-        aggregation,
-        "SUM(total_tokens)",
+        TraceMetric::TotalTokenCount.to_ch_expression(),
+        &format!("{}(value)", aggregation.to_ch_agg_function()),
     );
+    push_relative_time_condition(&mut query, "time", past_hours);
+    push_group_by_time_relative(&mut query, past_hours, group_by_interval);
 
-    execute_query(&clickhouse, &query_string).await
+    execute_query(&clickhouse, query).await
 }
 
 pub async fn get_total_token_count_metrics_absolute(
@@ -249,122 +348,276 @@ pub async fn get_total_token_count_metrics_absolute(
     end_time: DateTime<Utc>,
     aggregation: Aggregation,
 ) -> Result<Vec<MetricTimeValue<i64>>> {
-    let query_string = span_metric_query_absolute(
+    let mut query = trace_metric_query(
         project_id,
         group_by_interval,
-        start_time,
-        end_time,
-        aggregation,
-        "SUM(total_tokens)",
+        TraceMetric::TotalTokenCount.to_ch_expression(),
+        &format!("{}(value)", aggregation.to_ch_agg_function()),
     );
+    push_absolute_time_condition(&mut query, "time", start_time, end_time);
+    push_group_by_time_absolute(&mut query, start_time, end_time, group_by_interval);
 
-    execute_query(&clickhouse, &query_string).await
+    execute_query(&clickhouse, query).await
 }
 
-pub async fn get_cost_usd_metrics_relative(
+/// Query over traces up to the time condition. `metric` computes the value of each trace
+/// from its spans, and `value` aggregates the values of traces starting in the same interval.
+fn trace_metric_query(
+    project_id: Uuid,
+    group_by_interval: GroupByInterval,
+    metric: &str,
+    value: &str,
+) -> QueryBuilder {
+    let ch_round_time = group_by_interval.to_ch_truncate_time();
+
+    let mut query = QueryBuilder::new(format!(
+        "
+    WITH traces AS (
+    SELECT
+        trace_id,
+        project_id,
+        {ch_round_time}(MIN(start_time)) as time,
+        {metric} as value
+    FROM spans
+    GROUP BY project_id, trace_id
+    )
+    SELECT
+        time,
+        {value} as value
+    FROM traces
+    WHERE
+        project_id = "
+    ));
+    query.push_bind(project_id);
+    query
+}
+
+/// Trace metric split into one series per value of `group_by`, for the groups with the highest
+/// aggregated metric over the whole range.
+///
+/// Spans of a trace are split by their value, and the metric of each group is computed over its
+/// spans only, rather than over the whole trace. E.g. the cost of a trace which calls several
+/// models is split between the models, and its latency per model is the time from the first to
+/// the last span which called the model. Spans without a value, such as the spans which are not
+/// LLM calls when grouping by model, are left out unless `group_by.include_missing` is set.
+pub async fn get_trace_metric_series_relative(
     clickhouse: clickhouse::Client,
     group_by_interval: GroupByInterval,
     project_id: Uuid,
     past_hours: i64,
     aggregation: Aggregation,
-) -> Result<Vec<MetricTimeValue<f64>>> {
-    let query_string = span_metric_query_relative(
+    metric: TraceMetric,
+    group_by: GroupBy,
+) -> Result<Vec<GroupedMetricTimeValue<f64>>> {
+    let query = trace_metric_series_query(
         project_id,
         group_by_interval,
-        past_hours,
-        aggregation,
-        "SUM(total_cost)",
+        &aggregation,
+        metric,
+        group_by,
+        |query, column| push_relative_time_condition(query, column, past_hours),
+        |query| {
+            push_group_by_columns_and_time_relative(
+                query,
+                &["group_value"],
+                past_hours,
+                group_by_interval,
+            )
+        },
     );
 
-    execute_query(&clickhouse, &query_string).await
+    execute_query(&clickhouse, query).await
 }
 
-pub async fn get_cost_usd_metrics_absolute(
+/// Same as `get_trace_metric_series_relative`, for an absolute time range
+pub async fn get_trace_metric_series_absolute(
     clickhouse: clickhouse::Client,
     group_by_interval: GroupByInterval,
     project_id: Uuid,
     start_time: DateTime<Utc>,
     end_time: DateTime<Utc>,
     aggregation: Aggregation,
-) -> Result<Vec<MetricTimeValue<f64>>> {
-    let query_string = span_metric_query_absolute(
+    metric: TraceMetric,
+    group_by: GroupBy,
+) -> Result<Vec<GroupedMetricTimeValue<f64>>> {
+    let query = trace_metric_series_query(
         project_id,
         group_by_interval,
-        start_time,
-        end_time,
-        aggregation,
-        "SUM(total_cost)",
+        &aggregation,
+        metric,
+        group_by,
+        |query, column| push_absolute_time_condition(query, column, start_time, end_time),
+        |query| {
+            push_group_by_columns_and_time_absolute(
+                query,
+                &["group_value"],
+                start_time,
+                end_time,
+                group_by_interval,
+            )
+        },
     );
 
-    execute_query(&clickhouse, &query_string).await
+    execute_query(&clickhouse, query).await
 }
 
-fn span_metric_query_relative(
+/// `push_time_condition` pushes the condition of the range on the given column. It is applied
+/// to the spans, and then again to the start times of the groups of their traces.
+fn trace_metric_series_query(
     project_id: Uuid,
     group_by_interval: GroupByInterval,
-    past_hours: i64,
-    aggregation: Aggregation,
-    metric: &str,
-) -> String {
+    aggregation: &Aggregation,
+    metric: TraceMetric,
+    group_by: GroupBy,
+    push_time_condition: impl Fn(&mut QueryBuilder, &str),
+    push_group_by: impl FnOnce(&mut QueryBuilder),
+) -> QueryBuilder {
     let ch_round_time = group_by_interval.to_ch_truncate_time();
     let ch_aggregation = aggregation.to_ch_agg_function();
+    let ch_metric = metric.to_ch_expression();
+    let ch_group_by = group_by.dimension.to_ch_expression();
 
-    format!(
+    let mut query = QueryBuilder::new(format!(
         "
     WITH traces AS (
     SELECT
         trace_id,
         project_id,
+        {ch_group_by} as group_value,
         {ch_round_time}(MIN(start_time)) as time,
-        {metric} as value
+        {ch_metric} as value
     FROM spans
-    GROUP BY project_id, trace_id
+    WHERE project_id = "
+    ));
+    query.push_bind(project_id);
+    push_time_condition(&mut query, "start_time");
+    // Missing string columns are stored as '<null>', and missing resource attributes are empty
+    if !group_by.include_missing {
+        query.push(format!(" AND {ch_group_by} NOT IN ('<null>', '')"));
+    }
+    query.push(
+        "
+    GROUP BY project_id, trace_id, group_value
+    ),
+    top_groups AS (
+    SELECT group_value
+    FROM traces
+    WHERE 1 = 1",
+    );
+    push_time_condition(&mut query, "time");
+    query
+        .push(format!(
+            "
+    GROUP BY group_value
+    ORDER BY {ch_aggregation}(value) DESC
+    LIMIT "
+        ))
+        .push_bind(group_by.limit as i64)
+        .push(format!(
+            "
     )
     SELECT
+        group_value,
         time,
-        {ch_aggregation}(value) as value
+        toFloat64({ch_aggregation}(value)) as value
     FROM traces
     WHERE
-        project_id = '{project_id}'
-        AND time >= now() - INTERVAL {past_hours} HOUR
-    {}",
-        group_by_time_relative_statement(past_hours, group_by_interval)
-    )
+        group_value IN (SELECT group_value FROM top_groups)"
+        ));
+    push_time_condition(&mut query, "time");
+    push_group_by(&mut query);
+    query
 }
 
-fn span_metric_query_absolute(
+pub async fn get_span_metric_relative(
+    clickhouse: clickhouse::Client,
+    group_by_interval: GroupByInterval,
     project_id: Uuid,
+    past_hours: i64,
+    aggregation: Aggregation,
+    metric: SpanMetric,
+    filters: &SpanMetricFilters,
+) -> Result<Vec<MetricTimeValue<f64>>> {
+    let mut query = span_metric_query(project_id, group_by_interval, &aggregation, metric, filters);
+    push_relative_time_condition(&mut query, "start_time", past_hours);
+    push_group_by_time_relative(&mut query, past_hours, group_by_interval);
+
+    execute_query(&clickhouse, query).await
+}
+
+pub async fn get_span_metric_absolute(
+    clickhouse: clickhouse::Client,
     group_by_interval: GroupByInterval,
+    project_id: Uuid,
     start_time: DateTime<Utc>,
     end_time: DateTime<Utc>,
     aggregation: Aggregation,
-    metric: &str,
-) -> String {
+    metric: SpanMetric,
+    filters: &SpanMetricFilters,
+) -> Result<Vec<MetricTimeValue<f64>>> {
+    let mut query = span_metric_query(project_id, group_by_interval, &aggregation, metric, filters);
+    push_absolute_time_condition(&mut query, "start_time", start_time, end_time);
+    push_group_by_time_absolute(&mut query, start_time, end_time, group_by_interval);
+
+    execute_query(&clickhouse, query).await
+}
+
+/// Query over individual spans matching the filters, up to the time condition
+fn span_metric_query(
+    project_id: Uuid,
+    group_by_interval: GroupByInterval,
+    aggregation: &Aggregation,
+    metric: SpanMetric,
+    filters: &SpanMetricFilters,
+) -> QueryBuilder {
     let ch_round_time = group_by_interval.to_ch_truncate_time();
-    let ch_start_time = start_time.timestamp();
-    let ch_end_time = end_time.timestamp();
     let ch_aggregation = aggregation.to_ch_agg_function();
+    let ch_metric = metric.to_ch_expression();
 
-    format!(
+    let mut query = QueryBuilder::new(format!(
         "
-    WITH traces AS (
     SELECT
-        trace_id,
-        project_id,
-        {ch_round_time}(MIN(start_time)) as time,
-        {metric} as value
+        {ch_round_time}(start_time) as time,
+        toFloat64({ch_aggregation}({ch_metric})) as value
     FROM spans
-    GROUP BY project_id, trace_id
-    )
-    SELECT
-        time,
-        {ch_aggregation}(value) as value
-    FROM traces
     WHERE
-        project_id = '{project_id}'
-        AND time >= fromUnixTimestamp({ch_start_time})
-        AND time <= fromUnixTimestamp({ch_end_time})
-    {}",
-        group_by_time_absolute_statement(start_time, end_time, group_by_interval)
-    )
+        project_id = "
+    ));
+    query.push_bind(project_id);
+    filters.push_conditions(&mut query);
+    query
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ch::query_builder::QueryParam;
+
+    use super::*;
+
+    #[test]
+    fn test_span_metric_filters_are_bound() {
+        let filters = SpanMetricFilters {
+            span_type: Some(SpanType::LLM),
+            model: Some("gpt-4o' OR 1 = 1 --".to_string()),
+            provider: None,
+            path: Some("agent.step".to_string()),
+        };
+        let query = span_metric_query(
+            Uuid::nil(),
+            GroupByInterval::Hour,
+            &Aggregation::Total,
+            SpanMetric::SpanCount,
+            &filters,
+        );
+
+        assert!(query
+            .sql()
+            .ends_with("project_id = ? AND span_type = ? AND model = ? AND path = ?"));
+        assert!(!query.sql().contains("gpt-4o"));
+        let params = query.params();
+        assert_eq!(params.len(), 4);
+        assert!(matches!(params[1], QueryParam::Int(1)));
+        assert!(matches!(&params[2], QueryParam::String(s) if s == "gpt-4o' OR 1 = 1 --"));
+        assert!(matches!(&params[3], QueryParam::String(s) if s == "agent.step"));
+    }
 }
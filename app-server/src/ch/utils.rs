@@ -9,7 +9,7 @@ use crate::{
     features::{is_feature_enabled, Feature},
 };
 
-use super::modifiers::GroupByInterval;
+use super::{modifiers::GroupByInterval, query_builder::QueryBuilder};
 
 #[derive(Deserialize, Row)]
 pub struct TimeBounds {
@@ -42,47 +42,115 @@ pub fn nanoseconds_to_chrono(timestamp_nanos: i64) -> DateTime<Utc> {
     })
 }
 
-pub fn group_by_time_absolute_statement(
+pub fn push_group_by_time_absolute(
+    query: &mut QueryBuilder,
     start_time: DateTime<Utc>,
     end_time: DateTime<Utc>,
     group_by_interval: GroupByInterval,
-) -> String {
+) {
+    push_group_by_columns_and_time_absolute(query, &[], start_time, end_time, group_by_interval)
+}
+
+pub fn push_group_by_time_relative(
+    query: &mut QueryBuilder,
+    past_hours: i64,
+    group_by_interval: GroupByInterval,
+) {
+    push_group_by_columns_and_time_relative(query, &[], past_hours, group_by_interval)
+}
+
+/// Like `push_group_by_time_absolute`, but groups by `columns` first.
+/// The gaps in time are filled separately for each group.
+pub fn push_group_by_columns_and_time_absolute(
+    query: &mut QueryBuilder,
+    columns: &[&str],
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+    group_by_interval: GroupByInterval,
+) {
     let ch_round_time = group_by_interval.to_ch_truncate_time();
     let ch_interval = group_by_interval.to_interval();
     let ch_step = group_by_interval.to_ch_step();
-    let ch_start_time = start_time.timestamp();
-    let ch_end_time = end_time.timestamp();
+    let ch_columns = columns_prefix(columns);
 
-    format!(
-        "GROUP BY
-            time
+    query
+        .push(format!(
+            "
+        GROUP BY
+            {ch_columns}time
         ORDER BY
-            time
+            {ch_columns}time
         WITH FILL
-        FROM {ch_round_time}(fromUnixTimestamp({ch_start_time}))
-        TO {ch_round_time}(fromUnixTimestamp({ch_end_time}) + INTERVAL {ch_interval})
+        FROM {ch_round_time}(fromUnixTimestamp("
+        ))
+        .push_bind(start_time.timestamp())
+        .push(format!(
+            "))
+        TO {ch_round_time}(fromUnixTimestamp("
+        ))
+        .push_bind(end_time.timestamp())
+        .push(format!(
+            ") + INTERVAL {ch_interval})
         STEP {ch_step}"
-    )
+        ));
 }
 
-pub fn group_by_time_relative_statement(
+/// Like `push_group_by_time_relative`, but groups by `columns` first.
+/// The gaps in time are filled separately for each group.
+pub fn push_group_by_columns_and_time_relative(
+    query: &mut QueryBuilder,
+    columns: &[&str],
     past_hours: i64,
     group_by_interval: GroupByInterval,
-) -> String {
+) {
     let ch_round_time = group_by_interval.to_ch_truncate_time();
     let ch_interval = group_by_interval.to_interval();
     let ch_step = group_by_interval.to_ch_step();
+    let ch_columns = columns_prefix(columns);
 
-    format!(
-        "GROUP BY
-            time
+    query
+        .push(format!(
+            "
+        GROUP BY
+            {ch_columns}time
         ORDER BY
-            time
+            {ch_columns}time
         WITH FILL
-        FROM {ch_round_time}(NOW() - INTERVAL {past_hours} HOUR + INTERVAL {ch_interval})
+        FROM {ch_round_time}(NOW() - INTERVAL "
+        ))
+        .push_bind(past_hours)
+        .push(format!(
+            " HOUR + INTERVAL {ch_interval})
         TO {ch_round_time}(NOW() + INTERVAL {ch_interval})
         STEP {ch_step}"
-    )
+        ));
+}
+
+/// Appends the condition that `column` is within the last `past_hours`
+pub fn push_relative_time_condition(query: &mut QueryBuilder, column: &str, past_hours: i64) {
+    query
+        .push(format!(" AND {column} >= now() - INTERVAL "))
+        .push_bind(past_hours)
+        .push(" HOUR");
+}
+
+/// Appends the condition that `column` is between `start_time` and `end_time`, inclusive
+pub fn push_absolute_time_condition(
+    query: &mut QueryBuilder,
+    column: &str,
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+) {
+    query
+        .push(format!(" AND {column} >= fromUnixTimestamp("))
+        .push_bind(start_time.timestamp())
+        .push(format!(") AND {column} <= fromUnixTimestamp("))
+        .push_bind(end_time.timestamp())
+        .push(")");
+}
+
+fn columns_prefix(columns: &[&str]) -> String {
+    columns.iter().map(|column| format!("{column}, ")).collect()
 }
 
 // Template ID is not included here for events, so that all graphs in the event dashboard
@@ -108,16 +176,17 @@ async fn get_time_bounds(
         });
     }
 
-    let query_string = format!(
+    let mut query = QueryBuilder::new(format!(
         "SELECT
             MIN({column_name}) AS min_time,
             MAX({column_name}) AS max_time
         FROM
             {table_name}
-        WHERE project_id = '{project_id}'",
-    );
+        WHERE project_id = "
+    ));
+    query.push_bind(*project_id);
 
-    let mut cursor = clickhouse.query(&query_string).fetch::<TimeBounds>()?;
+    let mut cursor = query.build(clickhouse).fetch::<TimeBounds>()?;
 
     let time_bounds = cursor.next().await?.unwrap();
     Ok(time_bounds)
@@ -138,7 +207,7 @@ pub async fn get_bounds(
 
 pub async fn execute_query<'de, T>(
     clickhouse: &clickhouse::Client,
-    query_string: &str,
+    query: QueryBuilder,
 ) -> Result<Vec<T>>
 where
     T: Row + Deserialize<'de>,
@@ -147,7 +216,7 @@ where
         return Ok(Vec::new());
     }
 
-    let mut cursor = clickhouse.query(query_string).fetch::<T>()?;
+    let mut cursor = query.build(clickhouse).fetch::<T>()?;
 
     let mut res = Vec::new();
     while let Some(row) = cursor.next().await? {
@@ -156,15 +225,3 @@ where
 
     Ok(res)
 }
-
-/// Trivial SQL injection protection
-pub fn validate_string_against_injection(s: &str) -> Result<()> {
-    let invalid_chars = ["'", "\"", "\\", ";", "*", "/", "--"];
-    if invalid_chars.iter().any(|&c| s.contains(c))
-        || s.to_lowercase().contains("union")
-        || s.to_lowercase().contains("select")
-    {
-        return Err(anyhow::anyhow!("Invalid characters or SQL keywords"));
-    }
-    return Ok(());
-}
@@ -0,0 +1,115 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::{FromRow, PgExecutor, PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+#[derive(FromRow)]
+pub struct DeadLetter {
+    pub id: Uuid,
+    pub dead_lettered_at: DateTime<Utc>,
+    pub retry_count: i64,
+    pub error: Option<String>,
+    pub payload: String,
+}
+
+/// Position in the list of dead letters, which are ordered from the latest
+pub struct DeadLetterCursor {
+    pub dead_lettered_at: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+/// Does nothing if the dead letter is stored already, e.g. if it was redelivered
+pub async fn insert_dead_letter(pool: &PgPool, dead_letter: &DeadLetter) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO dead_lettered_spans (id, dead_lettered_at, retry_count, error, payload)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (id) DO NOTHING",
+    )
+    .bind(dead_letter.id)
+    .bind(dead_letter.dead_lettered_at)
+    .bind(dead_letter.retry_count)
+    .bind(&dead_letter.error)
+    .bind(&dead_letter.payload)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Returns dead letters older than the cursor, latest first
+pub async fn get_dead_letters(
+    pool: &PgPool,
+    limit: i64,
+    cursor: Option<&DeadLetterCursor>,
+) -> Result<Vec<DeadLetter>> {
+    let mut query = QueryBuilder::<Postgres>::new(
+        "SELECT id, dead_lettered_at, retry_count, error, payload FROM dead_lettered_spans",
+    );
+    if let Some(cursor) = cursor {
+        query
+            .push(" WHERE (dead_lettered_at, id) < (")
+            .push_bind(cursor.dead_lettered_at)
+            .push(", ")
+            .push_bind(cursor.id)
+            .push(")");
+    }
+    query
+        .push(" ORDER BY dead_lettered_at DESC, id DESC LIMIT ")
+        .push_bind(limit);
+
+    let dead_letters = query.build_query_as::<DeadLetter>().fetch_all(pool).await?;
+
+    Ok(dead_letters)
+}
+
+pub async fn get_dead_letter(pool: &PgPool, id: &Uuid) -> Result<Option<DeadLetter>> {
+    let dead_letter = sqlx::query_as::<_, DeadLetter>(
+        "SELECT id, dead_lettered_at, retry_count, error, payload
+        FROM dead_lettered_spans
+        WHERE id = $1",
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(dead_letter)
+}
+
+/// Locks the oldest dead letters, optionally only the ones with the given ids, until the
+/// transaction ends. Rows locked by a concurrent replay are skipped.
+pub async fn lock_dead_letters<'e>(
+    executor: impl PgExecutor<'e>,
+    ids: Option<&[Uuid]>,
+    dead_lettered_before: DateTime<Utc>,
+    limit: i64,
+) -> Result<Vec<DeadLetter>> {
+    let mut query = QueryBuilder::<Postgres>::new(
+        "SELECT id, dead_lettered_at, retry_count, error, payload
+        FROM dead_lettered_spans
+        WHERE dead_lettered_at <= ",
+    );
+    query.push_bind(dead_lettered_before);
+    if let Some(ids) = ids {
+        query.push(" AND id = ANY(").push_bind(ids).push(")");
+    }
+    query
+        .push(" ORDER BY dead_lettered_at, id LIMIT ")
+        .push_bind(limit)
+        .push(" FOR UPDATE SKIP LOCKED");
+
+    let dead_letters = query
+        .build_query_as::<DeadLetter>()
+        .fetch_all(executor)
+        .await?;
+
+    Ok(dead_letters)
+}
+
+pub async fn delete_dead_letters<'e>(executor: impl PgExecutor<'e>, ids: &[Uuid]) -> Result<()> {
+    sqlx::query("DELETE FROM dead_lettered_spans WHERE id = ANY($1)")
+        .bind(ids)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
@@ -204,6 +204,22 @@ pub async fn delete_evaluation(pool: &PgPool, evaluation_id: &Uuid) -> Result<()
     Ok(())
 }
 
+/// Deletes the evaluations of the project created before `cutoff`, along with their results
+pub async fn delete_evaluations_before(
+    pool: &PgPool,
+    project_id: &Uuid,
+    cutoff: DateTime<Utc>,
+) -> Result<u64> {
+    let deleted = sqlx::query("DELETE FROM evaluations WHERE project_id = $1 AND created_at < $2")
+        .bind(project_id)
+        .bind(cutoff)
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+    Ok(deleted)
+}
+
 pub async fn get_evaluation_datapoint(
     pool: &PgPool,
     evaluation_result_id: Uuid,
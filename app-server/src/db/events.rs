@@ -15,7 +15,7 @@ use crate::{
 
 use super::{
     event_templates::EventType,
-    modifiers::{DateRange, Filter},
+    modifiers::{DateRange, Filter, FilterExpression, FilterValueType},
     utils::{add_date_range_to_query, convert_any_value_to_json_value},
     DB,
 };
@@ -150,6 +150,7 @@ pub async fn create_events_by_template_name(
 
 pub async fn get_events_for_span(
     pool: &PgPool,
+    project_id: &Uuid,
     span_id: Uuid,
 ) -> Result<Vec<EventWithTemplateName>> {
     let events = sqlx::query_as::<_, EventWithTemplateName>(
@@ -167,21 +168,42 @@ pub async fn get_events_for_span(
             e.inputs
         FROM events e
         JOIN event_templates ON e.template_id = event_templates.id
-        WHERE span_id = $1
+        WHERE span_id = $1 AND event_templates.project_id = $2
         ORDER BY e.timestamp ASC",
     )
     .bind(span_id)
+    .bind(project_id)
     .fetch_all(pool)
     .await?;
 
     Ok(events)
 }
 
+/// Events have no project, so they are matched by the project of their template
+pub async fn delete_events_before(
+    pool: &PgPool,
+    project_id: &Uuid,
+    cutoff: DateTime<Utc>,
+) -> Result<u64> {
+    let deleted = sqlx::query(
+        "DELETE FROM events
+        WHERE timestamp < $2
+        AND template_id IN (SELECT id FROM event_templates WHERE project_id = $1)",
+    )
+    .bind(project_id)
+    .bind(cutoff)
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    Ok(deleted)
+}
+
 pub async fn get_events_by_template_id(
     pool: &PgPool,
     template_id: &Uuid,
     date_range: &Option<DateRange>,
-    filters: &Option<Vec<Filter>>,
+    filters: &Option<Vec<FilterExpression>>,
     offset: usize,
     limit: usize,
 ) -> Result<Vec<EventWithTemplateName>> {
@@ -231,7 +253,7 @@ pub async fn count_events_by_template_id(
     pool: &PgPool,
     template_id: &Uuid,
     date_range: &Option<DateRange>,
-    filters: &Option<Vec<Filter>>,
+    filters: &Option<Vec<FilterExpression>>,
 ) -> Result<i64> {
     let mut query = QueryBuilder::<Postgres>::new(
         "SELECT COUNT(*) count
@@ -308,46 +330,62 @@ pub async fn get_events_for_session(
     Ok(events)
 }
 
-fn add_filters_to_events_query(query: &mut QueryBuilder<Postgres>, filters: &Option<Vec<Filter>>) {
+fn add_filters_to_events_query(
+    query: &mut QueryBuilder<Postgres>,
+    filters: &Option<Vec<FilterExpression>>,
+) {
     if let Some(filters) = filters {
         filters.iter().for_each(|filter| {
-            let filter_value = match &filter.filter_value {
-                Value::String(v) => match serde_json::from_str(v) {
-                    Ok(v) => v,
-                    Err(_) => v.clone().into(),
-                },
-                v => v.clone(),
-            };
-            let filter_value_str = match &filter.filter_value {
+            query.push(" AND ");
+            filter.push_condition(query, &push_event_filter);
+        });
+    }
+}
+
+/// Event ids shown in the UI are shortened to their last two groups
+fn pad_short_uuid(value: String) -> String {
+    if Regex::new(r"^[\da-fA-F]{4}-[\da-fA-F]{12}$")
+        .unwrap()
+        .is_match(&value)
+    {
+        format!("00000000-0000-0000-{}", value)
+    } else {
+        value
+    }
+}
+
+/// Pads the short ids in filters by `id` and `span_id`, so that they can be compared as UUIDs
+fn normalize_event_filter(filter: &Filter) -> (Filter, FilterValueType) {
+    match filter.filter_column.as_str() {
+        "id" | "span_id" => {
+            let value = match &filter.filter_value {
                 Value::String(s) => s.clone(),
                 v => v.to_string(),
             };
-            if !filter.validate_column() {
-                log::warn!("Invalid column name: {}", filter.filter_column);
-                return;
-            }
-            query.push(" AND e.");
-            query.push(&filter.filter_column);
-
-            query.push(filter.filter_operator.to_sql_operator());
-            if ["id", "span_id"]
-                .iter()
-                .any(|col| col == &filter.filter_column.as_str())
-            {
-                let padded_uuid = if Regex::new(r"^[\da-fA-F]{4}-[\da-fA-F]{12}$")
-                    .unwrap()
-                    .is_match(&filter_value_str)
-                {
-                    format!("00000000-0000-0000-{}", filter_value_str)
-                } else {
-                    filter_value_str
-                };
-                query.push_bind(Uuid::parse_str(&padded_uuid).unwrap_or_default());
-            } else if &filter.filter_column == "value" {
-                query.push_bind(filter_value);
-            } else {
-                query.push_bind(filter_value_str);
-            }
-        });
+            let filter = Filter {
+                filter_value: Value::String(pad_short_uuid(value)),
+                ..filter.clone()
+            };
+            (filter, FilterValueType::Uuid)
+        }
+        "value" => (filter.clone(), FilterValueType::Json),
+        _ => (filter.clone(), FilterValueType::Text),
     }
 }
+
+pub fn validate_event_filter(filter: &Filter) -> Result<()> {
+    let (filter, value_type) = normalize_event_filter(filter);
+    filter.validate_value(value_type)
+}
+
+fn push_event_filter(query: &mut QueryBuilder<Postgres>, filter: &Filter) {
+    let column = format!("e.{}", filter.filter_column);
+    let (filter, value_type) = normalize_event_filter(filter);
+    filter.push_condition(
+        query,
+        |query| {
+            query.push(&column);
+        },
+        value_type,
+    );
+}
@@ -0,0 +1,165 @@
+use anyhow::Result;
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::opentelemetry::opentelemetry_proto_logs_v1::LogRecord as OtelLogRecord;
+
+use super::utils::{convert_any_value_to_json_value, span_id_to_uuid};
+
+#[derive(sqlx::FromRow, Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LogRecord {
+    pub id: Uuid,
+    pub trace_id: Option<Uuid>,
+    pub span_id: Option<Uuid>,
+    pub timestamp: DateTime<Utc>,
+    pub severity_number: i32,
+    pub severity_text: Option<String>,
+    pub body: Option<Value>,
+    pub attributes: Value,
+}
+
+impl LogRecord {
+    pub fn from_otel(record: OtelLogRecord) -> Self {
+        // Per OTel spec, trace and span ids of the wrong length or all zeroes are invalid
+        let trace_id = (record.trace_id.len() == 16 && record.trace_id.iter().any(|b| *b != 0))
+            .then(|| Uuid::from_slice(&record.trace_id).unwrap());
+        let span_id = (record.span_id.len() == 8 && record.span_id.iter().any(|b| *b != 0))
+            .then(|| span_id_to_uuid(&record.span_id));
+
+        // Observed time is set by the collector, so it's used only when the event time is missing
+        let time_unix_nano = if record.time_unix_nano != 0 {
+            record.time_unix_nano
+        } else {
+            record.observed_time_unix_nano
+        };
+
+        let attributes = record
+            .attributes
+            .into_iter()
+            .filter(|kv| kv.value.as_ref().is_some_and(|v| v.value.is_some()))
+            .map(|kv| (kv.key, convert_any_value_to_json_value(kv.value)))
+            .collect::<serde_json::Map<String, Value>>();
+
+        Self {
+            id: Uuid::new_v4(),
+            trace_id,
+            span_id,
+            timestamp: Utc.timestamp_nanos(time_unix_nano as i64),
+            severity_number: record.severity_number,
+            severity_text: (!record.severity_text.is_empty()).then_some(record.severity_text),
+            body: record
+                .body
+                .filter(|body| body.value.is_some())
+                .map(|body| convert_any_value_to_json_value(Some(body))),
+            attributes: Value::Object(attributes),
+        }
+    }
+}
+
+pub async fn record_logs(pool: &PgPool, project_id: Uuid, logs: &[LogRecord]) -> Result<()> {
+    if logs.is_empty() {
+        return Ok(());
+    }
+
+    let ids = logs.iter().map(|log| log.id).collect::<Vec<_>>();
+    let trace_ids = logs.iter().map(|log| log.trace_id).collect::<Vec<_>>();
+    let span_ids = logs.iter().map(|log| log.span_id).collect::<Vec<_>>();
+    let timestamps = logs.iter().map(|log| log.timestamp).collect::<Vec<_>>();
+    let severity_numbers = logs
+        .iter()
+        .map(|log| log.severity_number)
+        .collect::<Vec<_>>();
+    let severity_texts = logs
+        .iter()
+        .map(|log| log.severity_text.clone())
+        .collect::<Vec<_>>();
+    let bodies = logs.iter().map(|log| log.body.clone()).collect::<Vec<_>>();
+    let attributes = logs
+        .iter()
+        .map(|log| log.attributes.clone())
+        .collect::<Vec<_>>();
+
+    sqlx::query(
+        "INSERT INTO logs (
+            id,
+            project_id,
+            trace_id,
+            span_id,
+            timestamp,
+            severity_number,
+            severity_text,
+            body,
+            attributes
+        )
+        SELECT
+            unnest($1::uuid[]),
+            $2,
+            unnest($3::uuid[]),
+            unnest($4::uuid[]),
+            unnest($5::timestamptz[]),
+            unnest($6::int4[]),
+            unnest($7::text[]),
+            unnest($8::jsonb[]),
+            unnest($9::jsonb[])",
+    )
+    .bind(ids)
+    .bind(project_id)
+    .bind(trace_ids)
+    .bind(span_ids)
+    .bind(timestamps)
+    .bind(severity_numbers)
+    .bind(severity_texts)
+    .bind(bodies)
+    .bind(attributes)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_logs_for_span(
+    pool: &PgPool,
+    project_id: &Uuid,
+    span_id: Uuid,
+) -> Result<Vec<LogRecord>> {
+    let logs = sqlx::query_as::<_, LogRecord>(
+        "SELECT
+            id,
+            trace_id,
+            span_id,
+            timestamp,
+            severity_number,
+            severity_text,
+            body,
+            attributes
+        FROM logs
+        WHERE span_id = $1 AND project_id = $2
+        ORDER BY timestamp ASC",
+    )
+    .bind(span_id)
+    .bind(project_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(logs)
+}
+
+/// Deletes the logs of the project older than `cutoff`, including the ones without a span
+pub async fn delete_logs_before(
+    pool: &PgPool,
+    project_id: &Uuid,
+    cutoff: DateTime<Utc>,
+) -> Result<u64> {
+    let deleted = sqlx::query("DELETE FROM logs WHERE project_id = $1 AND timestamp < $2")
+        .bind(project_id)
+        .bind(cutoff)
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+    Ok(deleted)
+}
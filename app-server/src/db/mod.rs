@@ -2,16 +2,21 @@ use sqlx::PgPool;
 
 pub mod datapoints;
 pub mod datasets;
+pub mod dead_letters;
 pub mod evaluations;
 pub mod event_templates;
 pub mod events;
 pub mod labels;
+pub mod logs;
 pub mod modifiers;
 pub mod pipelines;
 pub mod prices;
 pub mod project_api_keys;
 pub mod projects;
 pub mod provider_api_keys;
+pub mod redaction_rules;
+pub mod sampling_rules;
+pub mod span_events;
 pub mod spans;
 pub mod stats;
 pub mod subscriptions;
@@ -1,6 +1,9 @@
+use anyhow::{bail, Result};
 use chrono::{DateTime, Utc};
 use serde::Deserialize;
 use serde_json::Value;
+use sqlx::{Postgres, QueryBuilder};
+use uuid::Uuid;
 
 use super::utils::validate_sql_string;
 
@@ -11,11 +14,31 @@ pub struct Filter {
     pub filter_column: String,
 }
 
+/// Filters combined with AND / OR. The entries of the `filter` url param are combined with AND,
+/// and an entry of the form `{"and": [...]}` or `{"or": [...]}` is a nested group.
+#[derive(Debug, PartialEq, Clone)]
+pub enum FilterExpression {
+    Filter(Filter),
+    And(Vec<FilterExpression>),
+    Or(Vec<FilterExpression>),
+}
+
 #[derive(Deserialize)]
-struct UrlParamFilter {
-    value: Value,
-    operator: String,
-    column: String,
+#[serde(untagged)]
+enum UrlParamFilter {
+    And {
+        and: Vec<UrlParamFilter>,
+    },
+    Or {
+        or: Vec<UrlParamFilter>,
+    },
+    Filter {
+        /// Not needed for `exists`
+        #[serde(default)]
+        value: Value,
+        operator: String,
+        column: String,
+    },
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -26,64 +49,315 @@ pub enum FilterOperator {
     Lte,
     Gte,
     Ne,
+    /// Case-insensitive substring match
+    Contains,
+    NotContains,
+    In,
+    NotIn,
+    /// Matches if the value is present, or if it is absent when the filter value is `false`
+    Exists,
+    /// POSIX regular expression match
+    Regex,
+}
+
+/// Type of the filtered expression, which decides how the filter value is bound
+#[derive(Debug, Clone, Copy)]
+pub enum FilterValueType {
+    Text,
+    Uuid,
+    Float,
+    Bool,
+    /// Postgres enum with the given type name
+    Enum(&'static str),
+    /// `jsonb`, which is compared with the filter value parsed as JSON. Text operators are
+    /// applied to the unquoted value.
+    Json,
+}
+
+impl FilterValueType {
+    fn name(&self) -> &'static str {
+        match self {
+            FilterValueType::Text => "text",
+            FilterValueType::Uuid => "a UUID",
+            FilterValueType::Float => "a number",
+            FilterValueType::Bool => "true or false",
+            FilterValueType::Enum(type_name) => type_name,
+            FilterValueType::Json => "JSON",
+        }
+    }
 }
 
 impl FilterOperator {
-    fn from_string(string: &str) -> Self {
-        match string {
+    fn from_string(string: &str) -> Result<Self> {
+        let operator = match string {
             "eq" => Self::Eq,
             "lt" => Self::Lt,
             "gt" => Self::Gt,
             "lte" => Self::Lte,
             "gte" => Self::Gte,
             "ne" => Self::Ne,
-            _ => Self::Eq,
+            "contains" => Self::Contains,
+            "not_contains" => Self::NotContains,
+            "in" => Self::In,
+            "not_in" => Self::NotIn,
+            "exists" => Self::Exists,
+            "regex" => Self::Regex,
+            _ => bail!("Invalid filter operator: {}", string),
+        };
+        Ok(operator)
+    }
+
+    /// SQL operator of a comparison, `None` for the operators which are not plain comparisons
+    pub fn to_sql_operator(&self) -> Option<String> {
+        let operator = match self {
+            FilterOperator::Eq => "=",
+            FilterOperator::Lt => "<",
+            FilterOperator::Gt => ">",
+            FilterOperator::Lte => "<=",
+            FilterOperator::Gte => ">=",
+            FilterOperator::Ne => "!=",
+            _ => return None,
+        };
+        Some(String::from(operator))
+    }
+}
+
+impl Filter {
+    /// Validate that the column is a valid Postgres column name. This is a minimal check
+    /// to prevent SQL injection attacks.
+    pub fn validate_column(&self) -> bool {
+        validate_sql_string(&self.filter_column)
+    }
+
+    /// Checks that the value can be compared with an expression of `value_type`, so that
+    /// invalid values are rejected when the filter is parsed, rather than by the database.
+    ///
+    /// Regexes are checked with Rust syntax, which differs from the Postgres one in a few
+    /// constructs, e.g. named groups. Those fail in the database and are reported as invalid
+    /// requests as well.
+    pub fn validate_value(&self, value_type: FilterValueType) -> Result<()> {
+        if self.filter_operator == FilterOperator::Regex {
+            if let Err(e) = fancy_regex::Regex::new(&self.value_string()) {
+                bail!("Invalid regex for {}: {}", self.filter_column, e);
+            }
+            return Ok(());
+        }
+        // Other operators compare the value as text
+        if self.filter_operator.to_sql_operator().is_none() {
+            return Ok(());
+        }
+
+        let value = self.value_string();
+        let is_valid = match value_type {
+            FilterValueType::Uuid => Uuid::parse_str(&value).is_ok(),
+            FilterValueType::Float => value.parse::<f64>().is_ok(),
+            FilterValueType::Bool => value.parse::<bool>().is_ok(),
+            FilterValueType::Text | FilterValueType::Enum(_) | FilterValueType::Json => true,
+        };
+        if !is_valid {
+            bail!(
+                "Invalid value for {}, expected {}: {}",
+                self.filter_column,
+                value_type.name(),
+                value
+            );
         }
+        Ok(())
     }
 
-    pub fn to_sql_operator(&self) -> String {
-        match self {
-            FilterOperator::Eq => String::from("="),
-            FilterOperator::Lt => String::from("<"),
-            FilterOperator::Gt => String::from(">"),
-            FilterOperator::Lte => String::from("<="),
-            FilterOperator::Gte => String::from(">="),
-            FilterOperator::Ne => String::from("!="),
+    /// Pushes the condition of this filter on the expression pushed by `push_expression`
+    pub fn push_condition<'a>(
+        &self,
+        query: &mut QueryBuilder<'a, Postgres>,
+        push_expression: impl FnOnce(&mut QueryBuilder<'a, Postgres>),
+        value_type: FilterValueType,
+    ) {
+        if self.filter_operator == FilterOperator::Exists {
+            push_expression(query);
+            let exists =
+                !matches!(&self.filter_value, Value::Bool(false)) && self.value_string() != "false";
+            query.push(if exists { " IS NOT NULL" } else { " IS NULL" });
+            return;
+        }
+
+        let Some(sql_operator) = self.filter_operator.to_sql_operator() else {
+            query.push("(");
+            push_expression(query);
+            query.push(match value_type {
+                FilterValueType::Json => ") #>> '{}'",
+                _ => ")::text",
+            });
+            match self.filter_operator {
+                FilterOperator::Contains => {
+                    query
+                        .push(" ILIKE ")
+                        .push_bind(format!("%{}%", escape_like_pattern(&self.value_string())));
+                }
+                FilterOperator::NotContains => {
+                    query
+                        .push(" NOT ILIKE ")
+                        .push_bind(format!("%{}%", escape_like_pattern(&self.value_string())));
+                }
+                FilterOperator::Regex => {
+                    query.push(" ~ ").push_bind(self.value_string());
+                }
+                FilterOperator::In => {
+                    query.push(" = ANY(").push_bind(self.value_list()).push(")");
+                }
+                _ => {
+                    query
+                        .push(" <> ALL(")
+                        .push_bind(self.value_list())
+                        .push(")");
+                }
+            }
+            return;
+        };
+
+        push_expression(query);
+        query.push(format!(" {sql_operator} "));
+        let value = self.value_string();
+        match value_type {
+            FilterValueType::Text => {
+                query.push_bind(value);
+            }
+            // Values are checked by `validate_value` when the filter is parsed
+            FilterValueType::Uuid => {
+                query.push_bind(value).push("::uuid");
+            }
+            FilterValueType::Float => {
+                query.push_bind(value).push("::float8");
+            }
+            FilterValueType::Bool => {
+                query.push_bind(value).push("::bool");
+            }
+            FilterValueType::Enum(type_name) => {
+                query.push_bind(value).push(format!("::{type_name}"));
+            }
+            FilterValueType::Json => {
+                let json_value = match &self.filter_value {
+                    Value::String(s) => serde_json::from_str(s).unwrap_or(Value::String(s.clone())),
+                    v => v.clone(),
+                };
+                query.push_bind(json_value).push("::jsonb");
+            }
+        }
+    }
+
+    fn value_string(&self) -> String {
+        match &self.filter_value {
+            Value::String(s) => s.clone(),
+            v => v.to_string(),
         }
     }
+
+    /// Values of `in` and `not_in`, given either as a JSON array or as a comma-separated string
+    fn value_list(&self) -> Vec<String> {
+        let values = match &self.filter_value {
+            Value::String(s) => match serde_json::from_str::<Vec<Value>>(s) {
+                Ok(values) => values,
+                Err(_) => {
+                    return s.split(',').map(|v| v.trim().to_string()).collect();
+                }
+            },
+            Value::Array(values) => values.clone(),
+            v => vec![v.clone()],
+        };
+        values
+            .into_iter()
+            .map(|v| match v {
+                Value::String(s) => s,
+                v => v.to_string(),
+            })
+            .collect()
+    }
 }
 
-impl Filter {
-    pub fn from_url_params(url_params: Value) -> Option<Vec<Self>> {
+impl FilterExpression {
+    /// Parses the `filter` url param, failing on unknown operators, invalid columns, and values
+    /// rejected by `validate_filter`, which usually checks them with `Filter::validate_value`
+    pub fn from_url_params(
+        url_params: Value,
+        validate_filter: &impl Fn(&Filter) -> Result<()>,
+    ) -> Result<Option<Vec<Self>>> {
         // TODO: these hacks are there, because `actix_web` (not `actix_web_lab`) does not support
         // repeated url params, such as `?filter=...&filter=...`.
         match url_params {
+            Value::String(filter) if filter.trim().is_empty() => Ok(None),
             Value::String(filter) => {
-                let arr = serde_json::from_str::<Vec<Value>>(&filter).ok()?;
-                Self::from_url_params(Value::Array(arr))
+                let arr = serde_json::from_str::<Vec<Value>>(&filter)?;
+                Self::from_url_params(Value::Array(arr), validate_filter)
             }
-            Value::Array(values) => Some(
-                values
+            Value::Array(values) => {
+                let filters = values
                     .into_iter()
-                    .filter_map(|value| {
-                        let filter: UrlParamFilter = serde_json::from_value(value).ok()?;
-                        let filter_column = filter.column;
-                        Some(Self {
-                            filter_value: filter.value,
-                            filter_operator: FilterOperator::from_string(&filter.operator),
-                            filter_column,
-                        })
+                    .map(|value| {
+                        Self::from_url_param_filter(serde_json::from_value(value)?, validate_filter)
                     })
-                    .collect(),
-            ),
-            _ => None,
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(Some(filters))
+            }
+            _ => Ok(None),
         }
     }
 
-    /// Validate that the column is a valid Postgres column name. This is a minimal check
-    /// to prevent SQL injection attacks.
-    pub fn validate_column(&self) -> bool {
-        validate_sql_string(&self.filter_column)
+    fn from_url_param_filter(
+        filter: UrlParamFilter,
+        validate_filter: &impl Fn(&Filter) -> Result<()>,
+    ) -> Result<Self> {
+        let group = |filters: Vec<UrlParamFilter>| {
+            if filters.is_empty() {
+                bail!("Filter group must not be empty");
+            }
+            filters
+                .into_iter()
+                .map(|filter| Self::from_url_param_filter(filter, validate_filter))
+                .collect::<Result<Vec<_>>>()
+        };
+        match filter {
+            UrlParamFilter::And { and } => Ok(Self::And(group(and)?)),
+            UrlParamFilter::Or { or } => Ok(Self::Or(group(or)?)),
+            UrlParamFilter::Filter {
+                value,
+                operator,
+                column,
+            } => {
+                let filter = Filter {
+                    filter_value: value,
+                    filter_operator: FilterOperator::from_string(&operator)?,
+                    filter_column: column,
+                };
+                if !filter.validate_column() {
+                    bail!("Invalid filter column: {}", filter.filter_column);
+                }
+                validate_filter(&filter)?;
+                Ok(Self::Filter(filter))
+            }
+        }
+    }
+
+    /// Pushes the expression as a single condition, `push_filter` pushes the condition of a filter
+    pub fn push_condition<'a>(
+        &self,
+        query: &mut QueryBuilder<'a, Postgres>,
+        push_filter: &impl Fn(&mut QueryBuilder<'a, Postgres>, &Filter),
+    ) {
+        let (filters, separator) = match self {
+            FilterExpression::Filter(filter) => {
+                push_filter(query, filter);
+                return;
+            }
+            FilterExpression::And(filters) => (filters, " AND "),
+            FilterExpression::Or(filters) => (filters, " OR "),
+        };
+        query.push("(");
+        for (i, filter) in filters.iter().enumerate() {
+            if i > 0 {
+                query.push(separator);
+            }
+            filter.push_condition(query, push_filter);
+        }
+        query.push(")");
     }
 }
 
@@ -108,6 +382,13 @@ pub enum DateRange {
     Absolute(AbsoluteDateInterval),
 }
 
+/// Position of the last exported row, exports are ordered by the start time and then the id
+#[derive(Debug, Clone, Copy)]
+pub struct ExportCursor {
+    pub start_time: DateTime<Utc>,
+    pub id: Uuid,
+}
+
 impl Default for DateRange {
     fn default() -> Self {
         DateRange::Relative(RelativeDateInterval {
@@ -116,31 +397,149 @@ impl Default for DateRange {
     }
 }
 
+/// Escapes the wildcards of `LIKE`, so that the value is matched literally.
+/// Backslash is the default escape character of Postgres.
+fn escape_like_pattern(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
 #[cfg(test)]
 mod tests {
-    use super::FilterOperator;
+    use serde_json::{json, Value};
+
+    use super::{escape_like_pattern, Filter, FilterExpression, FilterOperator, FilterValueType};
 
     #[test]
     fn test_filter_type_from_string() {
-        assert_eq!(FilterOperator::from_string("eq"), FilterOperator::Eq);
-        assert_eq!(FilterOperator::from_string("ne"), FilterOperator::Ne);
-        assert_eq!(FilterOperator::from_string("lt"), FilterOperator::Lt);
-        assert_eq!(FilterOperator::from_string("lte"), FilterOperator::Lte);
-        assert_eq!(FilterOperator::from_string("gt"), FilterOperator::Gt);
-        assert_eq!(FilterOperator::from_string("gte"), FilterOperator::Gte);
         assert_eq!(
-            FilterOperator::from_string("some_other_string"),
+            FilterOperator::from_string("eq").unwrap(),
             FilterOperator::Eq
         );
+        assert_eq!(
+            FilterOperator::from_string("ne").unwrap(),
+            FilterOperator::Ne
+        );
+        assert_eq!(
+            FilterOperator::from_string("lt").unwrap(),
+            FilterOperator::Lt
+        );
+        assert_eq!(
+            FilterOperator::from_string("lte").unwrap(),
+            FilterOperator::Lte
+        );
+        assert_eq!(
+            FilterOperator::from_string("gt").unwrap(),
+            FilterOperator::Gt
+        );
+        assert_eq!(
+            FilterOperator::from_string("gte").unwrap(),
+            FilterOperator::Gte
+        );
+        assert_eq!(
+            FilterOperator::from_string("not_contains").unwrap(),
+            FilterOperator::NotContains
+        );
+        assert_eq!(
+            FilterOperator::from_string("in").unwrap(),
+            FilterOperator::In
+        );
+        assert_eq!(
+            FilterOperator::from_string("exists").unwrap(),
+            FilterOperator::Exists
+        );
+        assert!(FilterOperator::from_string("some_other_string").is_err());
     }
 
     #[test]
     fn test_filter_type_to_sql() {
-        assert_eq!(FilterOperator::Eq.to_sql_operator(), String::from("="));
-        assert_eq!(FilterOperator::Ne.to_sql_operator(), String::from("!="));
-        assert_eq!(FilterOperator::Lt.to_sql_operator(), String::from("<"));
-        assert_eq!(FilterOperator::Lte.to_sql_operator(), String::from("<="));
-        assert_eq!(FilterOperator::Gt.to_sql_operator(), String::from(">"));
-        assert_eq!(FilterOperator::Gte.to_sql_operator(), String::from(">="));
+        assert_eq!(
+            FilterOperator::Eq.to_sql_operator(),
+            Some(String::from("="))
+        );
+        assert_eq!(
+            FilterOperator::Ne.to_sql_operator(),
+            Some(String::from("!="))
+        );
+        assert_eq!(
+            FilterOperator::Lt.to_sql_operator(),
+            Some(String::from("<"))
+        );
+        assert_eq!(
+            FilterOperator::Lte.to_sql_operator(),
+            Some(String::from("<="))
+        );
+        assert_eq!(
+            FilterOperator::Gt.to_sql_operator(),
+            Some(String::from(">"))
+        );
+        assert_eq!(
+            FilterOperator::Gte.to_sql_operator(),
+            Some(String::from(">="))
+        );
+        assert_eq!(FilterOperator::Contains.to_sql_operator(), None);
+    }
+
+    #[test]
+    fn test_escape_like_pattern() {
+        assert_eq!(escape_like_pattern("50%_off"), r"50\%\_off");
+        assert_eq!(escape_like_pattern(r"C:\dir"), r"C:\\dir");
+    }
+
+    #[test]
+    fn test_filter_groups_from_url_params() {
+        let filters = FilterExpression::from_url_params(
+            json!(
+                r#"[{"or": [{"column": "user_id", "operator": "in", "value": ["a", "b"]},
+                            {"column": "metadata.env", "operator": "exists"}]},
+                    {"column": "cost", "operator": "gt", "value": "0.5"}]"#
+            ),
+            &|filter| filter.validate_value(value_type(filter)),
+        )
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(filters.len(), 2);
+        let FilterExpression::Or(group) = &filters[0] else {
+            panic!("expected an OR group");
+        };
+        assert_eq!(group.len(), 2);
+        assert!(matches!(
+            &group[1],
+            FilterExpression::Filter(Filter {
+                filter_operator: FilterOperator::Exists,
+                filter_value: Value::Null,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_invalid_filters_from_url_params() {
+        let invalid = [
+            json!(r#"[{"column": "cost", "operator": "approx", "value": "1"}]"#),
+            json!(r#"[{"column": "cost; DROP TABLE traces", "operator": "eq", "value": "1"}]"#),
+            json!(r#"[{"or": []}]"#),
+            json!(r#"[{"column": "cost", "operator": "gt", "value": "cheap"}]"#),
+            json!(r#"[{"column": "id", "operator": "eq", "value": "not-a-uuid"}]"#),
+            json!(r#"[{"or": [{"column": "has_error", "operator": "eq", "value": "maybe"}]}]"#),
+            json!(r#"[{"column": "user_id", "operator": "regex", "value": "(unclosed"}]"#),
+        ];
+        for filter in invalid {
+            assert!(FilterExpression::from_url_params(filter, &|filter| filter
+                .validate_value(value_type(filter)))
+            .is_err());
+        }
+    }
+
+    fn value_type(filter: &Filter) -> FilterValueType {
+        match filter.filter_column.as_str() {
+            "id" => FilterValueType::Uuid,
+            "cost" => FilterValueType::Float,
+            "has_error" => FilterValueType::Bool,
+            _ => FilterValueType::Text,
+        }
     }
 }
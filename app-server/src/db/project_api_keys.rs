@@ -9,6 +9,11 @@ pub struct ProjectApiKey {
     pub name: Option<String>,
     pub hash: String,
     pub shorthand: String,
+    /// Spans per second which the key can export, unlimited if not set
+    pub rate_limit_per_second: Option<f64>,
+    /// Spans which can be exported at once after the key has been idle,
+    /// defaults to one second worth of spans
+    pub rate_limit_burst: Option<i32>,
 }
 
 #[derive(Serialize, FromRow)]
@@ -17,6 +22,8 @@ pub struct ProjectApiKeyResponse {
     pub project_id: Uuid,
     pub name: Option<String>,
     pub shorthand: String,
+    pub rate_limit_per_second: Option<f64>,
+    pub rate_limit_burst: Option<i32>,
 }
 
 pub async fn create_project_api_key(
@@ -30,7 +37,7 @@ pub async fn create_project_api_key(
         "INSERT
         INTO project_api_keys (shorthand, project_id, name, hash)
         VALUES ($1, $2, $3, $4)
-        RETURNING id, project_id, name, hash, shorthand",
+        RETURNING id, project_id, name, hash, shorthand, rate_limit_per_second, rate_limit_burst",
     )
     .bind(&shorthand)
     .bind(&project_id)
@@ -51,7 +58,9 @@ pub async fn get_api_keys_for_project(
             project_api_keys.project_id,
             project_api_keys.name,
             project_api_keys.id,
-            project_api_keys.shorthand
+            project_api_keys.shorthand,
+            project_api_keys.rate_limit_per_second,
+            project_api_keys.rate_limit_burst
         FROM
             project_api_keys
         WHERE
@@ -71,7 +80,9 @@ pub async fn get_api_key(pool: &PgPool, hash: &String) -> Result<ProjectApiKey>
             project_api_keys.project_id,
             project_api_keys.name,
             project_api_keys.id,
-            project_api_keys.shorthand
+            project_api_keys.shorthand,
+            project_api_keys.rate_limit_per_second,
+            project_api_keys.rate_limit_burst
         FROM
             project_api_keys
         WHERE
@@ -108,3 +119,27 @@ pub async fn delete_api_key(pool: &PgPool, id: &Uuid, project_id: &Uuid) -> Resu
 
     Ok(row.hash)
 }
+
+/// Returns the hash of the key, so that it can be invalidated in the cache
+pub async fn update_api_key_rate_limit(
+    pool: &PgPool,
+    id: &Uuid,
+    project_id: &Uuid,
+    rate_limit_per_second: Option<f64>,
+    rate_limit_burst: Option<i32>,
+) -> Result<String> {
+    let row = sqlx::query_as::<_, ProjectApiKeyHash>(
+        "UPDATE project_api_keys
+        SET rate_limit_per_second = $3, rate_limit_burst = $4
+        WHERE id = $1 AND project_id = $2
+        RETURNING hash",
+    )
+    .bind(id)
+    .bind(project_id)
+    .bind(rate_limit_per_second)
+    .bind(rate_limit_burst)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row.hash)
+}
@@ -1,5 +1,5 @@
 use anyhow::Result;
-use sqlx::PgPool;
+use sqlx::{FromRow, PgPool};
 use uuid::Uuid;
 
 use crate::projects::Project;
@@ -76,3 +76,45 @@ pub async fn delete_project(pool: &PgPool, project_id: &Uuid) -> Result<()> {
 
     Ok(())
 }
+
+#[derive(FromRow)]
+pub struct ProjectRetention {
+    pub id: Uuid,
+    pub retention_days: i32,
+}
+
+/// Projects which keep their data for a limited number of days
+pub async fn get_projects_with_retention(pool: &PgPool) -> Result<Vec<ProjectRetention>> {
+    let projects = sqlx::query_as::<_, ProjectRetention>(
+        "SELECT id, retention_days FROM projects WHERE retention_days IS NOT NULL",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(projects)
+}
+
+pub async fn get_project_retention_days(pool: &PgPool, project_id: &Uuid) -> Result<Option<i32>> {
+    let retention_days =
+        sqlx::query_scalar::<_, Option<i32>>("SELECT retention_days FROM projects WHERE id = $1")
+            .bind(project_id)
+            .fetch_one(pool)
+            .await?;
+
+    Ok(retention_days)
+}
+
+/// `None` keeps the data of the project forever
+pub async fn update_project_retention_days(
+    pool: &PgPool,
+    project_id: &Uuid,
+    retention_days: Option<i32>,
+) -> Result<()> {
+    sqlx::query("UPDATE projects SET retention_days = $2 WHERE id = $1")
+        .bind(project_id)
+        .bind(retention_days)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+#[derive(sqlx::Type, Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[sqlx(type_name = "redaction_rule_type")]
+#[allow(non_camel_case_types)]
+pub enum RedactionRuleType {
+    EMAIL,
+    PHONE,
+    CARD_NUMBER,
+    /// Regex pattern in `value`
+    PATTERN,
+    /// Attribute key in `value`
+    ATTRIBUTE,
+}
+
+#[derive(Serialize, FromRow, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RedactionRule {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub project_id: Uuid,
+    pub rule_type: RedactionRuleType,
+    pub value: Option<String>,
+}
+
+pub async fn get_redaction_rules(pool: &PgPool, project_id: &Uuid) -> Result<Vec<RedactionRule>> {
+    let rules = sqlx::query_as::<_, RedactionRule>(
+        "SELECT id, created_at, project_id, rule_type, value
+        FROM redaction_rules
+        WHERE project_id = $1
+        ORDER BY created_at ASC",
+    )
+    .bind(project_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rules)
+}
+
+pub async fn create_redaction_rule(
+    pool: &PgPool,
+    project_id: &Uuid,
+    rule_type: &RedactionRuleType,
+    value: &Option<String>,
+) -> Result<RedactionRule> {
+    let rule = sqlx::query_as::<_, RedactionRule>(
+        "INSERT INTO redaction_rules (project_id, rule_type, value)
+        VALUES ($1, $2, $3)
+        RETURNING id, created_at, project_id, rule_type, value",
+    )
+    .bind(project_id)
+    .bind(rule_type)
+    .bind(value)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(rule)
+}
+
+pub async fn delete_redaction_rule(pool: &PgPool, project_id: &Uuid, rule_id: &Uuid) -> Result<()> {
+    sqlx::query("DELETE FROM redaction_rules WHERE project_id = $1 AND id = $2")
+        .bind(project_id)
+        .bind(rule_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,73 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SamplingRules {
+    /// Share of traces kept regardless of what they contain, from 0 to 1
+    pub head_sample_rate: f64,
+    /// Keep traces with at least one errored span
+    pub keep_errors: bool,
+    /// Keep traces which take at least this long
+    pub slow_trace_ms: Option<i64>,
+    /// Keep traces which cost at least this much
+    pub expensive_trace_cost: Option<f64>,
+}
+
+/// Every trace is kept unless the project has sampling rules
+impl Default for SamplingRules {
+    fn default() -> Self {
+        Self {
+            head_sample_rate: 1.0,
+            keep_errors: true,
+            slow_trace_ms: None,
+            expensive_trace_cost: None,
+        }
+    }
+}
+
+pub async fn get_sampling_rules(pool: &PgPool, project_id: &Uuid) -> Result<Option<SamplingRules>> {
+    let rules = sqlx::query_as::<_, SamplingRules>(
+        "SELECT head_sample_rate, keep_errors, slow_trace_ms, expensive_trace_cost
+        FROM sampling_rules
+        WHERE project_id = $1",
+    )
+    .bind(project_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(rules)
+}
+
+pub async fn upsert_sampling_rules(
+    pool: &PgPool,
+    project_id: &Uuid,
+    rules: &SamplingRules,
+) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO sampling_rules (
+            project_id,
+            head_sample_rate,
+            keep_errors,
+            slow_trace_ms,
+            expensive_trace_cost
+        )
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (project_id) DO UPDATE SET
+            head_sample_rate = EXCLUDED.head_sample_rate,
+            keep_errors = EXCLUDED.keep_errors,
+            slow_trace_ms = EXCLUDED.slow_trace_ms,
+            expensive_trace_cost = EXCLUDED.expensive_trace_cost",
+    )
+    .bind(project_id)
+    .bind(rules.head_sample_rate)
+    .bind(rules.keep_errors)
+    .bind(rules.slow_trace_ms)
+    .bind(rules.expensive_trace_cost)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,133 @@
+use anyhow::Result;
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::opentelemetry::opentelemetry_proto_trace_v1::span::Event as OtelEvent;
+
+use super::utils::convert_any_value_to_json_value;
+
+/// OTel span event, as opposed to Laminar events, which are recorded in `events`
+#[derive(sqlx::FromRow, Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SpanEvent {
+    pub id: Uuid,
+    pub span_id: Uuid,
+    pub timestamp: DateTime<Utc>,
+    pub name: String,
+    pub attributes: Value,
+}
+
+impl SpanEvent {
+    pub fn from_otel(event: OtelEvent, span_id: Uuid) -> Self {
+        let attributes = event
+            .attributes
+            .into_iter()
+            .filter(|kv| kv.value.as_ref().is_some_and(|v| v.value.is_some()))
+            .map(|kv| (kv.key, convert_any_value_to_json_value(kv.value)))
+            .collect::<serde_json::Map<String, Value>>();
+
+        Self {
+            id: Uuid::new_v4(),
+            span_id,
+            timestamp: Utc.timestamp_nanos(event.time_unix_nano as i64),
+            name: event.name,
+            attributes: Value::Object(attributes),
+        }
+    }
+}
+
+pub async fn record_span_events(
+    pool: &PgPool,
+    project_id: Uuid,
+    events: &[SpanEvent],
+) -> Result<()> {
+    if events.is_empty() {
+        return Ok(());
+    }
+
+    let ids = events.iter().map(|event| event.id).collect::<Vec<_>>();
+    let span_ids = events.iter().map(|event| event.span_id).collect::<Vec<_>>();
+    let timestamps = events
+        .iter()
+        .map(|event| event.timestamp)
+        .collect::<Vec<_>>();
+    let names = events
+        .iter()
+        .map(|event| event.name.clone())
+        .collect::<Vec<_>>();
+    let attributes = events
+        .iter()
+        .map(|event| event.attributes.clone())
+        .collect::<Vec<_>>();
+
+    sqlx::query(
+        "INSERT INTO span_events (
+            id,
+            project_id,
+            span_id,
+            timestamp,
+            name,
+            attributes
+        )
+        SELECT
+            unnest($1::uuid[]),
+            $2,
+            unnest($3::uuid[]),
+            unnest($4::timestamptz[]),
+            unnest($5::text[]),
+            unnest($6::jsonb[])
+        ON CONFLICT (id) DO NOTHING",
+    )
+    .bind(ids)
+    .bind(project_id)
+    .bind(span_ids)
+    .bind(timestamps)
+    .bind(names)
+    .bind(attributes)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_span_events(
+    pool: &PgPool,
+    project_id: &Uuid,
+    span_id: Uuid,
+) -> Result<Vec<SpanEvent>> {
+    let events = sqlx::query_as::<_, SpanEvent>(
+        "SELECT
+            id,
+            span_id,
+            timestamp,
+            name,
+            attributes
+        FROM span_events
+        WHERE span_id = $1 AND project_id = $2
+        ORDER BY timestamp ASC",
+    )
+    .bind(span_id)
+    .bind(project_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(events)
+}
+
+pub async fn delete_span_events_before(
+    pool: &PgPool,
+    project_id: &Uuid,
+    cutoff: DateTime<Utc>,
+) -> Result<u64> {
+    let deleted = sqlx::query("DELETE FROM span_events WHERE project_id = $1 AND timestamp < $2")
+        .bind(project_id)
+        .bind(cutoff)
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+    Ok(deleted)
+}
@@ -2,10 +2,23 @@ use anyhow::Result;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use sqlx::{FromRow, PgPool, Postgres};
+use sqlx::{FromRow, PgExecutor, PgPool, Postgres, QueryBuilder};
 use uuid::Uuid;
 
+use crate::traces::span_attributes::{
+    GEN_AI_INPUT_TOKENS, GEN_AI_OUTPUT_TOKENS, GEN_AI_REQUEST_MODEL, GEN_AI_RESPONSE_MODEL,
+    GEN_AI_TOTAL_COST, GEN_AI_TOTAL_TOKENS, SPAN_PATH,
+};
+
+use super::{
+    modifiers::{DateRange, ExportCursor, Filter, FilterExpression, FilterValueType},
+    utils::add_date_range_to_query,
+};
+
 const PREVIEW_CHARACTERS: usize = 50;
+/// Maximum characters of each of the span's name, input, output and attributes in the
+/// `search_vector` column, which Postgres limits to 1MB
+const MAX_SEARCH_TEXT_CHARACTERS: usize = 50_000;
 
 #[derive(sqlx::Type, Deserialize, Serialize, PartialEq, Clone, Debug, Default)]
 #[sqlx(type_name = "span_type")]
@@ -19,6 +32,16 @@ pub enum SpanType {
     EVALUATION,
 }
 
+/// OTel span status code
+#[derive(sqlx::Type, Deserialize, Serialize, PartialEq, Clone, Debug, Default)]
+#[sqlx(type_name = "span_status")]
+pub enum SpanStatus {
+    #[default]
+    UNSET,
+    OK,
+    ERROR,
+}
+
 #[derive(Deserialize, Serialize, Clone, Debug, Default, FromRow)]
 #[serde(rename_all = "camelCase")]
 pub struct Span {
@@ -35,9 +58,26 @@ pub struct Span {
     pub end_time: DateTime<Utc>,
     pub events: Option<Value>,
     pub labels: Option<Value>,
+    /// Set if the span has an error status or recorded an exception
+    #[serde(default)]
+    pub has_error: bool,
+    #[serde(default)]
+    pub status: SpanStatus,
+    #[serde(default)]
+    pub status_message: Option<String>,
+    /// Attributes of the OTel resource that emitted the span, e.g. `service.name`
+    #[serde(default)]
+    pub resource_attributes: Value,
+    /// Instrumentation scope, usually the instrumentation library
+    #[serde(default)]
+    pub scope_name: Option<String>,
+    #[serde(default)]
+    pub scope_version: Option<String>,
 }
 
-pub async fn record_span(pool: &PgPool, span: &Span) -> Result<()> {
+/// Returns whether the span is inserted, as opposed to updated because it was recorded before.
+/// A concurrent insert of the same span waits for this one to commit, and then updates it.
+pub async fn record_span<'e>(executor: impl PgExecutor<'e>, span: &Span) -> Result<bool> {
     let input_preview = match &span.input {
         &Some(Value::String(ref s)) => Some(s.chars().take(PREVIEW_CHARACTERS).collect::<String>()),
         &Some(ref v) => Some(
@@ -58,7 +98,10 @@ pub async fn record_span(pool: &PgPool, span: &Span) -> Result<()> {
         ),
         &None => None,
     };
-    sqlx::query(
+    let mut input_output_text = search_text(span.input.as_ref());
+    input_output_text.push(' ');
+    input_output_text.push_str(&search_text(span.output.as_ref()));
+    let is_new = sqlx::query_scalar::<_, bool>(
         "INSERT INTO spans
             (version,
             span_id,
@@ -72,7 +115,14 @@ pub async fn record_span(pool: &PgPool, span: &Span) -> Result<()> {
             output,
             span_type,
             input_preview,
-            output_preview
+            output_preview,
+            has_error,
+            status,
+            status_message,
+            resource_attributes,
+            scope_name,
+            scope_version,
+            search_vector
         )
         VALUES(
             $1,
@@ -87,7 +137,16 @@ pub async fn record_span(pool: &PgPool, span: &Span) -> Result<()> {
             $10,
             $11,
             $12,
-            $13)
+            $13,
+            $14,
+            $15,
+            $16,
+            $17,
+            $18,
+            $19,
+            setweight(to_tsvector('english', $20), 'A')
+                || setweight(to_tsvector('english', $21), 'B')
+                || setweight(to_tsvector('english', $22), 'C'))
         ON CONFLICT (span_id) DO UPDATE SET 
             version = EXCLUDED.version,
             trace_id = EXCLUDED.trace_id,
@@ -100,7 +159,15 @@ pub async fn record_span(pool: &PgPool, span: &Span) -> Result<()> {
             output = EXCLUDED.output,
             span_type = EXCLUDED.span_type,
             input_preview = EXCLUDED.input_preview,
-            output_preview = EXCLUDED.output_preview
+            output_preview = EXCLUDED.output_preview,
+            has_error = EXCLUDED.has_error,
+            status = EXCLUDED.status,
+            status_message = EXCLUDED.status_message,
+            resource_attributes = EXCLUDED.resource_attributes,
+            scope_name = EXCLUDED.scope_name,
+            scope_version = EXCLUDED.scope_version,
+            search_vector = EXCLUDED.search_vector
+        RETURNING (xmax = 0)
     ",
     )
     .bind(&span.version)
@@ -116,19 +183,86 @@ pub async fn record_span(pool: &PgPool, span: &Span) -> Result<()> {
     .bind(&span.span_type as &SpanType)
     .bind(&input_preview)
     .bind(&output_preview)
-    .execute(pool)
+    .bind(&span.has_error)
+    .bind(&span.status as &SpanStatus)
+    .bind(&span.status_message as &Option<String>)
+    .bind(&span.resource_attributes)
+    .bind(&span.scope_name as &Option<String>)
+    .bind(&span.scope_version as &Option<String>)
+    .bind(
+        span.name
+            .chars()
+            .take(MAX_SEARCH_TEXT_CHARACTERS)
+            .collect::<String>(),
+    )
+    .bind(&input_output_text)
+    .bind(search_text(Some(&span.attributes)))
+    .fetch_one(executor)
     .await?;
 
-    Ok(())
+    Ok(is_new)
 }
 
-pub async fn get_trace_spans(
-    pool: &PgPool,
-    trace_id: Uuid,
-    search: Option<String>,
-) -> Result<Vec<Span>> {
+/// String values of the JSON, like `jsonb_to_tsvector` with `'["string"]'`, truncated to
+/// `MAX_SEARCH_TEXT_CHARACTERS`
+fn search_text(value: Option<&Value>) -> String {
+    fn push_strings(value: &Value, text: &mut String, chars: &mut usize) {
+        match value {
+            Value::String(s) => {
+                if *chars >= MAX_SEARCH_TEXT_CHARACTERS {
+                    return;
+                }
+                if !text.is_empty() {
+                    text.push(' ');
+                }
+                let taken = s
+                    .chars()
+                    .take(MAX_SEARCH_TEXT_CHARACTERS - *chars)
+                    .collect::<String>();
+                *chars += taken.chars().count();
+                text.push_str(&taken);
+            }
+            Value::Array(values) => values
+                .iter()
+                .for_each(|value| push_strings(value, text, chars)),
+            Value::Object(values) => values
+                .values()
+                .for_each(|value| push_strings(value, text, chars)),
+            _ => {}
+        }
+    }
+
+    let mut text = String::new();
+    if let Some(value) = value {
+        push_strings(value, &mut text, &mut 0);
+    }
+    text
+}
+
+/// Span of a trace which matches the search query
+#[derive(Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct SpanSearchMatch {
+    pub span_id: Uuid,
+    pub rank: f32,
+    /// Fragments of the span's name, input, output and attributes,
+    /// with the matched words wrapped in `<mark>` tags
+    pub highlight: String,
+}
+
+/// Pushes the search text as a tsquery, in the same text search configuration as
+/// the `search_vector` column of spans. `websearch_to_tsquery` supports quoted phrases,
+/// `or` and `-` for excluded words, and never fails on invalid syntax.
+pub fn push_search_query(query: &mut QueryBuilder<Postgres>, search: &str) {
+    query
+        .push("websearch_to_tsquery('english', ")
+        .push_bind(search.to_string())
+        .push(")");
+}
+
+pub async fn get_trace_spans(pool: &PgPool, trace_id: Uuid) -> Result<Vec<Span>> {
     let mut query = sqlx::QueryBuilder::<Postgres>::new(
-        "WITH span_events AS (
+        "WITH events_by_span AS (
             SELECT
                 events.span_id,
                 jsonb_agg(
@@ -180,40 +314,99 @@ pub async fn get_trace_spans(
                 spans.name,
                 spans.attributes,
                 spans.span_type,
-                COALESCE(span_events.events, '[]'::jsonb) AS events,
+                spans.has_error,
+                spans.status,
+                spans.status_message,
+                spans.resource_attributes,
+                spans.scope_name,
+                spans.scope_version,
+                COALESCE(events_by_span.events, '[]'::jsonb) AS events,
                 COALESCE(span_labels.labels, '[]'::jsonb) AS labels
             FROM spans
-            LEFT JOIN span_events ON spans.span_id = span_events.span_id
+            LEFT JOIN events_by_span ON spans.span_id = events_by_span.span_id
             LEFT JOIN span_labels ON spans.span_id = span_labels.span_id
             WHERE spans.trace_id = ",
     );
     query.push_bind(trace_id);
     query.push(
         ")
-        SELECT * FROM spans_info WHERE 1=1
+        SELECT * FROM spans_info ORDER BY start_time ASC
     ",
     );
 
-    if let Some(search) = search {
-        query
-            .push(" AND (input::TEXT ILIKE ")
-            .push_bind(format!("%{search}%"))
-            .push(" OR output::TEXT ILIKE ")
-            .push_bind(format!("%{search}%"))
-            .push(" OR name::TEXT ILIKE ")
-            .push_bind(format!("%{search}%"))
-            .push(" OR attributes::TEXT ILIKE ")
-            .push_bind(format!("%{search}%"));
-    }
+    let spans = query.build_query_as().fetch_all(pool).await?;
 
-    query.push(" ORDER BY start_time ASC");
+    Ok(spans)
+}
 
-    let spans = query.build_query_as().fetch_all(pool).await?;
+#[derive(FromRow)]
+pub struct SessionLlmSpan {
+    pub span_id: Uuid,
+    pub trace_id: Uuid,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub input: Option<Value>,
+    pub output: Option<Value>,
+}
+
+/// LLM calls made in the session, in the order they were made
+pub async fn get_session_llm_spans(
+    pool: &PgPool,
+    project_id: Uuid,
+    session_id: &str,
+) -> Result<Vec<SessionLlmSpan>> {
+    let spans = sqlx::query_as::<_, SessionLlmSpan>(
+        "SELECT
+            spans.span_id,
+            spans.trace_id,
+            spans.start_time,
+            spans.end_time,
+            spans.input,
+            spans.output
+        FROM spans
+        JOIN traces ON spans.trace_id = traces.id
+        WHERE traces.project_id = $1
+        AND traces.session_id = $2
+        AND spans.span_type = 'LLM'
+        ORDER BY spans.start_time ASC",
+    )
+    .bind(project_id)
+    .bind(session_id)
+    .fetch_all(pool)
+    .await?;
 
     Ok(spans)
 }
 
-pub async fn get_span(pool: &PgPool, id: Uuid) -> Result<Span> {
+/// Returns the spans of the trace which match the search, best matches first
+pub async fn search_trace_spans(
+    pool: &PgPool,
+    trace_id: Uuid,
+    search: &str,
+) -> Result<Vec<SpanSearchMatch>> {
+    let matches = sqlx::query_as::<_, SpanSearchMatch>(
+        "SELECT
+            span_id,
+            ts_rank_cd(search_vector, search_query) rank,
+            ts_headline(
+                'english',
+                concat_ws(' ', name, input::text, output::text, attributes::text),
+                search_query,
+                'StartSel=<mark>, StopSel=</mark>, MaxFragments=3, MaxWords=20, MinWords=5'
+            ) highlight
+        FROM spans, websearch_to_tsquery('english', $2) search_query
+        WHERE trace_id = $1 AND search_vector @@ search_query
+        ORDER BY rank DESC",
+    )
+    .bind(trace_id)
+    .bind(search)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(matches)
+}
+
+pub async fn get_span(pool: &PgPool, project_id: &Uuid, id: Uuid) -> Result<Option<Span>> {
     let span = sqlx::query_as::<_, Span>(
         "SELECT
             span_id,
@@ -227,14 +420,401 @@ pub async fn get_span(pool: &PgPool, id: Uuid) -> Result<Span> {
             input,
             output,
             span_type,
+            has_error,
+            status,
+            status_message,
+            resource_attributes,
+            scope_name,
+            scope_version,
             '[]'::jsonb as events,
             '[]'::jsonb as labels
         FROM spans
-        WHERE span_id = $1",
+        WHERE span_id = $1
+        AND trace_id IN (SELECT id FROM traces WHERE project_id = $2)",
     )
     .bind(id)
-    .fetch_one(pool)
+    .bind(project_id)
+    .fetch_optional(pool)
     .await?;
 
     Ok(span)
 }
+
+/// Span in the spans listing, without the full input and output
+#[derive(Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct SpanPreview {
+    pub span_id: Uuid,
+    pub trace_id: Uuid,
+    pub parent_span_id: Option<Uuid>,
+    pub name: String,
+    pub span_type: SpanType,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub latency: f64,
+    pub path: Option<String>,
+    pub model: Option<String>,
+    pub input_preview: Option<String>,
+    pub output_preview: Option<String>,
+    pub has_error: bool,
+    pub status: SpanStatus,
+}
+
+/// Span with its input, output, usage, labels and events, for exports
+#[derive(FromRow)]
+pub struct ExportedSpan {
+    pub span_id: Uuid,
+    pub trace_id: Uuid,
+    pub parent_span_id: Option<Uuid>,
+    pub name: String,
+    pub span_type: SpanType,
+    pub path: Option<String>,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub latency: f64,
+    pub model: Option<String>,
+    pub input: Option<Value>,
+    pub output: Option<Value>,
+    pub input_tokens: Option<i64>,
+    pub output_tokens: Option<i64>,
+    pub total_tokens: Option<i64>,
+    pub cost: Option<f64>,
+    pub has_error: bool,
+    pub status: SpanStatus,
+    pub attributes: Value,
+    pub labels: Value,
+    pub events: Value,
+}
+
+#[derive(FromRow)]
+struct TotalCount {
+    total_count: i64,
+}
+
+fn add_spans_info_expression(
+    query: &mut QueryBuilder<Postgres>,
+    date_range: &Option<DateRange>,
+    project_id: Uuid,
+) -> Result<()> {
+    query.push(format!(
+        "
+    spans_info AS (
+        SELECT
+            spans.span_id,
+            spans.trace_id,
+            spans.parent_span_id,
+            spans.name,
+            spans.span_type,
+            spans.start_time,
+            spans.end_time,
+            EXTRACT(EPOCH FROM (spans.end_time - spans.start_time))::float8 latency,
+            spans.attributes ->> '{SPAN_PATH}' path,
+            COALESCE(
+                spans.attributes ->> '{GEN_AI_RESPONSE_MODEL}',
+                spans.attributes ->> '{GEN_AI_REQUEST_MODEL}'
+            ) model,
+            spans.input_preview,
+            spans.output_preview,
+            spans.input,
+            spans.output,
+            spans.has_error,
+            spans.status,
+            spans.attributes,
+            spans.search_vector
+        FROM spans
+        JOIN traces ON spans.trace_id = traces.id
+        WHERE traces.project_id = "
+    ));
+    query.push_bind(project_id);
+
+    add_date_range_to_query(
+        query,
+        date_range,
+        "spans.start_time",
+        Some("spans.end_time"),
+    )?;
+
+    query.push(")");
+
+    Ok(())
+}
+
+fn add_filters_to_spans_query(
+    query: &mut QueryBuilder<Postgres>,
+    filters: &Option<Vec<FilterExpression>>,
+    search: &Option<String>,
+) {
+    if let Some(filters) = filters {
+        filters.iter().for_each(|filter| {
+            query.push(" AND ");
+            filter.push_condition(query, &push_span_filter);
+        });
+    }
+    if let Some(search) = search {
+        query.push(" AND search_vector @@ ");
+        push_search_query(query, search);
+    }
+}
+
+/// Type of a span column. Events, labels and attributes are compared as text or JSON.
+fn span_filter_value_type(column: &str) -> FilterValueType {
+    match column {
+        "span_id" | "trace_id" | "parent_span_id" => FilterValueType::Uuid,
+        "latency" => FilterValueType::Float,
+        "has_error" => FilterValueType::Bool,
+        "span_type" => FilterValueType::Enum("span_type"),
+        "status" => FilterValueType::Enum("span_status"),
+        _ => FilterValueType::Text,
+    }
+}
+
+pub fn validate_span_filter(filter: &Filter) -> Result<()> {
+    filter.validate_value(span_filter_value_type(&filter.filter_column))
+}
+
+fn push_span_filter(query: &mut QueryBuilder<Postgres>, filter: &Filter) {
+    let column = filter.filter_column.as_str();
+    if let Some(template_name) = column.strip_prefix("event.") {
+        query.push(
+            "span_id IN
+            (SELECT events.span_id
+            FROM events
+            JOIN event_templates ON events.template_id = event_templates.id
+            WHERE event_templates.name = ",
+        );
+        query.push_bind(template_name.to_string()).push(" AND ");
+        filter.push_condition(
+            query,
+            |query| {
+                query.push("events.value");
+            },
+            FilterValueType::Json,
+        );
+        query.push(")");
+        return;
+    }
+    if let Some(label_name) = column.strip_prefix("label.") {
+        query.push(
+            "span_id IN
+            (SELECT labels.span_id
+            FROM labels
+            JOIN label_classes ON labels.class_id = label_classes.id
+            WHERE label_classes.name = ",
+        );
+        query.push_bind(label_name.to_string()).push(" AND ");
+        filter.push_condition(
+            query,
+            |query| {
+                query.push("label_classes.value_map ->> labels.value::int4");
+            },
+            FilterValueType::Text,
+        );
+        query.push(")");
+        return;
+    }
+    if let Some(attribute) = column.strip_prefix("attributes.") {
+        let attribute = attribute.to_string();
+        filter.push_condition(
+            query,
+            |query| {
+                query.push("attributes -> ").push_bind(attribute);
+            },
+            FilterValueType::Json,
+        );
+        return;
+    }
+    filter.push_condition(
+        query,
+        |query| {
+            query.push(column);
+        },
+        span_filter_value_type(column),
+    );
+}
+
+/// Queries spans across the traces of a project, with given limit and offset.
+/// If there is a search, the best matches come first.
+pub async fn get_spans(
+    pool: &PgPool,
+    project_id: Uuid,
+    limit: usize,
+    offset: usize,
+    filters: &Option<Vec<FilterExpression>>,
+    date_range: &Option<DateRange>,
+    search: Option<String>,
+) -> Result<Vec<SpanPreview>> {
+    let mut query = QueryBuilder::<Postgres>::new("WITH ");
+    add_spans_info_expression(&mut query, date_range, project_id)?;
+    query.push(
+        "
+        SELECT
+            span_id,
+            trace_id,
+            parent_span_id,
+            name,
+            span_type,
+            start_time,
+            end_time,
+            latency,
+            path,
+            model,
+            input_preview,
+            output_preview,
+            has_error,
+            status
+        FROM spans_info
+        WHERE 1=1",
+    );
+
+    add_filters_to_spans_query(&mut query, filters, &search);
+
+    query.push(" ORDER BY ");
+    if let Some(search) = &search {
+        query.push("ts_rank_cd(search_vector, ");
+        push_search_query(&mut query, search);
+        query.push(") DESC, ");
+    }
+    query
+        .push("start_time DESC OFFSET ")
+        .push_bind(offset as i64)
+        .push(" LIMIT ")
+        .push_bind(limit as i64);
+
+    let spans = query
+        .build_query_as::<'_, SpanPreview>()
+        .fetch_all(pool)
+        .await?;
+
+    Ok(spans)
+}
+
+/// Numeric span attribute, or NULL if the attribute is missing or not a number
+fn numeric_attribute(attribute: &str) -> String {
+    format!(
+        "CASE WHEN jsonb_typeof(attributes -> '{attribute}') = 'number'
+            THEN (attributes -> '{attribute}')::float8 END"
+    )
+}
+
+/// Same as `get_spans`, but with full spans, in the order they started, after the cursor
+pub async fn get_spans_for_export(
+    pool: &PgPool,
+    project_id: Uuid,
+    limit: usize,
+    after: Option<ExportCursor>,
+    filters: &Option<Vec<FilterExpression>>,
+    date_range: &Option<DateRange>,
+    search: Option<String>,
+) -> Result<Vec<ExportedSpan>> {
+    let mut query = QueryBuilder::<Postgres>::new("WITH ");
+    add_spans_info_expression(&mut query, date_range, project_id)?;
+    query.push(format!(
+        "
+        SELECT
+            span_id,
+            trace_id,
+            parent_span_id,
+            name,
+            span_type,
+            path,
+            start_time,
+            end_time,
+            latency,
+            model,
+            input,
+            output,
+            ({})::int8 input_tokens,
+            ({})::int8 output_tokens,
+            ({})::int8 total_tokens,
+            {} cost,
+            has_error,
+            status,
+            attributes,
+            COALESCE((
+                SELECT jsonb_agg(jsonb_build_object(
+                    'name', label_classes.name,
+                    'value', label_classes.value_map ->> labels.value::int4,
+                    'source', labels.label_source,
+                    'reasoning', labels.reasoning
+                ))
+                FROM labels
+                JOIN label_classes ON labels.class_id = label_classes.id
+                WHERE labels.span_id = spans_info.span_id
+            ), '[]'::jsonb) labels,
+            COALESCE((
+                SELECT jsonb_agg(jsonb_build_object(
+                    'name', event_templates.name,
+                    'timestamp', events.timestamp,
+                    'value', events.value,
+                    'source', events.source
+                ))
+                FROM events
+                JOIN event_templates ON events.template_id = event_templates.id
+                WHERE events.span_id = spans_info.span_id
+            ), '[]'::jsonb) events
+        FROM spans_info
+        WHERE 1=1",
+        numeric_attribute(GEN_AI_INPUT_TOKENS),
+        numeric_attribute(GEN_AI_OUTPUT_TOKENS),
+        numeric_attribute(GEN_AI_TOTAL_TOKENS),
+        numeric_attribute(GEN_AI_TOTAL_COST),
+    ));
+
+    add_filters_to_spans_query(&mut query, filters, &search);
+    if let Some(after) = after {
+        query
+            .push(" AND (start_time, span_id) > (")
+            .push_bind(after.start_time)
+            .push(", ")
+            .push_bind(after.id)
+            .push(")");
+    }
+
+    query
+        .push(" ORDER BY start_time ASC, span_id ASC LIMIT ")
+        .push_bind(limit as i64);
+
+    let spans = query
+        .build_query_as::<'_, ExportedSpan>()
+        .fetch_all(pool)
+        .await?;
+
+    Ok(spans)
+}
+
+/// Returns the total count of spans matching the given filters
+pub async fn count_spans(
+    pool: &PgPool,
+    project_id: Uuid,
+    filters: &Option<Vec<FilterExpression>>,
+    date_range: &Option<DateRange>,
+    search: Option<String>,
+) -> Result<i64> {
+    let mut query = QueryBuilder::<Postgres>::new("WITH ");
+    add_spans_info_expression(&mut query, date_range, project_id)?;
+    query.push(" SELECT COUNT(*) as total_count FROM spans_info WHERE 1=1");
+
+    add_filters_to_spans_query(&mut query, filters, &search);
+
+    let count = query
+        .build_query_as::<'_, TotalCount>()
+        .fetch_one(pool)
+        .await?
+        .total_count;
+
+    Ok(count)
+}
+
+pub async fn count_all_spans_in_project(pool: &PgPool, project_id: Uuid) -> Result<i64> {
+    let count = sqlx::query_as::<_, TotalCount>(
+        "SELECT COUNT(*) as total_count
+        FROM spans
+        JOIN traces ON spans.trace_id = traces.id
+        WHERE traces.project_id = $1",
+    )
+    .bind(project_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(count.total_count)
+}
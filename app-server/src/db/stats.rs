@@ -40,24 +40,44 @@ pub async fn add_spans_and_events_to_project_usage_stats(
     Ok(())
 }
 
-#[derive(Debug, FromRow, Serialize)]
+#[derive(Debug, Clone, FromRow, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct StorageStats {
     pub storage_mib: Option<f64>,
+    pub datasets_storage_mib: Option<f64>,
+    pub traces_storage_mib: Option<f64>,
 }
 
+/// Traces are included, so that the effect of retention periods shows up in the stats.
+/// This scans all spans of the workspace, so the result should be cached.
 pub async fn get_workspace_storage_stats(
     pool: &PgPool,
     workspace_id: &Uuid,
 ) -> anyhow::Result<StorageStats> {
     let storage_stats = sqlx::query_as::<_, StorageStats>(
-        "SELECT (sum(pg_column_size(data)) + sum(pg_column_size(target)))::float8 / 1024 / 1024 as storage_mib
-        FROM dataset_datapoints 
-        WHERE dataset_id in (
-            SELECT id FROM datasets WHERE project_id in (
-                SELECT id FROM projects where workspace_id = $1
+        "WITH datasets_storage AS (
+            SELECT (sum(pg_column_size(data)) + sum(pg_column_size(target)))::float8 / 1024 / 1024 as storage_mib
+            FROM dataset_datapoints
+            WHERE dataset_id in (
+                SELECT id FROM datasets WHERE project_id in (
+                    SELECT id FROM projects where workspace_id = $1
+                )
+            )
+        ),
+        traces_storage AS (
+            SELECT sum(pg_column_size(spans.*))::float8 / 1024 / 1024 as storage_mib
+            FROM spans
+            WHERE trace_id in (
+                SELECT id FROM traces WHERE project_id in (
+                    SELECT id FROM projects where workspace_id = $1
+                )
             )
-        )",
+        )
+        SELECT
+            COALESCE(datasets_storage.storage_mib, 0) + COALESCE(traces_storage.storage_mib, 0) as storage_mib,
+            datasets_storage.storage_mib as datasets_storage_mib,
+            traces_storage.storage_mib as traces_storage_mib
+        FROM datasets_storage, traces_storage",
     )
     .bind(workspace_id)
     .fetch_one(pool)
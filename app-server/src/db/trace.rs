@@ -2,16 +2,19 @@ use anyhow::Result;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use sqlx::{FromRow, PgPool, Postgres, QueryBuilder};
+use sqlx::{FromRow, PgExecutor, PgPool, Postgres, QueryBuilder};
 use uuid::Uuid;
 
 use crate::{
-    db::{modifiers::DateRange, spans::SpanType},
+    db::{
+        modifiers::DateRange,
+        spans::{push_search_query, SpanType},
+    },
     traces::attributes::TraceAttributes,
 };
 
 use super::{
-    modifiers::{Filter, FilterOperator},
+    modifiers::{ExportCursor, Filter, FilterExpression, FilterValueType},
     utils::add_date_range_to_query,
 };
 
@@ -40,27 +43,51 @@ pub enum TraceType {
 #[derive(Serialize, sqlx::FromRow, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Trace {
-    id: Uuid,
+    pub id: Uuid,
     #[serde(default)]
-    start_time: Option<DateTime<Utc>>,
+    pub start_time: Option<DateTime<Utc>>,
     #[serde(default)]
-    end_time: Option<DateTime<Utc>>,
+    pub end_time: Option<DateTime<Utc>>,
     // Laminar trace format's version
-    version: String,
+    pub version: String,
     // Laminar customers' release version
-    release: Option<String>,
+    pub release: Option<String>,
     // User id of Laminar customers' user
-    user_id: Option<String>,
-    session_id: Option<String>,
-    metadata: Option<Value>,
-    input_token_count: i64,
-    output_token_count: i64,
-    total_token_count: i64,
-    input_cost: f64,
-    output_cost: f64,
-    cost: f64,
-    success: bool,
-    project_id: Uuid,
+    pub user_id: Option<String>,
+    pub session_id: Option<String>,
+    pub metadata: Option<Value>,
+    pub input_token_count: i64,
+    pub output_token_count: i64,
+    pub total_token_count: i64,
+    pub input_cost: f64,
+    pub output_cost: f64,
+    pub cost: f64,
+    pub success: bool,
+    pub has_error: bool,
+    pub project_id: Uuid,
+}
+
+/// Trace with the input and output of its top span, and the labels and events of all its spans
+#[derive(sqlx::FromRow)]
+pub struct ExportedTrace {
+    pub id: Uuid,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub latency: f64,
+    pub session_id: Option<String>,
+    pub user_id: Option<String>,
+    pub metadata: Option<Value>,
+    pub input: Option<Value>,
+    pub output: Option<Value>,
+    pub input_token_count: i64,
+    pub output_token_count: i64,
+    pub total_token_count: i64,
+    pub input_cost: f64,
+    pub output_cost: f64,
+    pub cost: f64,
+    pub has_error: bool,
+    pub labels: Value,
+    pub events: Value,
 }
 
 #[derive(Serialize, sqlx::FromRow)]
@@ -84,6 +111,7 @@ pub struct TraceWithTopSpan {
     output_cost: f64,
     cost: f64,
     success: bool,
+    has_error: bool,
     project_id: Uuid,
 
     top_span_input_preview: Option<String>,
@@ -98,8 +126,8 @@ struct TotalCount {
     total_count: i64,
 }
 
-pub async fn update_trace_attributes(
-    pool: &PgPool,
+pub async fn update_trace_attributes<'e>(
+    executor: impl PgExecutor<'e>,
     project_id: &Uuid,
     attributes: &TraceAttributes,
 ) -> Result<()> {
@@ -120,7 +148,8 @@ pub async fn update_trace_attributes(
             version,
             session_id,
             user_id,
-            trace_type
+            trace_type,
+            has_error
         )
         VALUES (
             $1,
@@ -137,7 +166,8 @@ pub async fn update_trace_attributes(
             $12,
             $13,
             $14,
-            COALESCE($15, 'DEFAULT'::trace_type)
+            COALESCE($15, 'DEFAULT'::trace_type),
+            COALESCE($16, false)
         )
         ON CONFLICT(id) DO
         UPDATE
@@ -153,7 +183,8 @@ pub async fn update_trace_attributes(
             end_time = CASE WHEN traces.end_time IS NULL OR traces.end_time < $11 THEN $11 ELSE traces.end_time END,
             session_id = CASE WHEN traces.session_id IS NULL THEN $13 ELSE traces.session_id END,
             user_id = CASE WHEN traces.user_id IS NULL THEN $14 ELSE traces.user_id END,
-            trace_type = CASE WHEN $15 IS NULL THEN traces.trace_type ELSE COALESCE($15, 'DEFAULT'::trace_type) END
+            trace_type = CASE WHEN $15 IS NULL THEN traces.trace_type ELSE COALESCE($15, 'DEFAULT'::trace_type) END,
+            has_error = traces.has_error OR COALESCE($16, false)
         "
     )
     .bind(attributes.id)
@@ -171,7 +202,8 @@ pub async fn update_trace_attributes(
     .bind(&attributes.session_id)
     .bind(&attributes.user_id)
     .bind(&attributes.trace_type)
-    .execute(pool)
+    .bind(attributes.has_error)
+    .execute(executor)
     .await?;
     Ok(())
 }
@@ -201,6 +233,7 @@ fn add_traces_info_expression(
             output_cost,
             cost,
             success,
+            has_error,
             trace_type,
             top_level_spans.input_preview top_span_input_preview,
             top_level_spans.output_preview top_span_output_preview,
@@ -250,105 +283,113 @@ fn add_traces_info_expression(
     Ok(())
 }
 
+/// Joins the traces with the best search rank of their matching spans, as `search_rank`
 fn add_text_join(
     query: &mut QueryBuilder<Postgres>,
     date_range: &Option<DateRange>,
-    text_search_filter: &String,
+    text_search_filter: &str,
 ) -> Result<()> {
     query.push(
         "
         JOIN (
-            SELECT DISTINCT trace_id
-            FROM spans 
-            WHERE ",
+            SELECT trace_id, MAX(ts_rank_cd(search_vector, search_query)) search_rank
+            FROM spans, ",
     );
-    query
-        .push("(input::TEXT ILIKE ")
-        .push_bind(format!("%{text_search_filter}%"))
-        .push(" OR output::TEXT ILIKE ")
-        .push_bind(format!("%{text_search_filter}%"))
-        .push(" OR name::TEXT ILIKE ")
-        .push_bind(format!("%{text_search_filter}%"))
-        .push(" OR attributes::TEXT ILIKE ")
-        .push_bind(format!("%{text_search_filter}%"))
-        .push(")");
+    push_search_query(query, text_search_filter);
+    query.push(" search_query WHERE search_vector @@ search_query");
 
     add_date_range_to_query(query, date_range, "start_time", Some("end_time"))?;
 
-    query.push(") matching_spans ON traces_info.id = matching_spans.trace_id");
+    query.push(
+        " GROUP BY trace_id
+        ) matching_spans ON traces_info.id = matching_spans.trace_id",
+    );
     Ok(())
 }
 
-fn add_filters_to_traces_query(query: &mut QueryBuilder<Postgres>, filters: &Option<Vec<Filter>>) {
+fn add_filters_to_traces_query(
+    query: &mut QueryBuilder<Postgres>,
+    filters: &Option<Vec<FilterExpression>>,
+) {
     if let Some(filters) = filters {
         filters.iter().for_each(|filter| {
-            let filter_value_str = match &filter.filter_value {
-                Value::String(s) => s.clone(),
-                v => v.to_string(),
-            };
-            if !filter.validate_column() {
-                log::warn!("Invalid column name: {}", filter.filter_column);
-                return;
-            }
-            if filter.filter_column.starts_with("event.") {
-                let template_name = filter.filter_column.strip_prefix("event.").unwrap();
-                filter_by_event_value(
-                    query,
-                    template_name.to_string(),
-                    filter.filter_operator.clone(),
-                    filter.filter_value.clone(),
-                );
-                return;
-            }
-            if filter.filter_column.starts_with("label.") {
-                let label_name = filter.filter_column.strip_prefix("label.").unwrap();
-                filter_by_span_label_value(
-                    query,
-                    label_name.to_string(),
-                    filter.filter_operator.clone(),
-                    filter.filter_value.clone(),
-                );
-                return;
-            }
             query.push(" AND ");
-            query.push(&filter.filter_column);
-            query.push(filter.filter_operator.to_sql_operator());
-            if ["id"]
-                .iter()
-                .any(|col| col == &filter.filter_column.as_str())
-            {
-                query.push_bind(Uuid::parse_str(&filter_value_str).unwrap_or_default());
-            } else if [
-                "latency",
-                "cost",
-                "total_token_count",
-                "input_token_count",
-                "output_token_count",
-                "input_cost",
-                "output_cost",
-            ]
-            .iter()
-            .any(|col| col == &filter.filter_column.as_str())
-            {
-                query.push_bind(filter_value_str.parse::<f64>().unwrap_or_default());
-            } else if filter.filter_column == "trace_type" {
-                query.push_bind(filter_value_str);
-                query.push("::trace_type");
-            } else {
-                query.push_bind(filter_value_str);
-            }
+            filter.push_condition(query, &push_trace_filter);
         });
     }
 }
 
+/// Type of a trace column. Columns of spans, events and labels, which traces are filtered by,
+/// are compared as text or JSON.
+fn trace_filter_value_type(column: &str) -> FilterValueType {
+    match column {
+        "id" => FilterValueType::Uuid,
+        "latency" | "cost" | "total_token_count" | "input_token_count" | "output_token_count"
+        | "input_cost" | "output_cost" => FilterValueType::Float,
+        "has_error" => FilterValueType::Bool,
+        "trace_type" => FilterValueType::Enum("trace_type"),
+        _ => FilterValueType::Text,
+    }
+}
+
+pub fn validate_trace_filter(filter: &Filter) -> Result<()> {
+    filter.validate_value(trace_filter_value_type(&filter.filter_column))
+}
+
+fn push_trace_filter(query: &mut QueryBuilder<Postgres>, filter: &Filter) {
+    let column = filter.filter_column.as_str();
+    if let Some(template_name) = column.strip_prefix("event.") {
+        filter_by_event_value(query, template_name.to_string(), filter);
+        return;
+    }
+    if let Some(label_name) = column.strip_prefix("label.") {
+        filter_by_span_label_value(query, label_name.to_string(), filter);
+        return;
+    }
+    if let Some(attribute) = column.strip_prefix("resource.") {
+        filter_by_span_resource_attribute(query, attribute.to_string(), filter);
+        return;
+    }
+    if let Some(attribute) = column.strip_prefix("attributes.") {
+        filter_by_span_attribute(query, attribute.to_string(), filter);
+        return;
+    }
+    if let Some(path) = column.strip_prefix("metadata.") {
+        // Metadata can be nested, so each part of the path is a key in the next object
+        let path = path.split('.').map(String::from).collect::<Vec<_>>();
+        filter.push_condition(
+            query,
+            |query| {
+                query.push("metadata #> ").push_bind(path);
+            },
+            FilterValueType::Json,
+        );
+        return;
+    }
+    if let Some(scope_column) = match column {
+        "scope.name" => Some("scope_name"),
+        "scope.version" => Some("scope_version"),
+        _ => None,
+    } {
+        filter_by_span_scope(query, scope_column, filter);
+        return;
+    }
+    filter.push_condition(
+        query,
+        |query| {
+            query.push(column);
+        },
+        trace_filter_value_type(column),
+    );
+}
+
 fn filter_by_event_value(
     query: &mut QueryBuilder<Postgres>,
     template_name: String,
-    filter_operator: FilterOperator,
-    event_value: Value,
+    filter: &Filter,
 ) {
     query.push(
-        " AND id IN
+        "id IN
         (SELECT trace_id
         FROM spans
         JOIN events ON spans.span_id = events.span_id
@@ -357,20 +398,24 @@ fn filter_by_event_value(
     ",
     );
     query.push_bind(template_name);
-    query.push(" AND events.value ");
-    query.push(filter_operator.to_sql_operator());
-    query.push_bind(event_value);
-    query.push("::jsonb)");
+    query.push(" AND ");
+    filter.push_condition(
+        query,
+        |query| {
+            query.push("events.value");
+        },
+        FilterValueType::Json,
+    );
+    query.push(")");
 }
 
 fn filter_by_span_label_value(
     query: &mut QueryBuilder<Postgres>,
     label_name: String,
-    filter_operator: FilterOperator,
-    label_value: Value,
+    filter: &Filter,
 ) {
     query.push(
-        " AND id IN
+        "id IN
         (SELECT trace_id
         FROM spans
         JOIN labels ON spans.span_id = labels.span_id
@@ -378,10 +423,77 @@ fn filter_by_span_label_value(
         WHERE label_classes.name = ",
     );
     query.push_bind(label_name);
-    query.push(" AND label_classes.value_map ->> labels.value::int4 ");
-    query.push(filter_operator.to_sql_operator());
-    query.push_bind(label_value);
-    query.push("::text)");
+    query.push(" AND ");
+    filter.push_condition(
+        query,
+        |query| {
+            query.push("label_classes.value_map ->> labels.value::int4");
+        },
+        FilterValueType::Text,
+    );
+    query.push(")");
+}
+
+fn filter_by_span_resource_attribute(
+    query: &mut QueryBuilder<Postgres>,
+    attribute: String,
+    filter: &Filter,
+) {
+    query.push(
+        "id IN
+        (SELECT trace_id
+        FROM spans
+        WHERE ",
+    );
+    filter.push_condition(
+        query,
+        |query| {
+            query.push("resource_attributes ->> ").push_bind(attribute);
+        },
+        FilterValueType::Text,
+    );
+    query.push(")");
+}
+
+/// Span attributes are stored flat, with dotted keys such as `gen_ai.request.model`,
+/// so the whole key is looked up at once
+fn filter_by_span_attribute(
+    query: &mut QueryBuilder<Postgres>,
+    attribute: String,
+    filter: &Filter,
+) {
+    query.push(
+        "id IN
+        (SELECT trace_id
+        FROM spans
+        WHERE ",
+    );
+    filter.push_condition(
+        query,
+        |query| {
+            query.push("attributes -> ").push_bind(attribute);
+        },
+        FilterValueType::Json,
+    );
+    query.push(")");
+}
+
+/// `scope_column` must be one of the known scope columns, it is not escaped
+fn filter_by_span_scope(query: &mut QueryBuilder<Postgres>, scope_column: &str, filter: &Filter) {
+    query.push(
+        "id IN
+        (SELECT trace_id
+        FROM spans
+        WHERE ",
+    );
+    filter.push_condition(
+        query,
+        |query| {
+            query.push(scope_column);
+        },
+        FilterValueType::Text,
+    );
+    query.push(")");
 }
 
 /// Queries traces for a project which match the given filters, with given limit and offset
@@ -390,7 +502,7 @@ pub async fn get_traces(
     project_id: Uuid,
     limit: usize,
     offset: usize,
-    filters: &Option<Vec<Filter>>,
+    filters: &Option<Vec<FilterExpression>>,
     date_range: &Option<DateRange>,
     text_search_filter: Option<String>,
 ) -> Result<Vec<TraceWithTopSpan>> {
@@ -416,6 +528,7 @@ pub async fn get_traces(
             output_cost,
             cost,
             success,
+            has_error,
             top_span_input_preview,
             top_span_output_preview,
             top_span_name,
@@ -424,6 +537,7 @@ pub async fn get_traces(
             status
         FROM traces_info ",
     );
+    let is_search = text_search_filter.is_some();
     if let Some(search) = text_search_filter {
         add_text_join(&mut query, date_range, &search)?;
     }
@@ -432,8 +546,12 @@ pub async fn get_traces(
 
     add_filters_to_traces_query(&mut query, &filters);
 
+    query.push(" ORDER BY ");
+    if is_search {
+        query.push("search_rank DESC, ");
+    }
     query
-        .push(" ORDER BY start_time DESC OFFSET ")
+        .push("start_time DESC OFFSET ")
         .push_bind(offset as i64)
         .push(" LIMIT ")
         .push_bind(limit as i64);
@@ -446,11 +564,118 @@ pub async fn get_traces(
     Ok(traces)
 }
 
+/// Same as `get_traces`, but with the data of the exports, in the order the traces started,
+/// after the cursor
+pub async fn get_traces_for_export(
+    pool: &PgPool,
+    project_id: Uuid,
+    limit: usize,
+    after: Option<ExportCursor>,
+    filters: &Option<Vec<FilterExpression>>,
+    date_range: &Option<DateRange>,
+    text_search_filter: Option<String>,
+) -> Result<Vec<ExportedTrace>> {
+    let mut query = QueryBuilder::<Postgres>::new("WITH ");
+    add_traces_info_expression(&mut query, date_range, project_id)?;
+
+    query.push(
+        "
+        SELECT
+            id,
+            start_time,
+            end_time,
+            latency::float8 latency,
+            session_id,
+            user_id,
+            metadata,
+            top_span.input,
+            top_span.output,
+            input_token_count,
+            output_token_count,
+            total_token_count,
+            input_cost,
+            output_cost,
+            cost,
+            has_error,",
+    );
+    push_labels_and_events_columns(&mut query, "traces_info.id");
+    query.push(
+        "
+        FROM traces_info
+        LEFT JOIN LATERAL (
+            SELECT input, output
+            FROM spans
+            WHERE spans.trace_id = traces_info.id AND spans.parent_span_id IS NULL
+            ORDER BY spans.start_time
+            LIMIT 1
+        ) top_span ON TRUE ",
+    );
+    if let Some(search) = text_search_filter {
+        add_text_join(&mut query, date_range, &search)?;
+    }
+    query.push(" WHERE project_id = ");
+    query.push_bind(project_id);
+
+    add_filters_to_traces_query(&mut query, &filters);
+    if let Some(after) = after {
+        query
+            .push(" AND (start_time, id) > (")
+            .push_bind(after.start_time)
+            .push(", ")
+            .push_bind(after.id)
+            .push(")");
+    }
+
+    query
+        .push(" ORDER BY start_time ASC, id ASC LIMIT ")
+        .push_bind(limit as i64);
+
+    let traces = query
+        .build_query_as::<'_, ExportedTrace>()
+        .fetch_all(pool)
+        .await?;
+
+    Ok(traces)
+}
+
+/// Pushes the `labels` and `events` columns, with the labels and events of all spans of the trace
+fn push_labels_and_events_columns(query: &mut QueryBuilder<Postgres>, trace_id_column: &str) {
+    query.push(format!(
+        "
+            COALESCE((
+                SELECT jsonb_agg(jsonb_build_object(
+                    'spanId', labels.span_id,
+                    'name', label_classes.name,
+                    'value', label_classes.value_map ->> labels.value::int4,
+                    'source', labels.label_source,
+                    'reasoning', labels.reasoning
+                ))
+                FROM labels
+                JOIN spans ON labels.span_id = spans.span_id
+                JOIN label_classes ON labels.class_id = label_classes.id
+                WHERE spans.trace_id = {trace_id_column}
+            ), '[]'::jsonb) labels,
+            COALESCE((
+                SELECT jsonb_agg(jsonb_build_object(
+                    'spanId', events.span_id,
+                    'name', event_templates.name,
+                    'timestamp', events.timestamp,
+                    'value', events.value,
+                    'source', events.source
+                ) ORDER BY events.timestamp)
+                FROM events
+                JOIN spans ON events.span_id = spans.span_id
+                JOIN event_templates ON events.template_id = event_templates.id
+                WHERE spans.trace_id = {trace_id_column}
+            ), '[]'::jsonb) events"
+    ));
+}
+
 /// Returns the total count of traces matching the given filters
 pub async fn count_traces(
     pool: &PgPool,
     project_id: Uuid,
-    filters: &Option<Vec<Filter>>,
+    filters: &Option<Vec<FilterExpression>>,
     date_range: &Option<DateRange>,
     text_search_filter: Option<String>,
 ) -> Result<i64> {
@@ -496,7 +721,7 @@ pub async fn count_all_traces_in_project(pool: &PgPool, project_id: Uuid) -> Res
     Ok(count.total_count)
 }
 
-pub async fn get_single_trace(pool: &PgPool, id: Uuid) -> Result<Trace> {
+pub async fn get_single_trace(pool: &PgPool, project_id: Uuid, id: Uuid) -> Result<Option<Trace>> {
     let trace = sqlx::query_as::<_, Trace>(
         "SELECT
             id,
@@ -514,18 +739,94 @@ pub async fn get_single_trace(pool: &PgPool, id: Uuid) -> Result<Trace> {
             input_cost,
             output_cost,
             cost,
-            success
+            success,
+            has_error
         FROM traces
-        WHERE id = $1
+        WHERE id = $1 AND project_id = $2
         AND start_time IS NOT NULL AND end_time IS NOT NULL",
     )
     .bind(id)
-    .fetch_one(pool)
+    .bind(project_id)
+    .fetch_optional(pool)
     .await?;
 
     Ok(trace)
 }
 
+/// Trace of a session, with the input and output of its top span,
+/// and the labels and events of all its spans
+#[derive(Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionTrace {
+    pub id: Uuid,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub latency: f64,
+    pub user_id: Option<String>,
+    pub metadata: Option<Value>,
+    pub top_span_id: Option<Uuid>,
+    pub top_span_name: Option<String>,
+    pub input: Option<Value>,
+    pub output: Option<Value>,
+    pub total_token_count: i64,
+    pub cost: f64,
+    pub has_error: bool,
+    pub labels: Value,
+    pub events: Value,
+}
+
+/// Traces of the session, in the order they started
+pub async fn get_session_traces(
+    pool: &PgPool,
+    project_id: Uuid,
+    session_id: &str,
+) -> Result<Vec<SessionTrace>> {
+    let mut query = QueryBuilder::<Postgres>::new(
+        "SELECT
+            traces.id,
+            traces.start_time,
+            traces.end_time,
+            EXTRACT(EPOCH FROM (traces.end_time - traces.start_time))::float8 latency,
+            traces.user_id,
+            traces.metadata,
+            top_span.span_id top_span_id,
+            top_span.name top_span_name,
+            top_span.input,
+            top_span.output,
+            traces.total_token_count,
+            traces.cost,
+            traces.has_error,",
+    );
+    push_labels_and_events_columns(&mut query, "traces.id");
+    query
+        .push(
+            "
+        FROM traces
+        LEFT JOIN LATERAL (
+            SELECT span_id, name, input, output
+            FROM spans
+            WHERE spans.trace_id = traces.id AND spans.parent_span_id IS NULL
+            ORDER BY spans.start_time
+            LIMIT 1
+        ) top_span ON TRUE
+        WHERE traces.project_id = ",
+        )
+        .push_bind(project_id)
+        .push(" AND traces.session_id = ")
+        .push_bind(session_id)
+        .push(
+            " AND traces.start_time IS NOT NULL AND traces.end_time IS NOT NULL
+        ORDER BY traces.start_time ASC",
+        );
+
+    let traces = query
+        .build_query_as::<'_, SessionTrace>()
+        .fetch_all(pool)
+        .await?;
+
+    Ok(traces)
+}
+
 #[derive(Serialize, FromRow)]
 #[serde(rename_all = "camelCase")]
 pub struct Session {
@@ -547,7 +848,7 @@ pub async fn get_sessions(
     project_id: Uuid,
     limit: usize,
     offset: usize,
-    filters: &Option<Vec<Filter>>,
+    filters: &Option<Vec<FilterExpression>>,
     date_range: &Option<DateRange>,
 ) -> Result<Vec<Session>> {
     let mut query = sqlx::QueryBuilder::new(
@@ -587,7 +888,7 @@ pub async fn get_sessions(
 pub async fn count_sessions(
     pool: &PgPool,
     project_id: Uuid,
-    filters: &Option<Vec<Filter>>,
+    filters: &Option<Vec<FilterExpression>>,
     date_range: &Option<DateRange>,
 ) -> Result<i64> {
     let mut query = sqlx::QueryBuilder::new(
@@ -624,3 +925,30 @@ pub async fn count_all_sessions_in_project(pool: &PgPool, project_id: Uuid) -> R
 
     Ok(count.total_count)
 }
+
+/// Deletes up to `limit` traces of the project which started before `cutoff`, with their spans.
+/// Returns the number of deleted traces.
+pub async fn delete_traces_before(
+    pool: &PgPool,
+    project_id: &Uuid,
+    cutoff: DateTime<Utc>,
+    limit: i64,
+) -> Result<u64> {
+    let deleted = sqlx::query(
+        "DELETE FROM traces
+        WHERE id IN (
+            SELECT id
+            FROM traces
+            WHERE project_id = $1 AND COALESCE(start_time, created_at) < $2
+            LIMIT $3
+        )",
+    )
+    .bind(project_id)
+    .bind(cutoff)
+    .bind(limit)
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    Ok(deleted)
+}
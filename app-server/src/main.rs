@@ -8,20 +8,34 @@ use actix_web_httpauth::middleware::HttpAuthentication;
 use aws_config::BehaviorVersion;
 use code_executor::{code_executor_grpc::code_executor_client::CodeExecutorClient, CodeExecutor};
 use dashmap::DashMap;
-use db::{pipelines::PipelineVersion, project_api_keys::ProjectApiKey, user::User};
+use db::{
+    pipelines::PipelineVersion, project_api_keys::ProjectApiKey, sampling_rules::SamplingRules,
+    stats::StorageStats, user::User,
+};
 use features::{is_feature_enabled, Feature};
+use mq::{rabbit::RabbitMQ, tokio_mpsc::TokioMpscQueue, MessageQueue};
 use names::NameGenerator;
-use opentelemetry::opentelemetry::proto::collector::trace::v1::trace_service_server::TraceServiceServer;
+use opentelemetry::opentelemetry::proto::collector::{
+    logs::v1::logs_service_server::LogsServiceServer,
+    trace::v1::trace_service_server::TraceServiceServer,
+};
 use projects::Project;
 use runtime::{create_general_purpose_runtime, wait_stop_signal};
 use storage::{mock::MockStorage, Storage};
 use tonic::transport::Server;
 use traces::{
-    consumer::process_queue_spans, grpc_service::ProcessTracesService,
-    limits::WorkspaceLimitsExceeded, OBSERVATIONS_EXCHANGE, OBSERVATIONS_QUEUE,
+    consumer::process_queue_spans,
+    dead_letter::{declare_dead_letter_topology, store_dead_letters},
+    grpc_service::{ProcessLogsService, ProcessTracesService},
+    limits::WorkspaceLimitsExceeded,
+    rate_limit::RateLimiter,
+    redaction::RedactionRules,
+    sampling::TailSampler,
+    OBSERVATIONS_EXCHANGE, OBSERVATIONS_QUEUE,
 };
 
 use cache::{cache::CacheTrait, Cache};
+use ch::metrics::CumulativeToDelta;
 use chunk::{
     character_split::CharacterSplitChunker,
     runner::{Chunker, ChunkerRunner, ChunkerType},
@@ -45,6 +59,7 @@ use std::{
     io::{self, Error},
     sync::Arc,
     thread::{self, JoinHandle},
+    time::Duration,
 };
 use tokio::sync::mpsc;
 use uuid::Uuid;
@@ -61,6 +76,7 @@ mod engine;
 mod evaluations;
 mod features;
 mod language_model;
+mod mq;
 mod names;
 mod opentelemetry;
 mod pipeline;
@@ -73,6 +89,13 @@ mod storage;
 mod traces;
 
 const DEFAULT_CACHE_SIZE: u64 = 100; // entries
+/// Redaction and sampling rules are read on every ingested span, and must eventually apply
+/// on all instances after they are changed on one of them
+const PROJECT_RULES_CACHE_TTL: Duration = Duration::from_secs(60);
+/// Span listeners started by each HTTP worker
+const SPAN_LISTENERS_PER_WORKER: usize = 8;
+/// Storage stats are computed by scanning all spans of the workspace
+const STORAGE_STATS_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
 
 fn tonic_error_to_io_error(err: tonic::transport::Error) -> io::Error {
     io::Error::new(io::ErrorKind::Other, err)
@@ -127,8 +150,31 @@ fn main() -> anyhow::Result<()> {
     let llm_costs_cache: Arc<MokaCache<String, LLMPriceEntry>> =
         Arc::new(MokaCache::new(DEFAULT_CACHE_SIZE));
     caches.insert(TypeId::of::<LLMPriceEntry>(), llm_costs_cache);
+    let redaction_rules_cache: Arc<MokaCache<String, RedactionRules>> = Arc::new(
+        MokaCache::builder()
+            .max_capacity(DEFAULT_CACHE_SIZE)
+            .time_to_live(PROJECT_RULES_CACHE_TTL)
+            .build(),
+    );
+    caches.insert(TypeId::of::<RedactionRules>(), redaction_rules_cache);
+    let sampling_rules_cache: Arc<MokaCache<String, SamplingRules>> = Arc::new(
+        MokaCache::builder()
+            .max_capacity(DEFAULT_CACHE_SIZE)
+            .time_to_live(PROJECT_RULES_CACHE_TTL)
+            .build(),
+    );
+    caches.insert(TypeId::of::<SamplingRules>(), sampling_rules_cache);
+    let storage_stats_cache: Arc<MokaCache<String, StorageStats>> = Arc::new(
+        MokaCache::builder()
+            .max_capacity(DEFAULT_CACHE_SIZE)
+            .time_to_live(STORAGE_STATS_CACHE_TTL)
+            .build(),
+    );
+    caches.insert(TypeId::of::<StorageStats>(), storage_stats_cache);
 
     let cache = Arc::new(Cache::new(caches));
+    // Shared by the HTTP and gRPC trace endpoints
+    let rate_limiter = Arc::new(RateLimiter::new());
 
     let db_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
 
@@ -166,7 +212,8 @@ fn main() -> anyhow::Result<()> {
         let clickhouse_user = env::var("CLICKHOUSE_USER").expect("CLICKHOUSE_USER must be set");
         let clickhouse_password = env::var("CLICKHOUSE_PASSWORD");
         // https://clickhouse.com/docs/en/cloud/bestpractices/asynchronous-inserts -> Create client which will wait for async inserts
-        // For now, we're not waiting for inserts to finish, but later need to add queue and batch on client-side
+        // By default, we're not waiting for inserts to finish. Spans are batched on client-side
+        // in traces::batch_writer, which waits for its inserts before acking the deliveries.
         let mut client = clickhouse::Client::default()
             .with_url(clickhouse_url)
             .with_user(clickhouse_user)
@@ -215,10 +262,20 @@ fn main() -> anyhow::Result<()> {
                 )
                 .await
                 .unwrap();
+
+            declare_dead_letter_topology(&channel).await.unwrap();
             rabbitmq_connection = Some(connection);
         }
     });
-    let rabbitmq_connection_grpc = rabbitmq_connection.clone();
+
+    let queue: Arc<dyn MessageQueue> = if let Some(connection) = rabbitmq_connection.clone() {
+        Arc::new(runtime_handle.block_on(RabbitMQ::new(connection)).unwrap())
+    } else {
+        let queue = TokioMpscQueue::new();
+        queue.declare_queue(OBSERVATIONS_QUEUE, OBSERVATIONS_EXCHANGE);
+        Arc::new(queue)
+    };
+    let queue_grpc = queue.clone();
 
     let mut aws_sdk_config = None;
     runtime_handle.block_on(async {
@@ -244,9 +301,17 @@ fn main() -> anyhow::Result<()> {
     };
     let storage_grpc = storage.clone();
 
+    runtime_handle.spawn(projects::retention::run_retention_job(
+        db.clone(),
+        clickhouse.clone(),
+    ));
+
     let runtime_handle_for_http = runtime_handle.clone();
     let db_for_http = db.clone();
     let cache_for_http = cache.clone();
+    let rate_limiter_for_http = rate_limiter.clone();
+    // Metrics are only exported over HTTP
+    let cumulative_to_delta = Arc::new(CumulativeToDelta::new());
     let http_server_handle = thread::Builder::new()
         .name("http".to_string())
         .spawn(move || {
@@ -330,6 +395,15 @@ fn main() -> anyhow::Result<()> {
                     Arc::new(language_model::LanguageModelRunner::new(language_models));
 
                 let name_generator = Arc::new(NameGenerator::new());
+                // The default of actix, set explicitly to know the number of span listeners
+                let http_workers = std::thread::available_parallelism().map_or(2, |n| n.get());
+                // Shared by all span listeners, so that spans of a trace are held in one place
+                let tail_sampler =
+                    Arc::new(TailSampler::new(http_workers * SPAN_LISTENERS_PER_WORKER));
+                tokio::spawn(tail_sampler.clone().release_expired_traces());
+                if let Some(connection) = rabbitmq_connection.clone() {
+                    tokio::spawn(store_dead_letters(connection, db_for_http.clone()));
+                }
 
                 HttpServer::new(move || {
                     let auth = HttpAuthentication::bearer(auth::validator);
@@ -341,25 +415,24 @@ fn main() -> anyhow::Result<()> {
                         language_model_runner.clone(),
                         chunker_runner.clone(),
                         semantic_search.clone(),
-                        rabbitmq_connection.clone(),
+                        queue.clone(),
                         code_executor.clone(),
                         db_for_http.clone(),
                         cache_for_http.clone(),
                     ));
 
-                    // start 8 threads per core to process spans from RabbitMQ
-                    if is_feature_enabled(Feature::FullBuild) {
-                        for _ in 0..8 {
-                            tokio::spawn(process_queue_spans(
-                                pipeline_runner.clone(),
-                                db_for_http.clone(),
-                                cache_for_http.clone(),
-                                semantic_search.clone(),
-                                rabbitmq_connection.clone(),
-                                clickhouse.clone(),
-                                chunker_runner.clone(),
-                            ));
-                        }
+                    // start 8 threads per core to process spans from the queue
+                    for _ in 0..SPAN_LISTENERS_PER_WORKER {
+                        tokio::spawn(process_queue_spans(
+                            pipeline_runner.clone(),
+                            db_for_http.clone(),
+                            cache_for_http.clone(),
+                            semantic_search.clone(),
+                            queue.clone(),
+                            clickhouse.clone(),
+                            chunker_runner.clone(),
+                            tail_sampler.clone(),
+                        ));
                     }
 
                     App::new()
@@ -372,11 +445,14 @@ fn main() -> anyhow::Result<()> {
                         .app_data(web::Data::new(interrupt_senders.clone()))
                         .app_data(web::Data::new(language_model_runner.clone()))
                         .app_data(web::Data::new(rabbitmq_connection.clone()))
+                        .app_data(web::Data::new(queue.clone()))
                         .app_data(web::Data::new(clickhouse.clone()))
                         .app_data(web::Data::new(name_generator.clone()))
                         .app_data(web::Data::new(semantic_search.clone()))
                         .app_data(web::Data::new(chunker_runner.clone()))
                         .app_data(web::Data::new(storage.clone()))
+                        .app_data(web::Data::from(rate_limiter_for_http.clone()))
+                        .app_data(web::Data::from(cumulative_to_delta.clone()))
                         // Scopes with specific auth or no auth
                         .service(
                             web::scope("api/v1/auth")
@@ -390,9 +466,17 @@ fn main() -> anyhow::Result<()> {
                         )
                         .service(
                             web::scope("api/v1/manage-subscriptions")
-                                .wrap(shared_secret_auth)
+                                .wrap(shared_secret_auth.clone())
                                 .service(routes::subscriptions::update_subscription),
                         )
+                        .service(
+                            web::scope("api/v1/admin")
+                                .wrap(shared_secret_auth)
+                                .service(routes::dead_letters::get_dead_letters)
+                                .service(routes::dead_letters::replay_all_dead_letters)
+                                .service(routes::dead_letters::get_dead_letter)
+                                .service(routes::dead_letters::replay_dead_letter),
+                        )
                         .service(
                             web::scope("/v1")
                                 .wrap(project_auth.clone())
@@ -403,6 +487,7 @@ fn main() -> anyhow::Result<()> {
                                 .service(api::v1::datasets::get_datapoints)
                                 .service(api::v1::evaluations::create_evaluation)
                                 .service(api::v1::metrics::process_metrics)
+                                .service(api::v1::logs::process_logs)
                                 .app_data(PayloadConfig::new(10 * 1024 * 1024)),
                         )
                         // Scopes with generic auth
@@ -470,6 +555,7 @@ fn main() -> anyhow::Result<()> {
                                         })
                                         .service(routes::projects::get_project)
                                         .service(routes::projects::delete_project)
+                                        .service(routes::projects::update_project_retention)
                                         .service(routes::pipelines::run_pipeline_graph)
                                         .service(routes::pipelines::get_pipelines)
                                         .service(routes::pipelines::create_pipeline)
@@ -491,6 +577,9 @@ fn main() -> anyhow::Result<()> {
                                         .service(routes::api_keys::create_project_api_key)
                                         .service(routes::api_keys::get_api_keys_for_project)
                                         .service(routes::api_keys::revoke_project_api_key)
+                                        .service(
+                                            routes::api_keys::update_project_api_key_rate_limit,
+                                        )
                                         .service(routes::evaluations::get_evaluation)
                                         .service(routes::evaluations::delete_evaluation)
                                         .service(routes::evaluations::get_evaluation_datapoint)
@@ -515,8 +604,13 @@ fn main() -> anyhow::Result<()> {
                                         .service(routes::evaluations::get_evaluation_datapoint)
                                         .service(routes::traces::get_traces)
                                         .service(routes::traces::get_single_trace)
+                                        .service(routes::traces::get_trace_diff)
                                         .service(routes::traces::get_single_span)
+                                        .service(routes::traces::get_spans)
+                                        .service(routes::traces::export_traces)
+                                        .service(routes::traces::export_spans)
                                         .service(routes::traces::get_sessions)
+                                        .service(routes::traces::get_session)
                                         .service(routes::labels::get_label_types)
                                         .service(routes::labels::get_span_labels)
                                         .service(routes::labels::update_span_label)
@@ -534,10 +628,18 @@ fn main() -> anyhow::Result<()> {
                                         .service(routes::events::get_events_by_template_id)
                                         .service(routes::events::get_events_metrics)
                                         .service(routes::traces::get_traces_metrics)
-                                        .service(routes::provider_api_keys::save_api_key),
+                                        .service(routes::traces::get_spans_metrics)
+                                        .service(routes::metrics::get_otel_metric)
+                                        .service(routes::provider_api_keys::save_api_key)
+                                        .service(routes::redaction_rules::get_redaction_rules)
+                                        .service(routes::redaction_rules::create_redaction_rule)
+                                        .service(routes::redaction_rules::delete_redaction_rule)
+                                        .service(routes::sampling_rules::get_sampling_rules)
+                                        .service(routes::sampling_rules::update_sampling_rules),
                                 ),
                         )
                 })
+                .workers(http_workers)
                 .bind(("0.0.0.0", port))?
                 .run()
                 .await
@@ -553,12 +655,16 @@ fn main() -> anyhow::Result<()> {
                 let process_traces_service = ProcessTracesService::new(
                     db.clone(),
                     cache.clone(),
-                    rabbitmq_connection_grpc.clone(),
+                    queue_grpc.clone(),
                     storage_grpc.clone(),
+                    rate_limiter.clone(),
                 );
+                let process_logs_service =
+                    ProcessLogsService::new(db.clone(), cache.clone(), rate_limiter.clone());
 
                 Server::builder()
                     .add_service(TraceServiceServer::new(process_traces_service))
+                    .add_service(LogsServiceServer::new(process_logs_service))
                     .serve_with_shutdown(grpc_address, async {
                         wait_stop_signal("gRPC service").await;
                     })
@@ -0,0 +1,54 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+pub mod rabbit;
+pub mod tokio_mpsc;
+
+/// How failed deliveries are retried, and where they go once they run out of retries
+#[derive(Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_retries: i64,
+    pub retry_delay: Duration,
+    /// Exchange whose queue holds messages for `retry_delay`,
+    /// and then dead-letters them back to the exchange they came from
+    pub retry_exchange: &'static str,
+    pub dead_letter_exchange: &'static str,
+}
+
+pub struct MessageQueueDelivery {
+    pub data: Vec<u8>,
+    pub acker: Arc<dyn MessageQueueAcker>,
+}
+
+#[async_trait]
+pub trait MessageQueueAcker: Sync + Send {
+    async fn ack(&self) -> Result<()>;
+    async fn nack(&self, requeue: bool) -> Result<()>;
+    /// Schedules the message for another attempt, or dead-letters it if it ran out of retries.
+    async fn retry_or_dead_letter(&self, retry_policy: &RetryPolicy, error: &str);
+    /// Dead-letters the message right away, e.g. when it cannot be parsed and retrying is pointless.
+    async fn dead_letter(&self, retry_policy: &RetryPolicy, error: &str);
+}
+
+#[async_trait]
+pub trait MessageQueueReceiver: Send {
+    /// Returns `None` once the queue is closed
+    async fn receive(&mut self) -> Option<Result<MessageQueueDelivery>>;
+}
+
+#[async_trait]
+pub trait MessageQueue: Sync + Send {
+    async fn publish(&self, message: &[u8], exchange: &str, routing_key: &str) -> Result<()>;
+    /// Binds `queue` to `exchange` and starts consuming from it.
+    ///
+    /// At most `prefetch_count` deliveries are handed out to the receiver before they are settled.
+    async fn get_receiver(
+        &self,
+        queue: &str,
+        exchange: &str,
+        routing_key: &str,
+        prefetch_count: u16,
+    ) -> Result<Box<dyn MessageQueueReceiver>>;
+}
@@ -0,0 +1,269 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::Utc;
+use futures::StreamExt;
+use lapin::{
+    message::Delivery,
+    options::{
+        BasicAckOptions, BasicConsumeOptions, BasicNackOptions, BasicPublishOptions,
+        BasicQosOptions, QueueBindOptions,
+    },
+    types::{AMQPValue, FieldTable, LongString, ShortString},
+    BasicProperties, Channel, Connection, Consumer,
+};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use super::{
+    MessageQueue, MessageQueueAcker, MessageQueueDelivery, MessageQueueReceiver, RetryPolicy,
+};
+
+const RETRY_COUNT_HEADER: &str = "x-lmnr-retry-count";
+const ERROR_HEADER: &str = "x-lmnr-error";
+
+pub struct RabbitMQ {
+    connection: Arc<Connection>,
+    publish_channel: Mutex<Channel>,
+}
+
+impl RabbitMQ {
+    pub async fn new(connection: Arc<Connection>) -> Result<Self> {
+        let publish_channel = connection.create_channel().await?;
+        Ok(Self {
+            connection,
+            publish_channel: Mutex::new(publish_channel),
+        })
+    }
+
+    /// Returns the shared publishing channel, reopening it if it was closed
+    async fn get_publish_channel(&self) -> Result<Channel> {
+        let mut channel = self.publish_channel.lock().await;
+        if !channel.status().connected() {
+            *channel = self.connection.create_channel().await?;
+        }
+        Ok(channel.clone())
+    }
+}
+
+#[async_trait]
+impl MessageQueue for RabbitMQ {
+    async fn publish(&self, message: &[u8], exchange: &str, routing_key: &str) -> Result<()> {
+        let channel = self.get_publish_channel().await?;
+        channel
+            .basic_publish(
+                exchange,
+                routing_key,
+                BasicPublishOptions::default(),
+                message,
+                BasicProperties::default(),
+            )
+            .await?
+            .await?;
+        Ok(())
+    }
+
+    async fn get_receiver(
+        &self,
+        queue: &str,
+        exchange: &str,
+        routing_key: &str,
+        prefetch_count: u16,
+    ) -> Result<Box<dyn MessageQueueReceiver>> {
+        let channel = self.connection.create_channel().await?;
+
+        channel
+            .queue_bind(
+                queue,
+                exchange,
+                routing_key,
+                QueueBindOptions::default(),
+                FieldTable::default(),
+            )
+            .await?;
+        channel
+            .basic_qos(prefetch_count, BasicQosOptions::default())
+            .await?;
+
+        let consumer = channel
+            .basic_consume(
+                queue,
+                routing_key,
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            )
+            .await?;
+
+        Ok(Box::new(RabbitMQReceiver { channel, consumer }))
+    }
+}
+
+struct RabbitMQReceiver {
+    channel: Channel,
+    consumer: Consumer,
+}
+
+#[async_trait]
+impl MessageQueueReceiver for RabbitMQReceiver {
+    async fn receive(&mut self) -> Option<Result<MessageQueueDelivery>> {
+        let delivery = self.consumer.next().await?;
+        Some(
+            delivery
+                .map(|delivery| MessageQueueDelivery {
+                    data: delivery.data.clone(),
+                    acker: Arc::new(RabbitMQAcker {
+                        channel: self.channel.clone(),
+                        delivery,
+                    }),
+                })
+                .map_err(|e| e.into()),
+        )
+    }
+}
+
+struct RabbitMQAcker {
+    channel: Channel,
+    delivery: Delivery,
+}
+
+#[async_trait]
+impl MessageQueueAcker for RabbitMQAcker {
+    async fn ack(&self) -> Result<()> {
+        self.delivery.ack(BasicAckOptions::default()).await?;
+        Ok(())
+    }
+
+    async fn nack(&self, requeue: bool) -> Result<()> {
+        self.delivery
+            .nack(BasicNackOptions {
+                requeue,
+                ..Default::default()
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Republishes the delivery to the retry exchange with an incremented retry count,
+    /// or to the dead-letter exchange if it ran out of retries.
+    ///
+    /// The original delivery is acked once the copy is published, and requeued if publishing fails.
+    async fn retry_or_dead_letter(&self, retry_policy: &RetryPolicy, error: &str) {
+        let retry_count = get_retry_count(&self.delivery);
+
+        let publish_result = if retry_count < retry_policy.max_retries {
+            let properties = BasicProperties::default()
+                .with_headers(headers(retry_count + 1, error))
+                .with_expiration(retry_policy.retry_delay.as_millis().to_string().into());
+            self.publish_copy(retry_policy.retry_exchange, properties)
+                .await
+        } else {
+            self.publish_dead_letter(retry_policy, retry_count, error)
+                .await
+        };
+
+        self.settle(publish_result).await;
+    }
+
+    async fn dead_letter(&self, retry_policy: &RetryPolicy, error: &str) {
+        let retry_count = get_retry_count(&self.delivery);
+        let publish_result = self
+            .publish_dead_letter(retry_policy, retry_count, error)
+            .await;
+        self.settle(publish_result).await;
+    }
+}
+
+impl RabbitMQAcker {
+    /// The message id and timestamp identify the dead letter, so that redeliveries
+    /// of it can be told apart from other dead letters with the same payload
+    async fn publish_dead_letter(
+        &self,
+        retry_policy: &RetryPolicy,
+        retry_count: i64,
+        error: &str,
+    ) -> Result<()> {
+        let properties = BasicProperties::default()
+            .with_message_id(Uuid::new_v4().to_string().into())
+            .with_timestamp(Utc::now().timestamp() as u64)
+            .with_headers(headers(retry_count, error));
+        self.publish_copy(retry_policy.dead_letter_exchange, properties)
+            .await
+    }
+
+    async fn publish_copy(&self, exchange: &str, properties: BasicProperties) -> Result<()> {
+        self.channel
+            .basic_publish(
+                exchange,
+                self.delivery.routing_key.as_str(),
+                BasicPublishOptions::default(),
+                &self.delivery.data,
+                properties,
+            )
+            .await?
+            .await?;
+        Ok(())
+    }
+
+    async fn settle(&self, publish_result: Result<()>) {
+        match publish_result {
+            Ok(_) => {
+                let _ = self
+                    .delivery
+                    .ack(BasicAckOptions::default())
+                    .await
+                    .map_err(|e| log::error!("Failed to ack RabbitMQ delivery: {:?}", e));
+            }
+            Err(e) => {
+                log::error!("Failed to publish message for retry: {:?}", e);
+                let _ = self
+                    .delivery
+                    .nack(BasicNackOptions {
+                        requeue: true,
+                        ..Default::default()
+                    })
+                    .await
+                    .map_err(|e| log::error!("Failed to nack RabbitMQ delivery: {:?}", e));
+            }
+        }
+    }
+}
+
+fn headers(retry_count: i64, error: &str) -> FieldTable {
+    let mut headers = FieldTable::default();
+    headers.insert(
+        RETRY_COUNT_HEADER.into(),
+        AMQPValue::LongLongInt(retry_count),
+    );
+    headers.insert(
+        ERROR_HEADER.into(),
+        AMQPValue::LongString(LongString::from(error.to_string())),
+    );
+    headers
+}
+
+fn get_header<'a>(delivery: &'a Delivery, name: &str) -> Option<&'a AMQPValue> {
+    delivery
+        .properties
+        .headers()
+        .as_ref()
+        .and_then(|headers| headers.inner().get(&ShortString::from(name)))
+}
+
+/// Number of times the delivery was retried before it was dead-lettered
+pub fn get_retry_count(delivery: &Delivery) -> i64 {
+    match get_header(delivery, RETRY_COUNT_HEADER) {
+        Some(AMQPValue::LongLongInt(count)) => *count,
+        Some(AMQPValue::LongInt(count)) => *count as i64,
+        Some(AMQPValue::LongUInt(count)) => *count as i64,
+        _ => 0,
+    }
+}
+
+/// Error of the last failed attempt to process the delivery
+pub fn get_error(delivery: &Delivery) -> Option<String> {
+    match get_header(delivery, ERROR_HEADER) {
+        Some(AMQPValue::LongString(error)) => Some(error.to_string()),
+        _ => None,
+    }
+}
@@ -0,0 +1,184 @@
+//! In-process message queue on top of tokio channels.
+//!
+//! Exchanges fan out to every queue bound to them, and receivers of the same queue
+//! compete for messages, like with RabbitMQ. Nothing is persisted, so messages that are
+//! in flight are lost on restart, and dead-lettered messages are logged and dropped.
+
+use std::{collections::HashSet, sync::Arc};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use dashmap::DashMap;
+use tokio::sync::{mpsc, Mutex};
+
+use super::{
+    MessageQueue, MessageQueueAcker, MessageQueueDelivery, MessageQueueReceiver, RetryPolicy,
+};
+
+const DEFAULT_QUEUE_CAPACITY: usize = 10_000;
+
+struct TokioMessage {
+    data: Vec<u8>,
+    retry_count: i64,
+}
+
+#[derive(Clone)]
+struct TokioQueue {
+    sender: mpsc::Sender<TokioMessage>,
+    receiver: Arc<Mutex<mpsc::Receiver<TokioMessage>>>,
+}
+
+pub struct TokioMpscQueue {
+    queues: DashMap<String, TokioQueue>,
+    /// Exchange name to the names of the queues bound to it
+    bindings: DashMap<String, HashSet<String>>,
+    capacity: usize,
+}
+
+impl TokioMpscQueue {
+    pub fn new() -> Self {
+        Self {
+            queues: DashMap::new(),
+            bindings: DashMap::new(),
+            capacity: DEFAULT_QUEUE_CAPACITY,
+        }
+    }
+
+    /// Creates the queue if needed and binds it to the exchange.
+    ///
+    /// Messages published to an exchange without bound queues are dropped,
+    /// so queues must be declared before anything is published.
+    pub fn declare_queue(&self, queue: &str, exchange: &str) {
+        self.get_or_create_queue(queue);
+        self.bindings
+            .entry(exchange.to_string())
+            .or_default()
+            .insert(queue.to_string());
+    }
+
+    fn get_or_create_queue(&self, queue: &str) -> TokioQueue {
+        self.queues
+            .entry(queue.to_string())
+            .or_insert_with(|| {
+                let (sender, receiver) = mpsc::channel(self.capacity);
+                TokioQueue {
+                    sender,
+                    receiver: Arc::new(Mutex::new(receiver)),
+                }
+            })
+            .clone()
+    }
+}
+
+#[async_trait]
+impl MessageQueue for TokioMpscQueue {
+    async fn publish(&self, message: &[u8], exchange: &str, _routing_key: &str) -> Result<()> {
+        let senders = match self.bindings.get(exchange) {
+            Some(queues) => queues
+                .iter()
+                .filter_map(|queue| self.queues.get(queue).map(|q| q.sender.clone()))
+                .collect::<Vec<_>>(),
+            None => Vec::new(),
+        };
+
+        for sender in senders {
+            sender
+                .send(TokioMessage {
+                    data: message.to_vec(),
+                    retry_count: 0,
+                })
+                .await
+                .map_err(|_| anyhow::anyhow!("Queue bound to exchange {} is closed", exchange))?;
+        }
+        Ok(())
+    }
+
+    async fn get_receiver(
+        &self,
+        queue: &str,
+        exchange: &str,
+        _routing_key: &str,
+        _prefetch_count: u16,
+    ) -> Result<Box<dyn MessageQueueReceiver>> {
+        self.declare_queue(queue, exchange);
+        Ok(Box::new(TokioMpscReceiver {
+            queue: self.get_or_create_queue(queue),
+        }))
+    }
+}
+
+struct TokioMpscReceiver {
+    queue: TokioQueue,
+}
+
+#[async_trait]
+impl MessageQueueReceiver for TokioMpscReceiver {
+    async fn receive(&mut self) -> Option<Result<MessageQueueDelivery>> {
+        let message = self.queue.receiver.lock().await.recv().await?;
+        Some(Ok(MessageQueueDelivery {
+            data: message.data.clone(),
+            acker: Arc::new(TokioMpscAcker {
+                sender: self.queue.sender.clone(),
+                data: message.data,
+                retry_count: message.retry_count,
+            }),
+        }))
+    }
+}
+
+struct TokioMpscAcker {
+    sender: mpsc::Sender<TokioMessage>,
+    data: Vec<u8>,
+    retry_count: i64,
+}
+
+impl TokioMpscAcker {
+    /// Puts the message back on the queue in the background, since the queue may be full
+    /// and the caller may be the one supposed to drain it.
+    fn requeue(&self, retry_count: i64, delay: Option<std::time::Duration>) {
+        let sender = self.sender.clone();
+        let message = TokioMessage {
+            data: self.data.clone(),
+            retry_count,
+        };
+        tokio::spawn(async move {
+            if let Some(delay) = delay {
+                tokio::time::sleep(delay).await;
+            }
+            if sender.send(message).await.is_err() {
+                log::error!("Failed to requeue message, the queue is closed");
+            }
+        });
+    }
+}
+
+#[async_trait]
+impl MessageQueueAcker for TokioMpscAcker {
+    async fn ack(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn nack(&self, requeue: bool) -> Result<()> {
+        if requeue {
+            self.requeue(self.retry_count, None);
+        }
+        Ok(())
+    }
+
+    async fn retry_or_dead_letter(&self, retry_policy: &RetryPolicy, error: &str) {
+        if self.retry_count < retry_policy.max_retries {
+            self.requeue(self.retry_count + 1, Some(retry_policy.retry_delay));
+        } else {
+            self.dead_letter(retry_policy, error).await;
+        }
+    }
+
+    async fn dead_letter(&self, _retry_policy: &RetryPolicy, error: &str) {
+        log::error!(
+            "Dropping message after {} retries: {}. Payload: {}",
+            self.retry_count,
+            error,
+            String::from_utf8_lossy(&self.data)
+        );
+    }
+}
@@ -11,6 +11,8 @@ use crate::{
     semantic_search::SemanticSearch,
 };
 
+pub mod retention;
+
 #[derive(Deserialize, Serialize, FromRow, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Project {
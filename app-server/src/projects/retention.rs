@@ -0,0 +1,84 @@
+//! Background job which deletes project data older than the project's retention period
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::{
+    ch,
+    db::{self, DB},
+    features::{is_feature_enabled, Feature},
+};
+
+/// Allowed retention periods, in days
+pub const RETENTION_DAYS_RANGE: std::ops::RangeInclusive<i32> = 1..=3650;
+const RETENTION_JOB_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Traces are deleted in batches, to keep the transactions short
+const DELETE_BATCH_SIZE: i64 = 1000;
+
+pub async fn run_retention_job(db: Arc<DB>, clickhouse: clickhouse::Client) {
+    let mut interval = tokio::time::interval(RETENTION_JOB_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = apply_retention(&db, &clickhouse).await {
+            log::error!("Failed to apply retention periods: {:?}", e);
+        }
+    }
+}
+
+async fn apply_retention(db: &DB, clickhouse: &clickhouse::Client) -> Result<()> {
+    let projects = db::projects::get_projects_with_retention(&db.pool).await?;
+    for project in projects {
+        let cutoff = Utc::now() - chrono::Duration::days(project.retention_days as i64);
+        // One failing project should not keep the others from being cleaned up
+        if let Err(e) = apply_project_retention(db, clickhouse, project.id, cutoff).await {
+            log::error!(
+                "Failed to apply retention period of project {}: {:?}",
+                project.id,
+                e
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn apply_project_retention(
+    db: &DB,
+    clickhouse: &clickhouse::Client,
+    project_id: Uuid,
+    cutoff: DateTime<Utc>,
+) -> Result<()> {
+    let mut deleted_traces = 0;
+    loop {
+        let deleted =
+            db::trace::delete_traces_before(&db.pool, &project_id, cutoff, DELETE_BATCH_SIZE)
+                .await?;
+        deleted_traces += deleted;
+        if deleted < DELETE_BATCH_SIZE as u64 {
+            break;
+        }
+    }
+    // These tables have no foreign keys to the spans, and logs may have no span at all
+    db::events::delete_events_before(&db.pool, &project_id, cutoff).await?;
+    db::logs::delete_logs_before(&db.pool, &project_id, cutoff).await?;
+    db::span_events::delete_span_events_before(&db.pool, &project_id, cutoff).await?;
+    let deleted_evaluations =
+        db::evaluations::delete_evaluations_before(&db.pool, &project_id, cutoff).await?;
+
+    if is_feature_enabled(Feature::FullBuild) {
+        ch::retention::delete_project_data_before(clickhouse, project_id, cutoff).await?;
+    }
+
+    if deleted_traces > 0 || deleted_evaluations > 0 {
+        log::info!(
+            "Retention: deleted {} traces and {} evaluations of project {} older than {}",
+            deleted_traces,
+            deleted_evaluations,
+            project_id,
+            cutoff
+        );
+    }
+    Ok(())
+}
@@ -4,7 +4,7 @@ use serde::{Deserialize, Serialize};
 use sha3::{Digest, Sha3_256};
 use uuid::Uuid;
 
-use super::ResponseResult;
+use super::{error::Error, ResponseResult};
 use crate::cache::Cache;
 
 #[derive(Deserialize)]
@@ -89,6 +89,52 @@ async fn revoke_project_api_key(
     Ok(HttpResponse::Ok().finish())
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UpdateApiKeyRateLimitRequest {
+    /// Spans per second, `None` removes the limit
+    rate_limit_per_second: Option<f64>,
+    rate_limit_burst: Option<i32>,
+}
+
+#[post("api-keys/{api_key_id}/rate-limit")]
+async fn update_project_api_key_rate_limit(
+    path: web::Path<(Uuid, Uuid)>,
+    db: web::Data<DB>,
+    req: web::Json<UpdateApiKeyRateLimitRequest>,
+    cache: web::Data<Cache>,
+) -> ResponseResult {
+    let (project_id, api_key_id) = path.into_inner();
+    let req = req.into_inner();
+
+    if req.rate_limit_per_second.is_some_and(|rate| rate <= 0.0) {
+        return Err(Error::invalid_request(Some(
+            "Rate limit must be greater than 0",
+        )));
+    }
+    if req.rate_limit_burst.is_some_and(|burst| burst < 1) {
+        return Err(Error::invalid_request(Some("Burst must be at least 1")));
+    }
+    if req.rate_limit_per_second.is_none() && req.rate_limit_burst.is_some() {
+        return Err(Error::invalid_request(Some(
+            "Burst can only be set along with the rate limit",
+        )));
+    }
+
+    let hash = db::project_api_keys::update_api_key_rate_limit(
+        &db.pool,
+        &api_key_id,
+        &project_id,
+        req.rate_limit_per_second,
+        req.rate_limit_burst,
+    )
+    .await?;
+
+    let _ = cache.remove::<ProjectApiKey>(&hash).await;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
 pub fn hash_api_key(api_key: &str) -> String {
     let mut hasher = Sha3_256::new();
     hasher.update(api_key.as_bytes());
@@ -0,0 +1,104 @@
+use std::sync::Arc;
+
+use actix_web::{get, post, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    db::{dead_letters::DeadLetterCursor, DB},
+    mq::MessageQueue,
+    traces::dead_letter,
+};
+
+use super::{error::Error, ResponseResult};
+
+const DEFAULT_DEAD_LETTERS_LIMIT: i64 = 50;
+const MAX_DEAD_LETTERS_LIMIT: i64 = 1000;
+
+/// Dead letters are listed from the latest. To get the next page, pass `deadLetteredAt`
+/// and `id` of the last dead letter of the previous page as `before` and `beforeId`.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GetDeadLettersParams {
+    #[serde(default)]
+    limit: Option<i64>,
+    #[serde(default)]
+    before: Option<DateTime<Utc>>,
+    #[serde(default)]
+    before_id: Option<Uuid>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ReplayDeadLettersResponse {
+    replayed_count: usize,
+}
+
+#[get("dead-letters")]
+pub async fn get_dead_letters(
+    db: web::Data<DB>,
+    params: web::Query<GetDeadLettersParams>,
+) -> ResponseResult {
+    let params = params.into_inner();
+    let limit = params.limit.unwrap_or(DEFAULT_DEAD_LETTERS_LIMIT);
+    if !(1..=MAX_DEAD_LETTERS_LIMIT).contains(&limit) {
+        return Err(Error::invalid_request(Some(&format!(
+            "limit must be between 1 and {MAX_DEAD_LETTERS_LIMIT}"
+        ))));
+    }
+    let cursor = match (params.before, params.before_id) {
+        (Some(dead_lettered_at), Some(id)) => Some(DeadLetterCursor {
+            dead_lettered_at,
+            id,
+        }),
+        (None, None) => None,
+        _ => {
+            return Err(Error::invalid_request(Some(
+                "before and beforeId must be passed together",
+            )))
+        }
+    };
+
+    let dead_letters = dead_letter::list_dead_letters(&db, limit, cursor.as_ref()).await?;
+
+    Ok(HttpResponse::Ok().json(dead_letters))
+}
+
+#[get("dead-letters/{id}")]
+pub async fn get_dead_letter(db: web::Data<DB>, path: web::Path<Uuid>) -> ResponseResult {
+    let id = path.into_inner();
+
+    match dead_letter::get_dead_letter(&db, &id).await? {
+        Some(dead_letter) => Ok(HttpResponse::Ok().json(dead_letter)),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+#[post("dead-letters/replay")]
+pub async fn replay_all_dead_letters(
+    db: web::Data<DB>,
+    queue: web::Data<Arc<dyn MessageQueue>>,
+) -> ResponseResult {
+    let replayed_count =
+        dead_letter::replay_dead_letters(&db, queue.as_ref().clone(), None).await?;
+
+    Ok(HttpResponse::Ok().json(ReplayDeadLettersResponse { replayed_count }))
+}
+
+#[post("dead-letters/{id}/replay")]
+pub async fn replay_dead_letter(
+    db: web::Data<DB>,
+    queue: web::Data<Arc<dyn MessageQueue>>,
+    path: web::Path<Uuid>,
+) -> ResponseResult {
+    let id = path.into_inner();
+
+    let replayed_count =
+        dead_letter::replay_dead_letters(&db, queue.as_ref().clone(), Some(&[id])).await?;
+
+    if replayed_count == 0 {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    Ok(HttpResponse::Ok().json(ReplayDeadLettersResponse { replayed_count }))
+}
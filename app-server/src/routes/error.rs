@@ -15,7 +15,7 @@ use crate::pipeline::GraphError;
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("{0}")]
-    InternalAnyhowError(#[from] anyhow::Error),
+    InternalAnyhowError(anyhow::Error),
     #[error("{0}")]
     MultipartError(#[from] actix_multipart::MultipartError),
     #[error("Request error, error_code: {error_code:?}, error_message: {error_message:?}")]
@@ -27,6 +27,23 @@ pub enum Error {
     Forbidden,
 }
 
+/// Postgres error code of an invalid regular expression, e.g. in a regex filter
+const INVALID_REGULAR_EXPRESSION: &str = "2201B";
+
+impl From<anyhow::Error> for Error {
+    fn from(error: anyhow::Error) -> Self {
+        if let Some(sqlx::Error::Database(db_error)) = error.downcast_ref::<sqlx::Error>() {
+            if db_error.code().as_deref() == Some(INVALID_REGULAR_EXPRESSION) {
+                return Self::invalid_request(Some(&format!(
+                    "Invalid regular expression: {}",
+                    db_error.message()
+                )));
+            }
+        }
+        Self::InternalAnyhowError(error)
+    }
+}
+
 // This can be refactored, but for now it can be used as a single source to see
 // all the error codes to be handled in frontend
 impl Error {
@@ -7,13 +7,13 @@ use crate::{
     db::{
         self,
         events::EventWithTemplateName,
-        modifiers::{AbsoluteDateInterval, DateRange, Filter, RelativeDateInterval},
+        modifiers::{AbsoluteDateInterval, DateRange, FilterExpression, RelativeDateInterval},
         DB,
     },
     routes::{PaginatedGetQueryParams, PaginatedResponse, DEFAULT_PAGE_SIZE},
 };
 
-use super::{GetMetricsQueryParams, ResponseResult};
+use super::{error::Error, GetMetricsQueryParams, ResponseResult};
 
 #[get("event-templates")]
 pub async fn get_event_templates(path: web::Path<Uuid>, db: web::Data<DB>) -> ResponseResult {
@@ -84,7 +84,9 @@ pub async fn get_events_by_template_id(
     let date_range = query_params.date_range;
     let limit = query_params.page_size.unwrap_or(DEFAULT_PAGE_SIZE);
     let offset = limit * query_params.page_number;
-    let filters = Filter::from_url_params(query_params.filter);
+    let filters =
+        FilterExpression::from_url_params(query_params.filter, &db::events::validate_event_filter)
+            .map_err(|e| Error::invalid_request(Some(&e.to_string())))?;
 
     let events = db::events::get_events_by_template_id(
         &db.pool,
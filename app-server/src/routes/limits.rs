@@ -1,6 +1,9 @@
 use actix_web::{get, web, HttpResponse};
 
-use crate::db::{self, user::User, DB};
+use crate::{
+    cache::Cache,
+    db::{self, stats::StorageStats, DB},
+};
 
 use super::ResponseResult;
 
@@ -15,7 +18,18 @@ pub async fn get_workspace_stats(
 }
 
 #[get("workspace/{workspace_id}/storage")]
-pub async fn get_workspace_storage_stats(user: User, db: web::Data<DB>) -> ResponseResult {
-    let stats = db::stats::get_workspace_storage_stats(&db.pool, &user.id).await?;
+pub async fn get_workspace_storage_stats(
+    workspace_id: web::Path<uuid::Uuid>,
+    db: web::Data<DB>,
+    cache: web::Data<Cache>,
+) -> ResponseResult {
+    let workspace_id = workspace_id.into_inner();
+    let cache_key = workspace_id.to_string();
+    if let Ok(Some(stats)) = cache.get::<StorageStats>(&cache_key).await {
+        return Ok(HttpResponse::Ok().json(stats));
+    }
+
+    let stats = db::stats::get_workspace_storage_stats(&db.pool, &workspace_id).await?;
+    let _ = cache.insert::<StorageStats>(cache_key, &stats).await;
     Ok(HttpResponse::Ok().json(stats))
 }
@@ -0,0 +1,89 @@
+use actix_web::{post, web, HttpResponse};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    ch::{self, utils::get_bounds},
+    db::modifiers::{AbsoluteDateInterval, DateRange, RelativeDateInterval},
+};
+
+use super::{GetMetricsQueryParams, ResponseResult};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GetOtelMetricParams {
+    /// OTel metric name, e.g. `gen_ai.client.token.usage`
+    name: String,
+    #[serde(flatten)]
+    base_params: GetMetricsQueryParams,
+}
+
+/// Get time series for a single ingested OTel metric
+#[post("metrics")]
+pub async fn get_otel_metric(
+    params: web::Path<Uuid>,
+    clickhouse: web::Data<clickhouse::Client>,
+    req: web::Json<GetOtelMetricParams>,
+) -> ResponseResult {
+    let project_id = params.into_inner();
+    let clickhouse = clickhouse.into_inner().as_ref().clone();
+    let req = req.into_inner();
+    let name = req.name;
+    let aggregation = req.base_params.aggregation;
+    let date_range = req.base_params.date_range.as_ref();
+    let group_by_interval = req.base_params.group_by_interval;
+
+    let defaulted_range =
+        date_range
+            .cloned()
+            .unwrap_or(DateRange::Relative(RelativeDateInterval {
+                past_hours: "all".to_string(),
+            }));
+
+    let range = if let DateRange::Relative(RelativeDateInterval { past_hours }) = &defaulted_range {
+        if past_hours == "all" {
+            let (start_date, end_date) =
+                get_bounds(&clickhouse, &project_id, "metrics", "timestamp").await?;
+            DateRange::Absolute(AbsoluteDateInterval {
+                start_date,
+                end_date,
+            })
+        } else {
+            defaulted_range
+        }
+    } else {
+        defaulted_range
+    };
+
+    let values = match range {
+        DateRange::Relative(interval) => {
+            let past_hours = interval
+                .past_hours
+                .parse::<i64>()
+                .map_err(|e| anyhow::anyhow!("Failed to parse past_hours as i64: {}", e))?;
+            ch::metrics::get_metric_values_relative(
+                clickhouse,
+                group_by_interval,
+                project_id,
+                &name,
+                past_hours,
+                aggregation,
+            )
+            .await?
+        }
+        DateRange::Absolute(interval) => {
+            ch::metrics::get_metric_values_absolute(
+                clickhouse,
+                group_by_interval,
+                project_id,
+                &name,
+                interval.start_date,
+                interval.end_date,
+                aggregation,
+            )
+            .await?
+        }
+    };
+
+    Ok(HttpResponse::Ok().json(values))
+}
@@ -1,14 +1,18 @@
 pub mod api_keys;
 pub mod auth;
 pub mod datasets;
+pub mod dead_letters;
 pub mod error;
 pub mod evaluations;
 pub mod events;
 pub mod labels;
 pub mod limits;
+pub mod metrics;
 pub mod pipelines;
 pub mod projects;
 pub mod provider_api_keys;
+pub mod redaction_rules;
+pub mod sampling_rules;
 pub mod subscriptions;
 pub mod traces;
 pub mod types;
@@ -59,7 +63,7 @@ pub struct PaginatedResponse<T> {
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct GetMetricsQueryParams {
-    /// Total or average
+    /// Total, average, min, max or a percentile
     pub aggregation: Aggregation,
     /// Date range per page
     #[serde(default, flatten)]
@@ -8,8 +8,8 @@ use uuid::Uuid;
 use crate::{
     cache::Cache,
     db::{self, user::User, DB},
-    projects,
-    routes::ResponseResult,
+    projects::{self, retention::RETENTION_DAYS_RANGE},
+    routes::{error::Error, ResponseResult},
     semantic_search::SemanticSearch,
 };
 
@@ -31,6 +31,8 @@ struct GetProjectResponse {
     events_this_month: i64,
     events_limit: i64,
     is_free_tier: bool,
+    /// Days the data of the project is kept for, `None` if it is kept forever
+    retention_days: Option<i32>,
 }
 
 #[get("")] // scope: /projects/{project_id}
@@ -40,6 +42,7 @@ async fn get_project(project_id: web::Path<Uuid>, db: web::Data<DB>) -> Response
     let project = db::projects::get_project(&db.pool, &project_id).await?;
 
     let workspace_stats = db::stats::get_workspace_stats(&db.pool, &project.workspace_id).await?;
+    let retention_days = db::projects::get_project_retention_days(&db.pool, &project_id).await?;
 
     let response = GetProjectResponse {
         id: project.id,
@@ -50,11 +53,42 @@ async fn get_project(project_id: web::Path<Uuid>, db: web::Data<DB>) -> Response
         events_this_month: workspace_stats.events_this_month,
         events_limit: workspace_stats.events_limit,
         is_free_tier: workspace_stats.tier_name.to_lowercase().trim() == "free",
+        retention_days,
     };
 
     Ok(HttpResponse::Ok().json(response))
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UpdateRetentionRequest {
+    /// `None` keeps the data forever
+    retention_days: Option<i32>,
+}
+
+#[post("retention")]
+async fn update_project_retention(
+    project_id: web::Path<Uuid>,
+    db: web::Data<DB>,
+    req: web::Json<UpdateRetentionRequest>,
+) -> ResponseResult {
+    let project_id = project_id.into_inner();
+    let retention_days = req.into_inner().retention_days;
+    if let Some(days) = retention_days {
+        if !RETENTION_DAYS_RANGE.contains(&days) {
+            return Err(Error::invalid_request(Some(&format!(
+                "Retention period must be between {} and {} days",
+                RETENTION_DAYS_RANGE.start(),
+                RETENTION_DAYS_RANGE.end()
+            ))));
+        }
+    }
+
+    db::projects::update_project_retention_days(&db.pool, &project_id, retention_days).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
 #[delete("")]
 async fn delete_project(
     project_id: web::Path<Uuid>,
@@ -0,0 +1,86 @@
+use actix_web::{delete, get, post, web, HttpResponse};
+use regex::Regex;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    cache::Cache,
+    db::{self, redaction_rules::RedactionRuleType, DB},
+    traces::redaction::RedactionRules,
+};
+
+use super::{error::Error, ResponseResult};
+
+#[get("redaction-rules")]
+pub async fn get_redaction_rules(path: web::Path<Uuid>, db: web::Data<DB>) -> ResponseResult {
+    let project_id = path.into_inner();
+    let rules = db::redaction_rules::get_redaction_rules(&db.pool, &project_id).await?;
+
+    Ok(HttpResponse::Ok().json(rules))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateRedactionRuleRequest {
+    pub rule_type: RedactionRuleType,
+    /// Regex pattern for `PATTERN` rules, attribute key for `ATTRIBUTE` rules
+    #[serde(default)]
+    pub value: Option<String>,
+}
+
+#[post("redaction-rules")]
+pub async fn create_redaction_rule(
+    path: web::Path<Uuid>,
+    req: web::Json<CreateRedactionRuleRequest>,
+    db: web::Data<DB>,
+    cache: web::Data<Cache>,
+) -> ResponseResult {
+    let project_id = path.into_inner();
+    let req = req.into_inner();
+
+    let value = match req.rule_type {
+        RedactionRuleType::PATTERN | RedactionRuleType::ATTRIBUTE => {
+            let value = req
+                .value
+                .filter(|value| !value.trim().is_empty())
+                .ok_or_else(|| {
+                    Error::invalid_request(Some("Value is required for this rule type"))
+                })?;
+            if req.rule_type == RedactionRuleType::PATTERN {
+                if let Err(e) = Regex::new(&value) {
+                    return Err(Error::invalid_request(Some(&format!(
+                        "Invalid pattern: {e}"
+                    ))));
+                }
+            }
+            Some(value)
+        }
+        // Built-in rules have their own patterns
+        _ => None,
+    };
+
+    let rule =
+        db::redaction_rules::create_redaction_rule(&db.pool, &project_id, &req.rule_type, &value)
+            .await?;
+    let _ = cache
+        .remove::<RedactionRules>(&project_id.to_string())
+        .await;
+
+    Ok(HttpResponse::Ok().json(rule))
+}
+
+#[delete("redaction-rules/{rule_id}")]
+pub async fn delete_redaction_rule(
+    path: web::Path<(Uuid, Uuid)>,
+    db: web::Data<DB>,
+    cache: web::Data<Cache>,
+) -> ResponseResult {
+    let (project_id, rule_id) = path.into_inner();
+
+    db::redaction_rules::delete_redaction_rule(&db.pool, &project_id, &rule_id).await?;
+    let _ = cache
+        .remove::<RedactionRules>(&project_id.to_string())
+        .await;
+
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,50 @@
+use actix_web::{get, post, web, HttpResponse};
+use uuid::Uuid;
+
+use crate::{
+    cache::Cache,
+    db::{self, sampling_rules::SamplingRules, DB},
+};
+
+use super::{error::Error, ResponseResult};
+
+#[get("sampling-rules")]
+pub async fn get_sampling_rules(path: web::Path<Uuid>, db: web::Data<DB>) -> ResponseResult {
+    let project_id = path.into_inner();
+    let rules = db::sampling_rules::get_sampling_rules(&db.pool, &project_id)
+        .await?
+        .unwrap_or_default();
+
+    Ok(HttpResponse::Ok().json(rules))
+}
+
+#[post("sampling-rules")]
+pub async fn update_sampling_rules(
+    path: web::Path<Uuid>,
+    req: web::Json<SamplingRules>,
+    db: web::Data<DB>,
+    cache: web::Data<Cache>,
+) -> ResponseResult {
+    let project_id = path.into_inner();
+    let rules = req.into_inner();
+
+    if !(0.0..=1.0).contains(&rules.head_sample_rate) {
+        return Err(Error::invalid_request(Some(
+            "Head sample rate must be between 0 and 1",
+        )));
+    }
+    if rules.slow_trace_ms.is_some_and(|ms| ms < 0)
+        || rules.expensive_trace_cost.is_some_and(|cost| cost < 0.0)
+    {
+        return Err(Error::invalid_request(Some(
+            "Tail sampling thresholds must not be negative",
+        )));
+    }
+
+    db::sampling_rules::upsert_sampling_rules(&db.pool, &project_id, &rules).await?;
+    let _ = cache
+        .insert::<SamplingRules>(project_id.to_string(), &rules)
+        .await;
+
+    Ok(HttpResponse::Ok().json(rules))
+}
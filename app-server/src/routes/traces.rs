@@ -1,23 +1,41 @@
-use super::{GetMetricsQueryParams, ResponseResult};
+use super::{error::Error, GetMetricsQueryParams, ResponseResult};
 use super::{PaginatedGetQueryParams, PaginatedResponse, DEFAULT_PAGE_SIZE};
 use crate::ch::utils::get_bounds;
+use crate::storage::{create_key, Storage};
+use crate::traces::diff::diff_traces;
+use crate::traces::export::{export_stream, max_storage_export_bytes, ExportFormat};
+use crate::traces::transcript::{build_transcript, TranscriptMessage};
+use crate::traces::tree::{build_span_tree, SpanTreeNode};
 use crate::{
-    ch::{self, modifiers::GroupByInterval, Aggregation},
+    ch::{
+        self,
+        modifiers::{GroupBy, GroupByDimension, GroupByInterval},
+        spans::{SpanMetric, SpanMetricFilters, TraceMetric},
+        Aggregation,
+    },
     db::{
         self,
         events::EventWithTemplateName,
-        modifiers::{DateRange, Filter, RelativeDateInterval},
-        spans::Span,
-        trace::{Session, Trace, TraceWithTopSpan},
+        logs::LogRecord,
+        modifiers::{DateRange, Filter, FilterExpression, RelativeDateInterval},
+        span_events::SpanEvent,
+        spans::{Span, SpanPreview, SpanSearchMatch},
+        trace::{Session, SessionTrace, Trace, TraceWithTopSpan},
         DB,
     },
 };
 use actix_web::{get, post, web, HttpResponse};
+use bytes::Bytes;
 use chrono::{DateTime, Utc};
+use futures::{Stream, TryStreamExt};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::{collections::HashSet, pin::pin, sync::Arc};
 use uuid::Uuid;
 
+const DEFAULT_GROUP_BY_LIMIT: u32 = 10;
+const MAX_GROUP_BY_LIMIT: u32 = 50;
+
 #[get("traces")]
 pub async fn get_traces(
     path: web::Path<Uuid>,
@@ -28,13 +46,15 @@ pub async fn get_traces(
     let query_params = query_params.into_inner();
     let limit = query_params.page_size.unwrap_or(DEFAULT_PAGE_SIZE);
     let offset = limit * query_params.page_number;
-    let filters = Filter::from_url_params(query_params.filter);
+    let filters =
+        FilterExpression::from_url_params(query_params.filter, &db::trace::validate_trace_filter)
+            .map_err(|e| Error::invalid_request(Some(&e.to_string())))?;
     let mut filters_vec = filters.unwrap_or_default();
-    filters_vec.push(Filter {
+    filters_vec.push(FilterExpression::Filter(Filter {
         filter_column: "trace_type".to_string(),
         filter_operator: db::modifiers::FilterOperator::Eq,
         filter_value: Value::String("DEFAULT".to_string()),
-    });
+    }));
     let date_range = query_params.date_range;
     let text_search_filter = query_params.search;
     let db = db.into_inner().clone();
@@ -97,6 +117,10 @@ struct TraceWithSpanPreviews {
     #[serde(flatten)]
     trace: Trace,
     spans: Vec<Span>,
+    /// Spans matching the search, ranked, with the matched text highlighted
+    search_matches: Vec<SpanSearchMatch>,
+    /// All spans of the trace nested by parent, regardless of the search
+    tree: Vec<SpanTreeNode>,
 }
 
 #[derive(Deserialize)]
@@ -112,57 +136,306 @@ pub async fn get_single_trace(
     db: web::Data<DB>,
     query_params: web::Query<GetTraceParams>,
 ) -> ResponseResult {
-    let (_project_id, trace_id) = params.into_inner();
-    let search = query_params.search.clone();
+    let (project_id, trace_id) = params.into_inner();
 
-    let trace = db::trace::get_single_trace(&db.pool, trace_id).await?;
+    let Some(trace) = db::trace::get_single_trace(&db.pool, project_id, trace_id).await? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
 
-    let span_previews = db::spans::get_trace_spans(&db.pool, trace_id, search).await?;
+    let spans = db::spans::get_trace_spans(&db.pool, trace_id).await?;
+    // The tree is built from all spans, while only the matching spans are listed
+    let tree = build_span_tree(&spans);
+    let (span_previews, search_matches) = match &query_params.search {
+        Some(search) => {
+            let search_matches = db::spans::search_trace_spans(&db.pool, trace_id, search).await?;
+            let matched_span_ids = search_matches
+                .iter()
+                .map(|search_match| search_match.span_id)
+                .collect::<HashSet<_>>();
+            let span_previews = spans
+                .into_iter()
+                .filter(|span| matched_span_ids.contains(&span.span_id))
+                .collect();
+            (span_previews, search_matches)
+        }
+        None => (spans, Vec::new()),
+    };
 
     let trace_with_spans = TraceWithSpanPreviews {
         trace,
         spans: span_previews,
+        search_matches,
+        tree,
     };
 
     Ok(HttpResponse::Ok().json(trace_with_spans))
 }
 
+#[get("traces/{trace_id}/diff/{compare_trace_id}")]
+pub async fn get_trace_diff(
+    params: web::Path<(Uuid, Uuid, Uuid)>,
+    db: web::Data<DB>,
+) -> ResponseResult {
+    let (project_id, base_trace_id, compare_trace_id) = params.into_inner();
+
+    let base_trace = db::trace::get_single_trace(&db.pool, project_id, base_trace_id).await?;
+    let compare_trace = db::trace::get_single_trace(&db.pool, project_id, compare_trace_id).await?;
+    let (Some(base_trace), Some(compare_trace)) = (base_trace, compare_trace) else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    let base_spans = db::spans::get_trace_spans(&db.pool, base_trace_id).await?;
+    let compare_spans = db::spans::get_trace_spans(&db.pool, compare_trace_id).await?;
+
+    let trace_diff = diff_traces(&base_trace, &base_spans, &compare_trace, &compare_spans);
+
+    Ok(HttpResponse::Ok().json(trace_diff))
+}
+
+#[get("spans")]
+pub async fn get_spans(
+    path: web::Path<Uuid>,
+    db: web::Data<DB>,
+    query_params: web::Query<PaginatedGetQueryParams>,
+) -> ResponseResult {
+    let project_id = path.into_inner();
+    let query_params = query_params.into_inner();
+    let limit = query_params.page_size.unwrap_or(DEFAULT_PAGE_SIZE);
+    let offset = limit * query_params.page_number;
+    let filters =
+        FilterExpression::from_url_params(query_params.filter, &db::spans::validate_span_filter)
+            .map_err(|e| Error::invalid_request(Some(&e.to_string())))?;
+    let date_range = query_params.date_range;
+    let search = query_params.search;
+
+    let spans = db::spans::get_spans(
+        &db.pool,
+        project_id,
+        limit,
+        offset,
+        &filters,
+        &date_range,
+        search.clone(),
+    )
+    .await?;
+    let total_count =
+        db::spans::count_spans(&db.pool, project_id, &filters, &date_range, search).await? as u64;
+    let any_in_project = if total_count == 0 {
+        db::spans::count_all_spans_in_project(&db.pool, project_id).await? > 0
+    } else {
+        true
+    };
+
+    let response = PaginatedResponse::<SpanPreview> {
+        total_count,
+        items: spans,
+        any_in_project,
+    };
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+enum ExportDestination {
+    /// Streams the file in the response
+    #[default]
+    Download,
+    /// Writes the file to the storage and returns its url
+    Storage,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportParams {
+    format: ExportFormat,
+    #[serde(default)]
+    destination: ExportDestination,
+    /// JSON array of filters, same as in the listings
+    #[serde(default)]
+    filter: Value,
+    #[serde(default, flatten)]
+    date_range: Option<DateRange>,
+    #[serde(default)]
+    search: Option<String>,
+}
+
+async fn export_response(
+    stream: impl Stream<Item = anyhow::Result<Bytes>> + 'static,
+    params: &ExportParams,
+    storage: &Arc<dyn Storage>,
+    project_id: Uuid,
+    file_name: &str,
+) -> ResponseResult {
+    let format = params.format;
+    match params.destination {
+        ExportDestination::Download => Ok(HttpResponse::Ok()
+            .content_type(format.content_type())
+            .insert_header((
+                "Content-Disposition",
+                format!(
+                    "attachment; filename=\"{file_name}.{}\"",
+                    format.file_extension()
+                ),
+            ))
+            .streaming(stream)),
+        ExportDestination::Storage => {
+            let max_size = max_storage_export_bytes();
+            let mut stream = pin!(stream);
+            let mut data = Vec::new();
+            while let Some(chunk) = stream.try_next().await? {
+                if data.len() + chunk.len() > max_size {
+                    return Err(Error::invalid_request(Some(&format!(
+                        "Export is larger than {} bytes, narrow down the filters or download it instead",
+                        max_size
+                    ))));
+                }
+                data.extend_from_slice(&chunk);
+            }
+            let key = create_key(&project_id, &Some(format.file_extension().to_string()));
+            let url = storage.store(data, &key).await?;
+            Ok(HttpResponse::Ok().json(serde_json::json!({ "url": url })))
+        }
+    }
+}
+
+/// Exports all traces matching the filters, with their inputs, outputs, usage, labels and events
+#[post("traces/export")]
+pub async fn export_traces(
+    path: web::Path<Uuid>,
+    db: web::Data<DB>,
+    storage: web::Data<Arc<dyn Storage>>,
+    params: web::Json<ExportParams>,
+) -> ResponseResult {
+    let project_id = path.into_inner();
+    let params = params.into_inner();
+    let filters =
+        FilterExpression::from_url_params(params.filter.clone(), &db::trace::validate_trace_filter)
+            .map_err(|e| Error::invalid_request(Some(&e.to_string())))?;
+    let mut filters_vec = filters.unwrap_or_default();
+    filters_vec.push(FilterExpression::Filter(Filter {
+        filter_column: "trace_type".to_string(),
+        filter_operator: db::modifiers::FilterOperator::Eq,
+        filter_value: Value::String("DEFAULT".to_string()),
+    }));
+    let filters = Some(filters_vec);
+    let date_range = params.date_range.clone();
+    let search = params.search.clone();
+    let pool = db.pool.clone();
+
+    let stream = export_stream(params.format, move |limit, after| {
+        let pool = pool.clone();
+        let filters = filters.clone();
+        let date_range = date_range.clone();
+        let search = search.clone();
+        async move {
+            db::trace::get_traces_for_export(
+                &pool,
+                project_id,
+                limit,
+                after,
+                &filters,
+                &date_range,
+                search,
+            )
+            .await
+        }
+    });
+
+    export_response(stream, &params, &storage, project_id, "traces").await
+}
+
+/// Exports all spans matching the filters, with their inputs, outputs, usage, labels and events
+#[post("spans/export")]
+pub async fn export_spans(
+    path: web::Path<Uuid>,
+    db: web::Data<DB>,
+    storage: web::Data<Arc<dyn Storage>>,
+    params: web::Json<ExportParams>,
+) -> ResponseResult {
+    let project_id = path.into_inner();
+    let params = params.into_inner();
+    let filters =
+        FilterExpression::from_url_params(params.filter.clone(), &db::spans::validate_span_filter)
+            .map_err(|e| Error::invalid_request(Some(&e.to_string())))?;
+    let date_range = params.date_range.clone();
+    let search = params.search.clone();
+    let pool = db.pool.clone();
+
+    let stream = export_stream(params.format, move |limit, after| {
+        let pool = pool.clone();
+        let filters = filters.clone();
+        let date_range = date_range.clone();
+        let search = search.clone();
+        async move {
+            db::spans::get_spans_for_export(
+                &pool,
+                project_id,
+                limit,
+                after,
+                &filters,
+                &date_range,
+                search,
+            )
+            .await
+        }
+    });
+
+    export_response(stream, &params, &storage, project_id, "spans").await
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 struct SpanWithEvents {
     #[serde(flatten)]
     span: Span,
     events: Vec<EventWithTemplateName>,
+    logs: Vec<LogRecord>,
+    span_events: Vec<SpanEvent>,
 }
 
 #[get("spans/{span_id}")]
 pub async fn get_single_span(params: web::Path<(Uuid, Uuid)>, db: web::Data<DB>) -> ResponseResult {
-    let (_project_id, span_id) = params.into_inner();
-
-    let span = db::spans::get_span(&db.pool, span_id).await?;
-    let events = db::events::get_events_for_span(&db.pool, span_id).await?;
+    let (project_id, span_id) = params.into_inner();
 
-    let span_with_events = SpanWithEvents { span, events };
+    let Some(span) = db::spans::get_span(&db.pool, &project_id, span_id).await? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let events = db::events::get_events_for_span(&db.pool, &project_id, span_id).await?;
+    let logs = db::logs::get_logs_for_span(&db.pool, &project_id, span_id).await?;
+    let span_events = db::span_events::get_span_events(&db.pool, &project_id, span_id).await?;
+
+    let span_with_events = SpanWithEvents {
+        span,
+        events,
+        logs,
+        span_events,
+    };
 
     Ok(HttpResponse::Ok().json(span_with_events))
 }
 
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-enum TraceMetric {
-    TraceCount,
-    TraceLatencySeconds,
-    TotalTokenCount,
-    CostUsd,
-}
-
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct GetTraceMetricsParams {
     metric: TraceMetric,
-    /// Total or average
+    /// Aggregation, date range and time interval
     #[serde(flatten)]
     base_params: GetMetricsQueryParams,
+    /// If set, returns one series per value of this dimension, with the metric computed over
+    /// the spans which have the value, see `ch::spans::get_trace_metric_series_relative`
+    #[serde(default)]
+    group_by: Option<GroupByDimension>,
+    /// Maximum number of series to return when grouping by a dimension, at most 50
+    #[serde(default = "default_group_by_limit")]
+    group_by_limit: u32,
+    /// Whether spans without a value of the dimension form a series of their own
+    #[serde(default)]
+    group_by_include_missing: bool,
+}
+
+fn default_group_by_limit() -> u32 {
+    DEFAULT_GROUP_BY_LIMIT
 }
 
 /// Get metrics for a single metric type (e.g. for average trace latency)
@@ -179,6 +452,11 @@ pub async fn get_traces_metrics(
     let aggregation = req.base_params.aggregation;
     let date_range = req.base_params.date_range.as_ref();
     let group_by_interval = req.base_params.group_by_interval;
+    let group_by = req.group_by.map(|dimension| GroupBy {
+        dimension,
+        limit: req.group_by_limit.clamp(1, MAX_GROUP_BY_LIMIT),
+        include_missing: req.group_by_include_missing,
+    });
 
     // We expect the frontend to always provide a date range.
     // However, for smooth UX we default this to all time.
@@ -202,6 +480,7 @@ pub async fn get_traces_metrics(
                     end_time,
                     group_by_interval,
                     aggregation,
+                    group_by,
                 )
                 .await;
             } else {
@@ -216,6 +495,7 @@ pub async fn get_traces_metrics(
                     past_hours,
                     group_by_interval,
                     aggregation,
+                    group_by,
                 )
                 .await
             }
@@ -229,12 +509,98 @@ pub async fn get_traces_metrics(
                 interval.end_date,
                 group_by_interval,
                 aggregation,
+                group_by,
             )
             .await
         }
     }
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GetSpanMetricsParams {
+    metric: SpanMetric,
+    #[serde(default)]
+    filters: SpanMetricFilters,
+    /// Aggregation, date range and time interval
+    #[serde(flatten)]
+    base_params: GetMetricsQueryParams,
+}
+
+/// Get metrics computed over individual spans rather than whole traces,
+/// e.g. p95 latency of LLM spans calling a given model
+#[post("spans/metrics")]
+pub async fn get_spans_metrics(
+    params: web::Path<Uuid>,
+    clickhouse: web::Data<clickhouse::Client>,
+    req: web::Json<GetSpanMetricsParams>,
+) -> ResponseResult {
+    let project_id = params.into_inner();
+    let clickhouse = clickhouse.into_inner().as_ref().clone();
+    let req = req.into_inner();
+    let metric = req.metric;
+    let filters = req.filters;
+    let aggregation = req.base_params.aggregation;
+    let date_range = req.base_params.date_range.as_ref();
+    let group_by_interval = req.base_params.group_by_interval;
+
+    let defaulted_range =
+        date_range
+            .cloned()
+            .unwrap_or(DateRange::Relative(RelativeDateInterval {
+                past_hours: "all".to_string(),
+            }));
+
+    let values = match defaulted_range {
+        DateRange::Relative(interval) if interval.past_hours != "all" => {
+            let past_hours = interval
+                .past_hours
+                .parse::<i64>()
+                .map_err(|e| anyhow::anyhow!("Failed to parse past_hours as i64: {}", e))?;
+            ch::spans::get_span_metric_relative(
+                clickhouse,
+                group_by_interval,
+                project_id,
+                past_hours,
+                aggregation,
+                metric,
+                &filters,
+            )
+            .await?
+        }
+        DateRange::Relative(_) => {
+            let (start_time, end_time) =
+                get_bounds(&clickhouse, &project_id, "spans", "start_time").await?;
+            ch::spans::get_span_metric_absolute(
+                clickhouse,
+                group_by_interval,
+                project_id,
+                start_time,
+                end_time,
+                aggregation,
+                metric,
+                &filters,
+            )
+            .await?
+        }
+        DateRange::Absolute(interval) => {
+            ch::spans::get_span_metric_absolute(
+                clickhouse,
+                group_by_interval,
+                project_id,
+                interval.start_date,
+                interval.end_date,
+                aggregation,
+                metric,
+                &filters,
+            )
+            .await?
+        }
+    };
+
+    Ok(HttpResponse::Ok().json(values))
+}
+
 #[get("sessions")]
 pub async fn get_sessions(
     db: web::Data<DB>,
@@ -245,7 +611,9 @@ pub async fn get_sessions(
     let date_range = &params.date_range;
     let limit = params.page_size.unwrap_or(DEFAULT_PAGE_SIZE);
     let offset = limit * (params.page_number);
-    let filters = Filter::from_url_params(params.filter.clone());
+    let filters =
+        FilterExpression::from_url_params(params.filter.clone(), &db::trace::validate_trace_filter)
+            .map_err(|e| Error::invalid_request(Some(&e.to_string())))?;
     let sessions =
         db::trace::get_sessions(&db.pool, project_id, limit, offset, &filters, date_range).await?;
 
@@ -264,6 +632,33 @@ pub async fn get_sessions(
     Ok(HttpResponse::Ok().json(response))
 }
 
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SessionReplay {
+    session_id: String,
+    traces: Vec<SessionTrace>,
+    /// LLM calls of all traces flattened into one conversation
+    transcript: Vec<TranscriptMessage>,
+}
+
+#[get("sessions/{session_id}")]
+pub async fn get_session(db: web::Data<DB>, path: web::Path<(Uuid, String)>) -> ResponseResult {
+    let (project_id, session_id) = path.into_inner();
+
+    let traces = db::trace::get_session_traces(&db.pool, project_id, &session_id).await?;
+    if traces.is_empty() {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    let llm_spans = db::spans::get_session_llm_spans(&db.pool, project_id, &session_id).await?;
+
+    let response = SessionReplay {
+        session_id,
+        traces,
+        transcript: build_transcript(llm_spans),
+    };
+    Ok(HttpResponse::Ok().json(response))
+}
+
 async fn get_metrics_relative_time(
     clickhouse: clickhouse::Client,
     metric: TraceMetric,
@@ -271,87 +666,60 @@ async fn get_metrics_relative_time(
     past_hours: i64,
     group_by_interval: GroupByInterval,
     aggregation: Aggregation,
+    group_by: Option<GroupBy>,
 ) -> ResponseResult {
-    match metric {
-        TraceMetric::TraceCount => match aggregation {
-            Aggregation::Average => {
-                return Err(anyhow::anyhow!(
-                    "Average grouping is not supported for traceCount metric"
-                )
-                .into());
-            }
-            Aggregation::Total => {
-                let values = ch::spans::get_total_trace_count_metrics_relative(
-                    clickhouse,
-                    group_by_interval,
-                    project_id,
-                    past_hours,
-                )
-                .await?;
+    if let Some(group_by) = group_by {
+        let rows = ch::spans::get_trace_metric_series_relative(
+            clickhouse,
+            group_by_interval,
+            project_id,
+            past_hours,
+            aggregation,
+            metric,
+            group_by,
+        )
+        .await?;
+        return Ok(HttpResponse::Ok().json(ch::group_into_series(rows)));
+    }
 
-                Ok(HttpResponse::Ok().json(values))
-            }
-        },
-        TraceMetric::TraceLatencySeconds => match aggregation {
-            Aggregation::Total => {
-                return Err(anyhow::anyhow!(
-                    "Total grouping is not supported for traceLatency metric"
-                )
-                .into());
-            }
-            Aggregation::Average => {
-                let values = ch::spans::get_trace_latency_seconds_metrics_relative(
-                    clickhouse,
-                    group_by_interval,
-                    project_id,
-                    past_hours,
-                    aggregation,
-                )
-                .await?;
+    match (metric, aggregation) {
+        // Counts are returned as integers
+        (TraceMetric::TraceCount, Aggregation::Total) => {
+            let values = ch::spans::get_total_trace_count_metrics_relative(
+                clickhouse,
+                group_by_interval,
+                project_id,
+                past_hours,
+            )
+            .await?;
 
-                Ok(HttpResponse::Ok().json(values))
-            }
-        },
-        TraceMetric::TotalTokenCount => match aggregation {
-            Aggregation::Total => {
-                let values = ch::spans::get_total_token_count_metrics_relative(
-                    clickhouse,
-                    group_by_interval,
-                    project_id,
-                    past_hours,
-                    aggregation,
-                )
-                .await?;
+            Ok(HttpResponse::Ok().json(values))
+        }
+        (TraceMetric::TotalTokenCount, Aggregation::Total) => {
+            let values = ch::spans::get_total_token_count_metrics_relative(
+                clickhouse,
+                group_by_interval,
+                project_id,
+                past_hours,
+                Aggregation::Total,
+            )
+            .await?;
 
-                Ok(HttpResponse::Ok().json(values))
-            }
-            Aggregation::Average => {
-                return Err(anyhow::anyhow!(
-                    "Average grouping is not supported for totalTokenCount metric"
-                )
-                .into());
-            }
-        },
-        TraceMetric::CostUsd => match aggregation {
-            Aggregation::Total => {
-                let values = ch::spans::get_cost_usd_metrics_relative(
-                    clickhouse,
-                    group_by_interval,
-                    project_id,
-                    past_hours,
-                    aggregation,
-                )
-                .await?;
+            Ok(HttpResponse::Ok().json(values))
+        }
+        (metric, aggregation) => {
+            let values = ch::spans::get_trace_metric_relative(
+                clickhouse,
+                group_by_interval,
+                project_id,
+                past_hours,
+                aggregation,
+                metric,
+            )
+            .await?;
 
-                Ok(HttpResponse::Ok().json(values))
-            }
-            Aggregation::Average => {
-                return Err(anyhow::anyhow!(
-                    "Average grouping is not supported for costUsd metric"
-                )
-                .into());
-            }
-        },
+            Ok(HttpResponse::Ok().json(values))
+        }
     }
 }
 
@@ -363,90 +731,63 @@ async fn get_metrics_absolute_time(
     end_time: DateTime<Utc>,
     group_by_interval: GroupByInterval,
     aggregation: Aggregation,
+    group_by: Option<GroupBy>,
 ) -> ResponseResult {
-    match metric {
-        TraceMetric::TraceCount => match aggregation {
-            Aggregation::Average => {
-                return Err(anyhow::anyhow!(
-                    "Average grouping is not supported for traceCount metric"
-                )
-                .into());
-            }
-            Aggregation::Total => {
-                let values = ch::spans::get_total_trace_count_metrics_absolute(
-                    clickhouse,
-                    group_by_interval,
-                    project_id,
-                    start_time,
-                    end_time,
-                )
-                .await?;
+    if let Some(group_by) = group_by {
+        let rows = ch::spans::get_trace_metric_series_absolute(
+            clickhouse,
+            group_by_interval,
+            project_id,
+            start_time,
+            end_time,
+            aggregation,
+            metric,
+            group_by,
+        )
+        .await?;
+        return Ok(HttpResponse::Ok().json(ch::group_into_series(rows)));
+    }
 
-                Ok(HttpResponse::Ok().json(values))
-            }
-        },
-        TraceMetric::TraceLatencySeconds => match aggregation {
-            Aggregation::Total => {
-                return Err(anyhow::anyhow!(
-                    "Total grouping is not supported for traceLatency metric"
-                )
-                .into());
-            }
-            Aggregation::Average => {
-                let values = ch::spans::get_trace_latency_seconds_metrics_absolute(
-                    clickhouse,
-                    group_by_interval,
-                    project_id,
-                    start_time,
-                    end_time,
-                    aggregation,
-                )
-                .await?;
+    match (metric, aggregation) {
+        // Counts are returned as integers
+        (TraceMetric::TraceCount, Aggregation::Total) => {
+            let values = ch::spans::get_total_trace_count_metrics_absolute(
+                clickhouse,
+                group_by_interval,
+                project_id,
+                start_time,
+                end_time,
+            )
+            .await?;
 
-                Ok(HttpResponse::Ok().json(values))
-            }
-        },
-        TraceMetric::TotalTokenCount => match aggregation {
-            Aggregation::Total => {
-                let values = ch::spans::get_total_token_count_metrics_absolute(
-                    clickhouse,
-                    group_by_interval,
-                    project_id,
-                    start_time,
-                    end_time,
-                    aggregation,
-                )
-                .await?;
+            Ok(HttpResponse::Ok().json(values))
+        }
+        (TraceMetric::TotalTokenCount, Aggregation::Total) => {
+            let values = ch::spans::get_total_token_count_metrics_absolute(
+                clickhouse,
+                group_by_interval,
+                project_id,
+                start_time,
+                end_time,
+                Aggregation::Total,
+            )
+            .await?;
 
-                Ok(HttpResponse::Ok().json(values))
-            }
-            Aggregation::Average => {
-                return Err(anyhow::anyhow!(
-                    "Average grouping is not supported for totalTokenCount metric"
-                )
-                .into());
-            }
-        },
-        TraceMetric::CostUsd => match aggregation {
-            Aggregation::Total => {
-                let values = ch::spans::get_cost_usd_metrics_absolute(
-                    clickhouse,
-                    group_by_interval,
-                    project_id,
-                    start_time,
-                    end_time,
-                    aggregation,
-                )
-                .await?;
+            Ok(HttpResponse::Ok().json(values))
+        }
+        (metric, aggregation) => {
+            let values = ch::spans::get_trace_metric_absolute(
+                clickhouse,
+                group_by_interval,
+                project_id,
+                start_time,
+                end_time,
+                aggregation,
+                metric,
+            )
+            .await?;
 
-                Ok(HttpResponse::Ok().json(values))
-            }
-            Aggregation::Average => {
-                return Err(anyhow::anyhow!(
-                    "Average grouping is not supported for costUsd metric"
-                )
-                .into());
-            }
-        },
+            Ok(HttpResponse::Ok().json(values))
+        }
     }
 }
@@ -17,6 +17,7 @@ pub struct TraceAttributes {
     /// Total costis not calculated on this struct and must be set manually
     pub cost: Option<f64>,
     pub success: Option<bool>,
+    pub has_error: Option<bool>,
     pub session_id: Option<String>,
     pub user_id: Option<String>,
     pub trace_type: Option<TraceType>,
@@ -76,4 +77,12 @@ impl TraceAttributes {
     pub fn update_trace_type(&mut self, trace_type: Option<TraceType>) {
         self.trace_type = trace_type;
     }
+
+    /// An errored span marks the whole trace as errored, and other spans leave it as is
+    pub fn update_has_error(&mut self, has_error: bool) {
+        if has_error {
+            self.has_error = Some(true);
+            self.success = Some(false);
+        }
+    }
 }
@@ -0,0 +1,198 @@
+//! This module batches spans before writing them to Clickhouse.
+//!
+//! Consumers hand over a span together with the acker of its queue delivery.
+//! The delivery is acked only after the batch containing the span is inserted.
+//! If the insert keeps failing, the delivery goes through the same retry and dead-letter
+//! policy as spans which fail to be recorded.
+
+use std::{env, sync::Arc, time::Duration};
+
+use backoff::ExponentialBackoffBuilder;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::{
+    ch::{self, spans::CHSpan},
+    db::spans::Span,
+    mq::{MessageQueueAcker, RetryPolicy},
+};
+
+use super::spans::SpanUsage;
+
+const DEFAULT_BATCH_SIZE: usize = 1000;
+const DEFAULT_FLUSH_INTERVAL_MS: u64 = 1000;
+const DEFAULT_MAX_RETRY_ELAPSED_MS: u64 = 30_000;
+
+pub struct SpanWriteRequest {
+    pub span: CHSpan,
+    pub acker: Arc<dyn MessageQueueAcker>,
+}
+
+#[derive(Clone, Copy)]
+pub struct BatchWriterConfig {
+    /// Flush once this many spans are buffered
+    pub batch_size: usize,
+    /// Flush whatever is buffered at least this often
+    pub flush_interval: Duration,
+    /// Give up retrying a failed insert after this long
+    pub max_retry_elapsed: Duration,
+}
+
+impl BatchWriterConfig {
+    pub fn from_env() -> Self {
+        Self {
+            batch_size: env::var("CH_SPANS_BATCH_SIZE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_BATCH_SIZE),
+            flush_interval: Duration::from_millis(
+                env::var("CH_SPANS_FLUSH_INTERVAL_MS")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(DEFAULT_FLUSH_INTERVAL_MS),
+            ),
+            max_retry_elapsed: Duration::from_millis(
+                env::var("CH_SPANS_MAX_RETRY_ELAPSED_MS")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(DEFAULT_MAX_RETRY_ELAPSED_MS),
+            ),
+        }
+    }
+}
+
+/// Spawns the writer task and returns the sender to it.
+///
+/// The channel is bounded by the batch size, so consumers wait on `send`
+/// while a batch is being flushed or retried.
+pub fn spawn_span_batch_writer(
+    clickhouse: clickhouse::Client,
+    config: BatchWriterConfig,
+    retry_policy: RetryPolicy,
+) -> mpsc::Sender<SpanWriteRequest> {
+    // Deliveries are acked after the insert, so it must not return before the data is written
+    let clickhouse = clickhouse.with_option("wait_for_async_insert", "1");
+    let (tx, rx) = mpsc::channel(config.batch_size.max(1));
+    tokio::spawn(run_span_batch_writer(rx, clickhouse, config, retry_policy));
+    tx
+}
+
+/// Hands the span over to the Clickhouse writer, which acks the delivery once it is written.
+/// Without Clickhouse, the delivery is acked right away.
+pub async fn write_span_to_clickhouse(
+    span_writer: &Option<mpsc::Sender<SpanWriteRequest>>,
+    span: &Span,
+    span_usage: SpanUsage,
+    project_id: Uuid,
+    acker: Arc<dyn MessageQueueAcker>,
+) {
+    let Some(span_writer) = span_writer else {
+        let _ = acker
+            .ack()
+            .await
+            .map_err(|e| log::error!("Failed to ack span delivery: {:?}", e));
+        return;
+    };
+
+    let write_request = SpanWriteRequest {
+        span: CHSpan::from_db_span(span, span_usage, project_id),
+        acker: acker.clone(),
+    };
+    if span_writer.send(write_request).await.is_err() {
+        log::error!(
+            "Clickhouse span writer is closed. span_id [{}], project_id [{}]",
+            span.span_id,
+            project_id,
+        );
+        let _ = acker
+            .nack(true)
+            .await
+            .map_err(|e| log::error!("Failed to nack span delivery: {:?}", e));
+    }
+}
+
+async fn run_span_batch_writer(
+    mut rx: mpsc::Receiver<SpanWriteRequest>,
+    clickhouse: clickhouse::Client,
+    config: BatchWriterConfig,
+    retry_policy: RetryPolicy,
+) {
+    let mut batch = Vec::with_capacity(config.batch_size);
+    let mut interval = tokio::time::interval(config.flush_interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            request = rx.recv() => match request {
+                Some(request) => {
+                    batch.push(request);
+                    if batch.len() >= config.batch_size {
+                        flush(&clickhouse, &mut batch, &config, &retry_policy).await;
+                    }
+                }
+                None => {
+                    // All consumers are gone, write what is left and stop
+                    flush(&clickhouse, &mut batch, &config, &retry_policy).await;
+                    break;
+                }
+            },
+            _ = interval.tick() => {
+                flush(&clickhouse, &mut batch, &config, &retry_policy).await;
+            }
+        }
+    }
+}
+
+async fn flush(
+    clickhouse: &clickhouse::Client,
+    batch: &mut Vec<SpanWriteRequest>,
+    config: &BatchWriterConfig,
+    retry_policy: &RetryPolicy,
+) {
+    if batch.is_empty() {
+        return;
+    }
+
+    let (spans, ackers): (Vec<CHSpan>, Vec<Arc<dyn MessageQueueAcker>>) = std::mem::take(batch)
+        .into_iter()
+        .map(|request| (request.span, request.acker))
+        .unzip();
+
+    let backoff = ExponentialBackoffBuilder::new()
+        .with_max_elapsed_time(Some(config.max_retry_elapsed))
+        .build();
+    let insert = || async {
+        ch::spans::insert_spans(clickhouse.clone(), &spans)
+            .await
+            .map_err(|e| {
+                log::warn!(
+                    "Failed to insert batch of {} spans into Clickhouse, retrying: {:?}",
+                    spans.len(),
+                    e
+                );
+                backoff::Error::transient(e)
+            })
+    };
+
+    match backoff::future::retry(backoff, insert).await {
+        Ok(_) => {
+            for acker in ackers {
+                let _ = acker
+                    .ack()
+                    .await
+                    .map_err(|e| log::error!("Failed to ack span delivery: {:?}", e));
+            }
+        }
+        Err(e) => {
+            log::error!(
+                "Giving up inserting batch of {} spans into Clickhouse: {:?}",
+                spans.len(),
+                e
+            );
+            let error = format!("Failed to insert span into Clickhouse: {}", e);
+            for acker in ackers {
+                acker.retry_or_dead_letter(retry_policy, &error).await;
+            }
+        }
+    }
+}
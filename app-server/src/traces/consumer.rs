@@ -1,19 +1,31 @@
-//! This module reads spans from RabbitMQ and processes them: writes to DB,
+//! This module reads spans from the message queue and processes them: writes to DB,
 //! clickhouse, and semantic search.
 
 use std::sync::Arc;
 
-use futures::StreamExt;
-use lapin::{options::BasicConsumeOptions, options::*, types::FieldTable, Connection};
+use uuid::Uuid;
 
-use super::{OBSERVATIONS_EXCHANGE, OBSERVATIONS_QUEUE, OBSERVATIONS_ROUTING_KEY};
+use super::{
+    batch_writer::{spawn_span_batch_writer, write_span_to_clickhouse, BatchWriterConfig},
+    dead_letter::span_retry_policy,
+    events::create_events,
+    sampling::{get_sampling_rules, PendingSpan, TailSampler},
+    OBSERVATIONS_EXCHANGE, OBSERVATIONS_QUEUE, OBSERVATIONS_ROUTING_KEY,
+};
 use crate::{
     api::v1::traces::RabbitMqSpanMessage,
     cache::Cache,
-    ch::{self, spans::CHSpan},
     chunk,
-    db::{labels::get_registered_label_classes_for_path, spans::Span, stats, DB},
+    db::{
+        events::{EventObservation, EventSource},
+        labels::get_registered_label_classes_for_path,
+        sampling_rules::SamplingRules,
+        span_events::{record_span_events, SpanEvent},
+        spans::Span,
+        stats, DB,
+    },
     features::{is_feature_enabled, Feature},
+    mq::{MessageQueue, RetryPolicy},
     pipeline::runner::PipelineRunner,
     semantic_search::SemanticSearch,
     traces::{evaluators::run_evaluator, utils::record_span_to_db},
@@ -24,9 +36,10 @@ pub async fn process_queue_spans(
     db: Arc<DB>,
     cache: Arc<Cache>,
     semantic_search: Arc<dyn SemanticSearch>,
-    rabbitmq_connection: Option<Arc<Connection>>,
+    queue: Arc<dyn MessageQueue>,
     clickhouse: clickhouse::Client,
     chunker_runner: Arc<chunk::runner::ChunkerRunner>,
+    tail_sampler: Arc<TailSampler>,
 ) {
     loop {
         inner_process_queue_spans(
@@ -34,12 +47,13 @@ pub async fn process_queue_spans(
             db.clone(),
             cache.clone(),
             semantic_search.clone(),
-            rabbitmq_connection.clone(),
+            queue.clone(),
             clickhouse.clone(),
             chunker_runner.clone(),
+            tail_sampler.clone(),
         )
         .await;
-        log::warn!("Span listener exited. Creating a new RabbitMQ channel...");
+        log::warn!("Span listener exited. Creating a new queue receiver...");
     }
 }
 
@@ -48,72 +62,69 @@ async fn inner_process_queue_spans(
     db: Arc<DB>,
     cache: Arc<Cache>,
     _semantic_search: Arc<dyn SemanticSearch>,
-    rabbitmq_connection: Option<Arc<Connection>>,
+    queue: Arc<dyn MessageQueue>,
     clickhouse: clickhouse::Client,
     _chunker_runner: Arc<chunk::runner::ChunkerRunner>,
+    tail_sampler: Arc<TailSampler>,
 ) {
-    if !is_feature_enabled(Feature::FullBuild) {
-        return;
-    }
-    // Safe to unwrap because we checked is_feature_enabled above
-    let channel = rabbitmq_connection.unwrap().create_channel().await.unwrap();
-
-    channel
-        .queue_bind(
+    let batch_writer_config = BatchWriterConfig::from_env();
+    // Deliveries stay unacked until their batch is written to Clickhouse, or while their spans
+    // are held by the tail sampler, so allow enough of them in flight for both,
+    // but not unboundedly many.
+    let prefetch_count = (batch_writer_config.batch_size * 2
+        + tail_sampler.held_spans_per_listener())
+    .min(u16::MAX as usize) as u16;
+    let mut receiver = queue
+        .get_receiver(
             OBSERVATIONS_QUEUE,
             OBSERVATIONS_EXCHANGE,
             OBSERVATIONS_ROUTING_KEY,
-            QueueBindOptions::default(),
-            FieldTable::default(),
+            prefetch_count,
         )
         .await
         .unwrap();
 
-    let mut consumer = channel
-        .basic_consume(
-            OBSERVATIONS_QUEUE,
-            OBSERVATIONS_ROUTING_KEY,
-            BasicConsumeOptions::default(),
-            FieldTable::default(),
-        )
-        .await
-        .unwrap();
+    let retry_policy = span_retry_policy();
+    // Dropped together with the receiver when this listener exits,
+    // which makes the writer flush the remaining spans and stop.
+    // Without Clickhouse, deliveries are acked as soon as the span is in the DB.
+    let span_writer = is_feature_enabled(Feature::FullBuild)
+        .then(|| spawn_span_batch_writer(clickhouse.clone(), batch_writer_config, retry_policy));
 
-    log::info!("Started processing spans from RabbitMQ");
+    log::info!("Started processing spans from the queue");
 
-    while let Some(delivery) = consumer.next().await {
+    while let Some(delivery) = receiver.receive().await {
         let Ok(delivery) = delivery else {
-            log::error!("Failed to get delivery from RabbitMQ. Continuing...");
+            log::error!("Failed to get delivery from the queue. Continuing...");
             continue;
         };
+        let acker = delivery.acker;
 
-        let Ok(payload) = String::from_utf8(delivery.data.clone()) else {
-            log::error!("Failed to parse delivery data as UTF-8. Continuing...");
+        let Ok(payload) = String::from_utf8(delivery.data) else {
+            log::error!("Failed to parse delivery data as UTF-8. Dead-lettering...");
+            acker
+                .dead_letter(&retry_policy, "Delivery data is not valid UTF-8")
+                .await;
             continue;
         };
 
-        let Ok(rabbitmq_span_message) = serde_json::from_str::<RabbitMqSpanMessage>(&payload)
-        else {
-            log::error!("Failed to parse delivery data as `RabbitMqSpanMessage`. Continuing...");
-            continue;
+        let rabbitmq_span_message = match serde_json::from_str::<RabbitMqSpanMessage>(&payload) {
+            Ok(message) => message,
+            Err(e) => {
+                log::error!(
+                    "Failed to parse delivery data as `RabbitMqSpanMessage`. Dead-lettering..."
+                );
+                acker
+                    .dead_letter(
+                        &retry_policy,
+                        &format!("Failed to parse `RabbitMqSpanMessage`: {}", e),
+                    )
+                    .await;
+                continue;
+            }
         };
 
-        let mut span: Span = rabbitmq_span_message.span;
-        let events_count = rabbitmq_span_message.events.len();
-
-        if let Err(e) = stats::add_spans_and_events_to_project_usage_stats(
-            &db.pool,
-            &rabbitmq_span_message.project_id,
-            1,
-            events_count as i64,
-        )
-        .await
-        {
-            log::error!(
-                "Failed to add spans and events to project usage stats: {:?}",
-                e
-            );
-        }
+        let span: Span = rabbitmq_span_message.span;
 
         if is_feature_enabled(Feature::UsageLimit) {
             match super::limits::update_workspace_limit_exceeded_by_project_id(
@@ -133,16 +144,17 @@ async fn inner_process_queue_spans(
                 Ok(limits_exceeded) => {
                     // TODO: do the same for events
                     if limits_exceeded.spans {
-                        let _ = delivery
-                            .ack(BasicAckOptions::default())
+                        let _ = acker
+                            .ack()
                             .await
-                            .map_err(|e| log::error!("Failed to ack RabbitMQ delivery: {:?}", e));
+                            .map_err(|e| log::error!("Failed to ack span delivery: {:?}", e));
                         continue;
                     }
                 }
             }
         }
 
+        let project_id = rabbitmq_span_message.project_id;
         let span_usage = super::utils::get_llm_usage_for_span(
             &mut span.get_attributes(),
             db.clone(),
@@ -150,73 +162,205 @@ async fn inner_process_queue_spans(
         )
         .await;
 
-        if let Err(e) = record_span_to_db(
+        let sampling_rules = get_sampling_rules(db.clone(), cache.clone(), &project_id)
+            .await
+            .unwrap_or_else(|e| {
+                log::error!(
+                    "Failed to get sampling rules. project_id [{}]: {:?}",
+                    project_id,
+                    e
+                );
+                SamplingRules::default()
+            });
+        let is_sampled = tail_sampler
+            .sample(&sampling_rules, &span, &span_usage)
+            .await;
+        let pending_span = PendingSpan {
+            project_id,
+            span,
+            span_usage,
+            span_events: rabbitmq_span_message.span_events,
+            events: rabbitmq_span_message.events,
+            acker,
+            span_writer: span_writer.clone(),
+        };
+
+        if !is_sampled {
+            // Held until the trace is either kept or dropped, see `TailSampler`
+            if let Some(pending_span) = tail_sampler.hold(pending_span).await {
+                record_span(
+                    pipeline_runner.clone(),
+                    db.clone(),
+                    clickhouse.clone(),
+                    &retry_policy,
+                    pending_span,
+                )
+                .await;
+            }
+            continue;
+        }
+
+        // The trace may have been kept by this span, after its earlier spans were dropped
+        for held_span in tail_sampler.take_held(&pending_span.span.trace_id).await {
+            record_span(
+                pipeline_runner.clone(),
+                db.clone(),
+                clickhouse.clone(),
+                &retry_policy,
+                held_span,
+            )
+            .await;
+        }
+
+        record_span(
+            pipeline_runner.clone(),
             db.clone(),
-            &span_usage,
-            &rabbitmq_span_message.project_id,
-            &mut span,
+            clickhouse.clone(),
+            &retry_policy,
+            pending_span,
         )
-        .await
-        {
+        .await;
+    }
+
+    log::warn!("Queue closed. Shutting down span listener");
+}
+
+/// Records what comes along with a newly recorded span, and counts them towards the usage
+async fn record_span_events_and_events(
+    db: Arc<DB>,
+    clickhouse: clickhouse::Client,
+    project_id: Uuid,
+    span: &Span,
+    span_events: &[SpanEvent],
+    events: Vec<EventObservation>,
+) {
+    if let Err(e) = stats::add_spans_and_events_to_project_usage_stats(
+        &db.pool,
+        &project_id,
+        1,
+        events.len() as i64,
+    )
+    .await
+    {
+        log::error!(
+            "Failed to add spans and events to project usage stats: {:?}",
+            e
+        );
+    }
+
+    if let Err(e) = record_span_events(&db.pool, project_id, span_events).await {
+        log::error!(
+            "Failed to record span events. span_id [{}], project_id [{}]: {:?}",
+            span.span_id,
+            project_id,
+            e
+        );
+    }
+
+    if !events.is_empty() {
+        if let Err(e) = create_events(db, clickhouse, events, EventSource::CODE, project_id).await {
             log::error!(
-                "Failed to record span. span_id [{}], project_id [{}]: {:?}",
+                "Failed to create events. span_id [{}], project_id [{}]: {:?}",
                 span.span_id,
-                rabbitmq_span_message.project_id,
+                project_id,
                 e
             );
-        } else {
-            // ack the message as soon as the span is recorded
-            let _ = delivery
-                .ack(BasicAckOptions::default())
-                .await
-                .map_err(|e| log::error!("Failed to ack RabbitMQ delivery: {:?}", e));
         }
+    }
+}
+
+/// Records the span in the DB, and then hands it over to the Clickhouse writer,
+/// which acks its delivery. If the span cannot be recorded, its delivery is retried.
+async fn record_span(
+    pipeline_runner: Arc<PipelineRunner>,
+    db: Arc<DB>,
+    clickhouse: clickhouse::Client,
+    retry_policy: &RetryPolicy,
+    pending_span: PendingSpan,
+) {
+    let PendingSpan {
+        project_id,
+        mut span,
+        span_usage,
+        span_events,
+        events,
+        acker,
+        span_writer,
+    } = pending_span;
 
-        let ch_span = CHSpan::from_db_span(&span, span_usage, rabbitmq_span_message.project_id);
-        // TODO: Queue batches and send them every 1-2 seconds
-        let insert_span_res = ch::spans::insert_span(clickhouse.clone(), &ch_span).await;
-        if let Err(e) = insert_span_res {
+    let is_new = match record_span_to_db(db.clone(), &span_usage, &project_id, &mut span).await {
+        Ok(is_new) => is_new,
+        Err(e) => {
             log::error!(
-                "Failed to insert span into Clickhouse. span_id [{}], project_id [{}]: {:?}",
+                "Failed to record span. span_id [{}], project_id [{}]: {:?}",
                 span.span_id,
-                rabbitmq_span_message.project_id,
+                project_id,
+                e
+            );
+            acker
+                .retry_or_dead_letter(retry_policy, &format!("Failed to record span: {}", e))
+                .await;
+            return;
+        }
+    };
+
+    // A retried delivery is only written to Clickhouse again,
+    // and evaluators do not run on it twice
+    if is_new {
+        record_span_events_and_events(
+            db.clone(),
+            clickhouse,
+            project_id,
+            &span,
+            &span_events,
+            events,
+        )
+        .await;
+    }
+
+    write_span_to_clickhouse(&span_writer, &span, span_usage, project_id, acker).await;
+
+    if is_new {
+        run_registered_evaluators(pipeline_runner, db, project_id, &span).await;
+    }
+}
+
+async fn run_registered_evaluators(
+    pipeline_runner: Arc<PipelineRunner>,
+    db: Arc<DB>,
+    project_id: Uuid,
+    span: &Span,
+) {
+    let registered_label_classes = match get_registered_label_classes_for_path(
+        &db.pool,
+        project_id,
+        &span.get_attributes().path().unwrap_or_default(),
+    )
+    .await
+    {
+        Ok(classes) => classes,
+        Err(e) => {
+            log::error!(
+                "Failed to get registered label classes. project_id [{}]: {:?}",
+                project_id,
                 e
             );
+            Vec::new() // Return an empty vector if there's an error
         }
+    };
 
-        let registered_label_classes = match get_registered_label_classes_for_path(
-            &db.pool,
-            rabbitmq_span_message.project_id,
-            &span.get_attributes().path().unwrap_or_default(),
+    for registered_label_class in registered_label_classes {
+        match run_evaluator(
+            pipeline_runner.clone(),
+            project_id,
+            registered_label_class.label_class_id,
+            span,
+            db.clone(),
         )
         .await
         {
-            Ok(classes) => classes,
-            Err(e) => {
-                log::error!(
-                    "Failed to get registered label classes. project_id [{}]: {:?}",
-                    rabbitmq_span_message.project_id,
-                    e
-                );
-                Vec::new() // Return an empty vector if there's an error
-            }
-        };
-
-        for registered_label_class in registered_label_classes {
-            match run_evaluator(
-                pipeline_runner.clone(),
-                rabbitmq_span_message.project_id,
-                registered_label_class.label_class_id,
-                &span,
-                db.clone(),
-            )
-            .await
-            {
-                Ok(_) => (),
-                Err(e) => log::error!("Failed to run evaluator: {:?}", e),
-            }
+            Ok(_) => (),
+            Err(e) => log::error!("Failed to run evaluator: {:?}", e),
         }
     }
-
-    log::warn!("RabbitMQ closed connection. Shutting down span listener");
 }
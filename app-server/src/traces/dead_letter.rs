@@ -0,0 +1,296 @@
+//! Retries and dead-lettering of span messages that fail processing.
+//!
+//! Failed messages are republished to a retry exchange, whose queue holds them
+//! for the retry delay and then dead-letters them back to `OBSERVATIONS_EXCHANGE`.
+//! Once the retries are exhausted, or if the message cannot be parsed at all,
+//! it is published to the dead-letter exchange, from which it is moved into the
+//! `dead_lettered_spans` table, where it stays until replayed.
+
+use std::{env, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use lapin::{
+    message::Delivery,
+    options::{
+        BasicAckOptions, BasicConsumeOptions, BasicNackOptions, BasicQosOptions,
+        ExchangeDeclareOptions, QueueBindOptions, QueueDeclareOptions,
+    },
+    types::{AMQPValue, FieldTable},
+    Channel, Connection,
+};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    api::v1::traces::RabbitMqSpanMessage,
+    db::{
+        self,
+        dead_letters::{DeadLetter, DeadLetterCursor},
+        DB,
+    },
+    mq::{rabbit, MessageQueue, RetryPolicy},
+};
+
+use super::{OBSERVATIONS_EXCHANGE, OBSERVATIONS_ROUTING_KEY};
+
+pub const OBSERVATIONS_RETRY_EXCHANGE: &str = "observations_retry_exchange";
+pub const OBSERVATIONS_RETRY_QUEUE: &str = "observations_retry_queue";
+pub const OBSERVATIONS_DEAD_LETTER_EXCHANGE: &str = "observations_dead_letter_exchange";
+pub const OBSERVATIONS_DEAD_LETTER_QUEUE: &str = "observations_dead_letter_queue";
+
+const DEFAULT_MAX_RETRIES: i64 = 3;
+const DEFAULT_RETRY_DELAY_MS: u64 = 5000;
+const STORE_DEAD_LETTERS_PREFETCH: u16 = 100;
+const STORE_DEAD_LETTERS_RETRY_DELAY: Duration = Duration::from_secs(5);
+/// Dead letters replayed per transaction
+const REPLAY_BATCH_SIZE: i64 = 500;
+
+pub fn span_retry_policy() -> RetryPolicy {
+    RetryPolicy {
+        max_retries: env::var("SPAN_PROCESSING_MAX_RETRIES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_MAX_RETRIES),
+        retry_delay: Duration::from_millis(
+            env::var("SPAN_PROCESSING_RETRY_DELAY_MS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_RETRY_DELAY_MS),
+        ),
+        retry_exchange: OBSERVATIONS_RETRY_EXCHANGE,
+        dead_letter_exchange: OBSERVATIONS_DEAD_LETTER_EXCHANGE,
+    }
+}
+
+pub async fn declare_dead_letter_topology(channel: &Channel) -> Result<()> {
+    channel
+        .exchange_declare(
+            OBSERVATIONS_RETRY_EXCHANGE,
+            lapin::ExchangeKind::Fanout,
+            ExchangeDeclareOptions::default(),
+            FieldTable::default(),
+        )
+        .await?;
+
+    let mut retry_queue_args = FieldTable::default();
+    retry_queue_args.insert(
+        "x-dead-letter-exchange".into(),
+        AMQPValue::LongString(OBSERVATIONS_EXCHANGE.into()),
+    );
+    channel
+        .queue_declare(
+            OBSERVATIONS_RETRY_QUEUE,
+            QueueDeclareOptions::default(),
+            retry_queue_args,
+        )
+        .await?;
+    channel
+        .queue_bind(
+            OBSERVATIONS_RETRY_QUEUE,
+            OBSERVATIONS_RETRY_EXCHANGE,
+            OBSERVATIONS_ROUTING_KEY,
+            QueueBindOptions::default(),
+            FieldTable::default(),
+        )
+        .await?;
+
+    channel
+        .exchange_declare(
+            OBSERVATIONS_DEAD_LETTER_EXCHANGE,
+            lapin::ExchangeKind::Fanout,
+            ExchangeDeclareOptions::default(),
+            FieldTable::default(),
+        )
+        .await?;
+    channel
+        .queue_declare(
+            OBSERVATIONS_DEAD_LETTER_QUEUE,
+            QueueDeclareOptions::default(),
+            FieldTable::default(),
+        )
+        .await?;
+    channel
+        .queue_bind(
+            OBSERVATIONS_DEAD_LETTER_QUEUE,
+            OBSERVATIONS_DEAD_LETTER_EXCHANGE,
+            OBSERVATIONS_ROUTING_KEY,
+            QueueBindOptions::default(),
+            FieldTable::default(),
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetteredSpan {
+    pub id: Uuid,
+    pub dead_lettered_at: DateTime<Utc>,
+    pub retry_count: i64,
+    pub error: Option<String>,
+    pub message: Option<RabbitMqSpanMessage>,
+    /// Original payload, only present if it could not be parsed as `RabbitMqSpanMessage`
+    pub raw_payload: Option<String>,
+}
+
+impl From<DeadLetter> for DeadLetteredSpan {
+    fn from(dead_letter: DeadLetter) -> Self {
+        let message = serde_json::from_str::<RabbitMqSpanMessage>(&dead_letter.payload).ok();
+        let raw_payload = match message {
+            Some(_) => None,
+            None => Some(dead_letter.payload),
+        };
+
+        Self {
+            id: dead_letter.id,
+            dead_lettered_at: dead_letter.dead_lettered_at,
+            retry_count: dead_letter.retry_count,
+            error: dead_letter.error,
+            message,
+            raw_payload,
+        }
+    }
+}
+
+fn dead_letter_from_delivery(delivery: &Delivery) -> DeadLetter {
+    DeadLetter {
+        // Set when the message is dead-lettered, so that redeliveries are stored only once
+        id: delivery
+            .properties
+            .message_id()
+            .as_ref()
+            .and_then(|id| Uuid::parse_str(id.as_str()).ok())
+            .unwrap_or_else(Uuid::new_v4),
+        dead_lettered_at: delivery
+            .properties
+            .timestamp()
+            .and_then(|ts| DateTime::from_timestamp(ts as i64, 0))
+            .unwrap_or_else(Utc::now),
+        retry_count: rabbit::get_retry_count(delivery),
+        error: rabbit::get_error(delivery),
+        payload: String::from_utf8_lossy(&delivery.data).to_string(),
+    }
+}
+
+/// Moves messages from the dead-letter queue into the `dead_lettered_spans` table,
+/// where they can be paged through and replayed by id. Runs forever.
+pub async fn store_dead_letters(connection: Arc<Connection>, db: Arc<DB>) {
+    loop {
+        if let Err(e) = consume_dead_letters(&connection, &db).await {
+            log::error!("Failed to consume dead-lettered messages: {:?}", e);
+        }
+        tokio::time::sleep(STORE_DEAD_LETTERS_RETRY_DELAY).await;
+    }
+}
+
+async fn consume_dead_letters(connection: &Connection, db: &DB) -> Result<()> {
+    let channel = connection.create_channel().await?;
+    channel
+        .basic_qos(STORE_DEAD_LETTERS_PREFETCH, BasicQosOptions::default())
+        .await?;
+    let mut consumer = channel
+        .basic_consume(
+            OBSERVATIONS_DEAD_LETTER_QUEUE,
+            "",
+            BasicConsumeOptions::default(),
+            FieldTable::default(),
+        )
+        .await?;
+
+    while let Some(delivery) = consumer.next().await {
+        let delivery = delivery?;
+        let dead_letter = dead_letter_from_delivery(&delivery);
+        match db::dead_letters::insert_dead_letter(&db.pool, &dead_letter).await {
+            Ok(_) => delivery.ack(BasicAckOptions::default()).await?,
+            Err(e) => {
+                log::error!("Failed to store dead-lettered message: {:?}", e);
+                delivery
+                    .nack(BasicNackOptions {
+                        requeue: true,
+                        ..Default::default()
+                    })
+                    .await?;
+                tokio::time::sleep(STORE_DEAD_LETTERS_RETRY_DELAY).await;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn list_dead_letters(
+    db: &DB,
+    limit: i64,
+    cursor: Option<&DeadLetterCursor>,
+) -> Result<Vec<DeadLetteredSpan>> {
+    let dead_letters = db::dead_letters::get_dead_letters(&db.pool, limit, cursor).await?;
+    Ok(dead_letters
+        .into_iter()
+        .map(DeadLetteredSpan::from)
+        .collect())
+}
+
+pub async fn get_dead_letter(db: &DB, id: &Uuid) -> Result<Option<DeadLetteredSpan>> {
+    let dead_letter = db::dead_letters::get_dead_letter(&db.pool, id).await?;
+    Ok(dead_letter.map(DeadLetteredSpan::from))
+}
+
+/// Publishes dead letters back to `OBSERVATIONS_EXCHANGE` with a fresh retry budget,
+/// and deletes the ones which were published.
+///
+/// Replays all dead letters if `ids` is `None`, except the ones dead-lettered while replaying.
+/// Returns the number of replayed dead letters.
+pub async fn replay_dead_letters(
+    db: &DB,
+    queue: Arc<dyn MessageQueue>,
+    ids: Option<&[Uuid]>,
+) -> Result<usize> {
+    let replay_started_at = Utc::now();
+    let mut replayed = 0;
+
+    loop {
+        let mut transaction = db.pool.begin().await?;
+        let dead_letters = db::dead_letters::lock_dead_letters(
+            &mut *transaction,
+            ids,
+            replay_started_at,
+            REPLAY_BATCH_SIZE,
+        )
+        .await?;
+        if dead_letters.is_empty() {
+            break;
+        }
+
+        let mut published_ids = Vec::new();
+        for dead_letter in &dead_letters {
+            match queue
+                .publish(
+                    dead_letter.payload.as_bytes(),
+                    OBSERVATIONS_EXCHANGE,
+                    OBSERVATIONS_ROUTING_KEY,
+                )
+                .await
+            {
+                Ok(_) => published_ids.push(dead_letter.id),
+                Err(e) => log::error!(
+                    "Failed to replay dead-lettered message [{}]: {:?}",
+                    dead_letter.id,
+                    e
+                ),
+            }
+        }
+        db::dead_letters::delete_dead_letters(&mut *transaction, &published_ids).await?;
+        transaction.commit().await?;
+        replayed += published_ids.len();
+
+        // The rest are kept for the next replay
+        if published_ids.len() < dead_letters.len() {
+            break;
+        }
+    }
+
+    Ok(replayed)
+}
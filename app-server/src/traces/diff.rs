@@ -0,0 +1,339 @@
+//! Compares two traces span by span, e.g. runs of a pipeline before and after a prompt change.
+//!
+//! Spans are aligned by their path and name. Spans with the same path and name, e.g. calls
+//! in a loop, are paired in the order they started.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+use serde_json::Value;
+use similar::TextDiff;
+use uuid::Uuid;
+
+use crate::db::{spans::Span, trace::Trace};
+
+use super::utils::seconds_between;
+
+#[derive(Serialize, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum SpanDiffStatus {
+    Added,
+    Removed,
+    Changed,
+    Unchanged,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpanDiff {
+    pub path: String,
+    pub name: String,
+    pub status: SpanDiffStatus,
+    pub base_span_id: Option<Uuid>,
+    pub compare_span_id: Option<Uuid>,
+    /// Deltas are set for spans present in both traces, as compare minus base
+    pub latency_delta: Option<f64>,
+    pub tokens_delta: Option<i64>,
+    pub cost_delta: Option<f64>,
+    /// Unified diffs, set if the input or output differs
+    pub input_diff: Option<String>,
+    pub output_diff: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceDiff {
+    pub base_trace_id: Uuid,
+    pub compare_trace_id: Uuid,
+    /// In seconds
+    pub latency_delta: f64,
+    pub tokens_delta: i64,
+    pub cost_delta: f64,
+    /// Spans of the base trace in the order they started, followed by the added spans
+    pub spans: Vec<SpanDiff>,
+}
+
+struct SpanStats {
+    path: String,
+    latency: f64,
+    tokens: i64,
+    cost: f64,
+}
+
+impl SpanStats {
+    fn new(span: &Span) -> Self {
+        let mut attributes = span.get_attributes();
+        Self {
+            path: attributes.path().unwrap_or_else(|| span.name.clone()),
+            latency: seconds_between(span.start_time, span.end_time),
+            tokens: attributes.input_tokens() + attributes.completion_tokens(),
+            cost: attributes.total_cost(),
+        }
+    }
+}
+
+pub fn diff_traces(
+    base_trace: &Trace,
+    base_spans: &[Span],
+    compare_trace: &Trace,
+    compare_spans: &[Span],
+) -> TraceDiff {
+    let base_stats = base_spans.iter().map(SpanStats::new).collect::<Vec<_>>();
+    let compare_stats = compare_spans.iter().map(SpanStats::new).collect::<Vec<_>>();
+
+    let mut unmatched_compare_spans: HashMap<(&str, &str), Vec<usize>> = HashMap::new();
+    for (i, (span, stats)) in compare_spans.iter().zip(&compare_stats).enumerate().rev() {
+        unmatched_compare_spans
+            .entry((stats.path.as_str(), span.name.as_str()))
+            .or_default()
+            .push(i);
+    }
+
+    let mut spans = Vec::new();
+    let mut is_matched = vec![false; compare_spans.len()];
+    for (base_span, base) in base_spans.iter().zip(&base_stats) {
+        let matched = unmatched_compare_spans
+            .get_mut(&(base.path.as_str(), base_span.name.as_str()))
+            .and_then(|indices| indices.pop());
+        let Some(i) = matched else {
+            spans.push(unmatched_span_diff(
+                base_span,
+                base,
+                SpanDiffStatus::Removed,
+            ));
+            continue;
+        };
+        is_matched[i] = true;
+        let (compare_span, compare) = (&compare_spans[i], &compare_stats[i]);
+
+        let input_diff = text_diff(&base_span.input, &compare_span.input);
+        let output_diff = text_diff(&base_span.output, &compare_span.output);
+        let status = if input_diff.is_some() || output_diff.is_some() {
+            SpanDiffStatus::Changed
+        } else {
+            SpanDiffStatus::Unchanged
+        };
+        spans.push(SpanDiff {
+            path: base.path.clone(),
+            name: base_span.name.clone(),
+            status,
+            base_span_id: Some(base_span.span_id),
+            compare_span_id: Some(compare_span.span_id),
+            latency_delta: Some(compare.latency - base.latency),
+            tokens_delta: Some(compare.tokens - base.tokens),
+            cost_delta: Some(compare.cost - base.cost),
+            input_diff,
+            output_diff,
+        });
+    }
+    for (i, (compare_span, compare)) in compare_spans.iter().zip(&compare_stats).enumerate() {
+        if !is_matched[i] {
+            spans.push(unmatched_span_diff(
+                compare_span,
+                compare,
+                SpanDiffStatus::Added,
+            ));
+        }
+    }
+
+    TraceDiff {
+        base_trace_id: base_trace.id,
+        compare_trace_id: compare_trace.id,
+        latency_delta: trace_latency(compare_trace) - trace_latency(base_trace),
+        tokens_delta: compare_trace.total_token_count - base_trace.total_token_count,
+        cost_delta: compare_trace.cost - base_trace.cost,
+        spans,
+    }
+}
+
+fn unmatched_span_diff(span: &Span, stats: &SpanStats, status: SpanDiffStatus) -> SpanDiff {
+    let (base_span_id, compare_span_id) = match status {
+        SpanDiffStatus::Added => (None, Some(span.span_id)),
+        _ => (Some(span.span_id), None),
+    };
+    SpanDiff {
+        path: stats.path.clone(),
+        name: span.name.clone(),
+        status,
+        base_span_id,
+        compare_span_id,
+        latency_delta: None,
+        tokens_delta: None,
+        cost_delta: None,
+        input_diff: None,
+        output_diff: None,
+    }
+}
+
+fn trace_latency(trace: &Trace) -> f64 {
+    match (trace.start_time, trace.end_time) {
+        (Some(start_time), Some(end_time)) => seconds_between(start_time, end_time),
+        _ => 0.0,
+    }
+}
+
+/// Strings are compared as they are, so that multiline text, e.g. prompts, is diffed by line
+fn diff_text(value: &Option<Value>) -> String {
+    let mut text = match value {
+        Some(Value::String(s)) => s.clone(),
+        Some(value) => serde_json::to_string_pretty(value).unwrap_or_default(),
+        None => return String::new(),
+    };
+    if !text.ends_with('\n') {
+        text.push('\n');
+    }
+    text
+}
+
+fn text_diff(base: &Option<Value>, compare: &Option<Value>) -> Option<String> {
+    if base == compare {
+        return None;
+    }
+    let (base, compare) = (diff_text(base), diff_text(compare));
+    let diff = TextDiff::from_lines(&base, &compare)
+        .unified_diff()
+        .header("base", "compare")
+        .to_string();
+    Some(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::traces::test_utils;
+
+    use super::*;
+
+    fn span(name: &str, path: &str, output: Value) -> Span {
+        Span {
+            attributes: json!({"lmnr.span.path": path}),
+            output: Some(output),
+            ..test_utils::span(name, None, 0, 0)
+        }
+    }
+
+    fn statuses(diff: &TraceDiff) -> Vec<(&str, &SpanDiffStatus)> {
+        diff.spans
+            .iter()
+            .map(|span| (span.name.as_str(), &span.status))
+            .collect()
+    }
+
+    fn trace() -> Trace {
+        Trace {
+            id: Uuid::new_v4(),
+            start_time: None,
+            end_time: None,
+            version: String::new(),
+            release: None,
+            user_id: None,
+            session_id: None,
+            metadata: None,
+            input_token_count: 0,
+            output_token_count: 0,
+            total_token_count: 0,
+            input_cost: 0.0,
+            output_cost: 0.0,
+            cost: 0.0,
+            success: true,
+            has_error: false,
+            project_id: Uuid::nil(),
+        }
+    }
+
+    #[test]
+    fn test_spans_are_aligned_by_path_and_name() {
+        let base_spans = vec![
+            span("agent", "agent", json!("done")),
+            span("openai.chat", "agent.openai.chat", json!("first\nanswer")),
+            span("search", "agent.search", json!([])),
+        ];
+        let compare_spans = vec![
+            span("agent", "agent", json!("done")),
+            span("openai.chat", "agent.openai.chat", json!("first\nreply")),
+            span("rerank", "agent.rerank", json!([])),
+        ];
+
+        let diff = diff_traces(&trace(), &base_spans, &trace(), &compare_spans);
+
+        assert_eq!(
+            statuses(&diff),
+            vec![
+                ("agent", &SpanDiffStatus::Unchanged),
+                ("openai.chat", &SpanDiffStatus::Changed),
+                ("search", &SpanDiffStatus::Removed),
+                ("rerank", &SpanDiffStatus::Added),
+            ]
+        );
+        assert_eq!(
+            diff.spans[1].output_diff.as_deref(),
+            Some("--- base\n+++ compare\n@@ -1,2 +1,2 @@\n first\n-answer\n+reply\n")
+        );
+    }
+
+    #[test]
+    fn test_unmatched_spans() {
+        // Same name under a different path is a different span
+        let base_spans = vec![span("search", "agent.search", json!([]))];
+        let compare_spans = vec![
+            span("search", "agent.retrieve.search", json!([])),
+            span("rerank", "agent.rerank", json!([])),
+        ];
+
+        let diff = diff_traces(&trace(), &base_spans, &trace(), &compare_spans);
+
+        assert_eq!(
+            statuses(&diff),
+            vec![
+                ("search", &SpanDiffStatus::Removed),
+                ("search", &SpanDiffStatus::Added),
+                ("rerank", &SpanDiffStatus::Added),
+            ]
+        );
+        let removed = &diff.spans[0];
+        assert_eq!(removed.base_span_id, Some(base_spans[0].span_id));
+        assert_eq!(removed.compare_span_id, None);
+        assert!(removed.latency_delta.is_none() && removed.output_diff.is_none());
+        let added = &diff.spans[1];
+        assert_eq!(added.base_span_id, None);
+        assert_eq!(added.compare_span_id, Some(compare_spans[0].span_id));
+    }
+
+    #[test]
+    fn test_duplicate_names_are_paired_in_order() {
+        let base_spans = vec![
+            span("openai.chat", "agent.openai.chat", json!("first")),
+            span("openai.chat", "agent.openai.chat", json!("second")),
+        ];
+        let compare_spans = vec![
+            span("openai.chat", "agent.openai.chat", json!("first")),
+            span("openai.chat", "agent.openai.chat", json!("changed")),
+            span("openai.chat", "agent.openai.chat", json!("third")),
+        ];
+
+        let diff = diff_traces(&trace(), &base_spans, &trace(), &compare_spans);
+
+        assert_eq!(
+            statuses(&diff),
+            vec![
+                ("openai.chat", &SpanDiffStatus::Unchanged),
+                ("openai.chat", &SpanDiffStatus::Changed),
+                ("openai.chat", &SpanDiffStatus::Added),
+            ]
+        );
+        let span_ids = diff
+            .spans
+            .iter()
+            .map(|span| (span.base_span_id, span.compare_span_id))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            span_ids,
+            vec![
+                (Some(base_spans[0].span_id), Some(compare_spans[0].span_id)),
+                (Some(base_spans[1].span_id), Some(compare_spans[1].span_id)),
+                (None, Some(compare_spans[2].span_id)),
+            ]
+        );
+    }
+}
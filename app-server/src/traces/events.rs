@@ -12,6 +12,7 @@ use crate::{
         events::{EventObservation, EventSource},
         DB,
     },
+    features::{is_feature_enabled, Feature},
 };
 
 // TODO: Make this function more readable and separate into smaller functions
@@ -134,6 +135,10 @@ pub async fn create_events(
     db::events::create_events_by_template_name(db, events.clone(), &template_ids, &event_source)
         .await?;
 
+    if !is_feature_enabled(Feature::FullBuild) {
+        return Ok(());
+    }
+
     let ch_events = events
         .into_iter()
         .zip(event_templates.into_iter())
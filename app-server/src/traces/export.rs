@@ -0,0 +1,601 @@
+//! Bulk export of traces and spans to files, for analysis outside of Laminar
+
+use std::{env, future::Future, sync::Arc};
+
+use anyhow::Result;
+use arrow_array::{
+    ArrayRef, BooleanArray, Float64Array, Int64Array, RecordBatch, StringArray,
+    TimestampMicrosecondArray,
+};
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures::Stream;
+use parquet::arrow::ArrowWriter;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::db::{modifiers::ExportCursor, spans::ExportedSpan, trace::ExportedTrace};
+
+/// Number of rows fetched from the database, and written as one parquet row group, at a time
+const EXPORT_PAGE_SIZE: usize = 1000;
+/// Exports to storage are uploaded as one object, so they are held in memory until complete
+const DEFAULT_MAX_STORAGE_EXPORT_BYTES: usize = 256 * 1024 * 1024;
+
+pub fn max_storage_export_bytes() -> usize {
+    env::var("MAX_STORAGE_EXPORT_BYTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MAX_STORAGE_EXPORT_BYTES)
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Jsonl,
+    Csv,
+    Parquet,
+}
+
+impl ExportFormat {
+    pub fn file_extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Jsonl => "jsonl",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Parquet => "parquet",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Jsonl => "application/jsonl",
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum ColumnType {
+    Text,
+    Int,
+    Float,
+    Bool,
+    Timestamp,
+    /// Nested JSON, which is kept as is in JSONL, and serialized to a string in CSV and Parquet
+    Json,
+}
+
+pub struct ExportColumn {
+    pub name: &'static str,
+    pub column_type: ColumnType,
+}
+
+const fn column(name: &'static str, column_type: ColumnType) -> ExportColumn {
+    ExportColumn { name, column_type }
+}
+
+/// Value of a column, which must match the type of the column
+pub enum ExportValue {
+    Text(Option<String>),
+    Int(Option<i64>),
+    Float(Option<f64>),
+    Bool(Option<bool>),
+    Timestamp(Option<DateTime<Utc>>),
+    Json(Option<Value>),
+}
+
+impl ExportValue {
+    fn to_json(&self) -> Value {
+        match self {
+            ExportValue::Text(v) => v.clone().map(Value::String).unwrap_or(Value::Null),
+            ExportValue::Int(v) => v.map(Value::from).unwrap_or(Value::Null),
+            ExportValue::Float(v) => v.map(Value::from).unwrap_or(Value::Null),
+            ExportValue::Bool(v) => v.map(Value::Bool).unwrap_or(Value::Null),
+            ExportValue::Timestamp(v) => v
+                .map(|t| Value::String(t.to_rfc3339()))
+                .unwrap_or(Value::Null),
+            ExportValue::Json(v) => v.clone().unwrap_or(Value::Null),
+        }
+    }
+
+    /// Text in CSV and Parquet, where nulls are empty
+    fn to_text(&self) -> Option<String> {
+        match self.to_json() {
+            Value::Null => None,
+            Value::String(s) => Some(s),
+            v => Some(v.to_string()),
+        }
+    }
+}
+
+pub trait ExportRow {
+    fn columns() -> &'static [ExportColumn];
+    /// Position of the row, from which the next page is fetched
+    fn cursor(&self) -> ExportCursor;
+    /// Values in the order of `columns`
+    fn into_values(self) -> Vec<ExportValue>;
+}
+
+/// Name of an enum variant as it is serialized in the API, e.g. `LLM` for `SpanType::LLM`
+fn serialized_name(value: &impl Serialize) -> Option<String> {
+    match serde_json::to_value(value) {
+        Ok(Value::String(name)) => Some(name),
+        _ => None,
+    }
+}
+
+/// Writes rows in batches, so that the output can be streamed as it is written
+pub struct ExportWriter {
+    format: ExportFormat,
+    columns: &'static [ExportColumn],
+    /// Output which has not been taken yet
+    buffer: Vec<u8>,
+    parquet_writer: Option<ArrowWriter<Vec<u8>>>,
+}
+
+impl ExportWriter {
+    pub fn new<R: ExportRow>(format: ExportFormat) -> Result<Self> {
+        let columns = R::columns();
+        let mut writer = Self {
+            format,
+            columns,
+            buffer: Vec::new(),
+            parquet_writer: None,
+        };
+        match format {
+            ExportFormat::Jsonl => {}
+            ExportFormat::Csv => {
+                let mut csv_writer = csv::Writer::from_writer(Vec::new());
+                csv_writer.write_record(columns.iter().map(|c| c.name))?;
+                writer.buffer = csv_writer.into_inner()?;
+            }
+            ExportFormat::Parquet => {
+                writer.parquet_writer = Some(ArrowWriter::try_new(
+                    Vec::new(),
+                    Arc::new(arrow_schema(columns)),
+                    None,
+                )?);
+            }
+        }
+        Ok(writer)
+    }
+
+    pub fn write_rows<R: ExportRow>(&mut self, rows: Vec<R>) -> Result<()> {
+        let rows = rows
+            .into_iter()
+            .map(|row| row.into_values())
+            .collect::<Vec<_>>();
+        match self.format {
+            ExportFormat::Jsonl => {
+                for values in rows {
+                    let object = self
+                        .columns
+                        .iter()
+                        .zip(values.iter())
+                        .map(|(column, value)| (column.name.to_string(), value.to_json()))
+                        .collect::<Map<String, Value>>();
+                    serde_json::to_writer(&mut self.buffer, &object)?;
+                    self.buffer.push(b'\n');
+                }
+            }
+            ExportFormat::Csv => {
+                let mut csv_writer = csv::WriterBuilder::new()
+                    .has_headers(false)
+                    .from_writer(Vec::new());
+                for values in rows {
+                    csv_writer.write_record(
+                        values
+                            .iter()
+                            .map(|value| value.to_text().unwrap_or_default()),
+                    )?;
+                }
+                self.buffer.extend(csv_writer.into_inner()?);
+            }
+            ExportFormat::Parquet => {
+                if rows.is_empty() {
+                    return Ok(());
+                }
+                let batch = record_batch(self.columns, rows)?;
+                let parquet_writer = self.parquet_writer.as_mut().unwrap();
+                parquet_writer.write(&batch)?;
+                parquet_writer.flush()?;
+                self.buffer.append(parquet_writer.inner_mut());
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes the output written so far
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buffer)
+    }
+
+    /// Returns the rest of the output, which includes the parquet footer
+    pub fn finish(mut self) -> Result<Vec<u8>> {
+        if let Some(mut parquet_writer) = self.parquet_writer.take() {
+            parquet_writer.finish()?;
+            self.buffer.append(parquet_writer.inner_mut());
+        }
+        Ok(self.buffer)
+    }
+}
+
+fn arrow_schema(columns: &[ExportColumn]) -> Schema {
+    let fields = columns
+        .iter()
+        .map(|column| {
+            let data_type = match column.column_type {
+                ColumnType::Text | ColumnType::Json => DataType::Utf8,
+                ColumnType::Int => DataType::Int64,
+                ColumnType::Float => DataType::Float64,
+                ColumnType::Bool => DataType::Boolean,
+                ColumnType::Timestamp => {
+                    DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into()))
+                }
+            };
+            Field::new(column.name, data_type, true)
+        })
+        .collect::<Vec<_>>();
+    Schema::new(fields)
+}
+
+fn record_batch(columns: &[ExportColumn], rows: Vec<Vec<ExportValue>>) -> Result<RecordBatch> {
+    let mut values_by_column = columns.iter().map(|_| Vec::new()).collect::<Vec<_>>();
+    for values in rows {
+        for (i, value) in values.into_iter().enumerate() {
+            values_by_column[i].push(value);
+        }
+    }
+
+    let arrays = columns
+        .iter()
+        .zip(values_by_column)
+        .map(|(column, values)| -> ArrayRef {
+            match column.column_type {
+                ColumnType::Text | ColumnType::Json => {
+                    Arc::new(values.iter().map(|v| v.to_text()).collect::<StringArray>())
+                }
+                ColumnType::Int => Arc::new(
+                    values
+                        .iter()
+                        .map(|v| match v {
+                            ExportValue::Int(v) => *v,
+                            _ => None,
+                        })
+                        .collect::<Int64Array>(),
+                ),
+                ColumnType::Float => Arc::new(
+                    values
+                        .iter()
+                        .map(|v| match v {
+                            ExportValue::Float(v) => *v,
+                            _ => None,
+                        })
+                        .collect::<Float64Array>(),
+                ),
+                ColumnType::Bool => Arc::new(
+                    values
+                        .iter()
+                        .map(|v| match v {
+                            ExportValue::Bool(v) => *v,
+                            _ => None,
+                        })
+                        .collect::<BooleanArray>(),
+                ),
+                ColumnType::Timestamp => Arc::new(
+                    values
+                        .iter()
+                        .map(|v| match v {
+                            ExportValue::Timestamp(v) => v.map(|t| t.timestamp_micros()),
+                            _ => None,
+                        })
+                        .collect::<TimestampMicrosecondArray>()
+                        .with_timezone("UTC"),
+                ),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    Ok(RecordBatch::try_new(
+        Arc::new(arrow_schema(columns)),
+        arrays,
+    )?)
+}
+
+/// Fetches all rows with `fetch_page(limit, after)`, where `after` is the cursor of the last row
+/// of the previous page, and streams them in the given format
+pub fn export_stream<R, F, Fut>(
+    format: ExportFormat,
+    fetch_page: F,
+) -> impl Stream<Item = Result<Bytes>>
+where
+    R: ExportRow,
+    F: Fn(usize, Option<ExportCursor>) -> Fut,
+    Fut: Future<Output = Result<Vec<R>>>,
+{
+    async_stream::try_stream! {
+        let mut writer = ExportWriter::new::<R>(format)?;
+        let mut after = None;
+        loop {
+            let rows = fetch_page(EXPORT_PAGE_SIZE, after).await?;
+            let is_last_page = rows.len() < EXPORT_PAGE_SIZE;
+            after = rows.last().map(ExportRow::cursor);
+            writer.write_rows(rows)?;
+            yield Bytes::from(writer.take_output());
+            if is_last_page {
+                break;
+            }
+        }
+        yield Bytes::from(writer.finish()?);
+    }
+}
+
+impl ExportRow for ExportedTrace {
+    fn columns() -> &'static [ExportColumn] {
+        const COLUMNS: &[ExportColumn] = &[
+            column("id", ColumnType::Text),
+            column("start_time", ColumnType::Timestamp),
+            column("end_time", ColumnType::Timestamp),
+            column("latency", ColumnType::Float),
+            column("session_id", ColumnType::Text),
+            column("user_id", ColumnType::Text),
+            column("metadata", ColumnType::Json),
+            column("input", ColumnType::Json),
+            column("output", ColumnType::Json),
+            column("input_token_count", ColumnType::Int),
+            column("output_token_count", ColumnType::Int),
+            column("total_token_count", ColumnType::Int),
+            column("input_cost", ColumnType::Float),
+            column("output_cost", ColumnType::Float),
+            column("cost", ColumnType::Float),
+            column("has_error", ColumnType::Bool),
+            column("labels", ColumnType::Json),
+            column("events", ColumnType::Json),
+        ];
+        COLUMNS
+    }
+
+    fn cursor(&self) -> ExportCursor {
+        ExportCursor {
+            start_time: self.start_time,
+            id: self.id,
+        }
+    }
+
+    fn into_values(self) -> Vec<ExportValue> {
+        vec![
+            ExportValue::Text(Some(self.id.to_string())),
+            ExportValue::Timestamp(Some(self.start_time)),
+            ExportValue::Timestamp(Some(self.end_time)),
+            ExportValue::Float(Some(self.latency)),
+            ExportValue::Text(self.session_id),
+            ExportValue::Text(self.user_id),
+            ExportValue::Json(self.metadata),
+            ExportValue::Json(self.input),
+            ExportValue::Json(self.output),
+            ExportValue::Int(Some(self.input_token_count)),
+            ExportValue::Int(Some(self.output_token_count)),
+            ExportValue::Int(Some(self.total_token_count)),
+            ExportValue::Float(Some(self.input_cost)),
+            ExportValue::Float(Some(self.output_cost)),
+            ExportValue::Float(Some(self.cost)),
+            ExportValue::Bool(Some(self.has_error)),
+            ExportValue::Json(Some(self.labels)),
+            ExportValue::Json(Some(self.events)),
+        ]
+    }
+}
+
+impl ExportRow for ExportedSpan {
+    fn columns() -> &'static [ExportColumn] {
+        const COLUMNS: &[ExportColumn] = &[
+            column("span_id", ColumnType::Text),
+            column("trace_id", ColumnType::Text),
+            column("parent_span_id", ColumnType::Text),
+            column("name", ColumnType::Text),
+            column("span_type", ColumnType::Text),
+            column("path", ColumnType::Text),
+            column("start_time", ColumnType::Timestamp),
+            column("end_time", ColumnType::Timestamp),
+            column("latency", ColumnType::Float),
+            column("model", ColumnType::Text),
+            column("input", ColumnType::Json),
+            column("output", ColumnType::Json),
+            column("input_tokens", ColumnType::Int),
+            column("output_tokens", ColumnType::Int),
+            column("total_tokens", ColumnType::Int),
+            column("cost", ColumnType::Float),
+            column("has_error", ColumnType::Bool),
+            column("status", ColumnType::Text),
+            column("attributes", ColumnType::Json),
+            column("labels", ColumnType::Json),
+            column("events", ColumnType::Json),
+        ];
+        COLUMNS
+    }
+
+    fn cursor(&self) -> ExportCursor {
+        ExportCursor {
+            start_time: self.start_time,
+            id: self.span_id,
+        }
+    }
+
+    fn into_values(self) -> Vec<ExportValue> {
+        vec![
+            ExportValue::Text(Some(self.span_id.to_string())),
+            ExportValue::Text(Some(self.trace_id.to_string())),
+            ExportValue::Text(self.parent_span_id.map(|id| id.to_string())),
+            ExportValue::Text(Some(self.name)),
+            ExportValue::Text(serialized_name(&self.span_type)),
+            ExportValue::Text(self.path),
+            ExportValue::Timestamp(Some(self.start_time)),
+            ExportValue::Timestamp(Some(self.end_time)),
+            ExportValue::Float(Some(self.latency)),
+            ExportValue::Text(self.model),
+            ExportValue::Json(self.input),
+            ExportValue::Json(self.output),
+            ExportValue::Int(self.input_tokens),
+            ExportValue::Int(self.output_tokens),
+            ExportValue::Int(self.total_tokens),
+            ExportValue::Float(self.cost),
+            ExportValue::Bool(Some(self.has_error)),
+            ExportValue::Text(serialized_name(&self.status)),
+            ExportValue::Json(Some(self.attributes)),
+            ExportValue::Json(Some(self.labels)),
+            ExportValue::Json(Some(self.events)),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::Array;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use super::*;
+
+    struct TestRow {
+        name: Option<String>,
+        tokens: i64,
+        metadata: Value,
+    }
+
+    impl ExportRow for TestRow {
+        fn columns() -> &'static [ExportColumn] {
+            const COLUMNS: &[ExportColumn] = &[
+                column("name", ColumnType::Text),
+                column("tokens", ColumnType::Int),
+                column("metadata", ColumnType::Json),
+            ];
+            COLUMNS
+        }
+
+        fn cursor(&self) -> ExportCursor {
+            ExportCursor {
+                start_time: Utc::now(),
+                id: Uuid::nil(),
+            }
+        }
+
+        fn into_values(self) -> Vec<ExportValue> {
+            vec![
+                ExportValue::Text(self.name),
+                ExportValue::Int(Some(self.tokens)),
+                ExportValue::Json(Some(self.metadata)),
+            ]
+        }
+    }
+
+    fn rows() -> Vec<TestRow> {
+        vec![
+            TestRow {
+                name: Some("a, \"quoted\"".to_string()),
+                tokens: 1,
+                metadata: json!({"key": [1, 2]}),
+            },
+            TestRow {
+                name: None,
+                tokens: 2,
+                metadata: json!("text"),
+            },
+        ]
+    }
+
+    /// Writes the rows in two batches, taking the output in between as when streaming
+    fn export(format: ExportFormat) -> Vec<u8> {
+        let mut writer = ExportWriter::new::<TestRow>(format).unwrap();
+        let mut rows = rows();
+        let second_batch = rows.split_off(1);
+        writer.write_rows(rows).unwrap();
+        let mut output = writer.take_output();
+        writer.write_rows(second_batch).unwrap();
+        output.extend(writer.take_output());
+        output.extend(writer.finish().unwrap());
+        output
+    }
+
+    #[test]
+    fn test_jsonl_keeps_nested_json() {
+        let output = String::from_utf8(export(ExportFormat::Jsonl)).unwrap();
+        let lines = output
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            lines,
+            vec![
+                json!({"name": "a, \"quoted\"", "tokens": 1, "metadata": {"key": [1, 2]}}),
+                json!({"name": null, "tokens": 2, "metadata": "text"}),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_csv_has_one_header_and_escapes_values() {
+        let output = String::from_utf8(export(ExportFormat::Csv)).unwrap();
+
+        assert_eq!(
+            output,
+            "name,tokens,metadata\n\
+             \"a, \"\"quoted\"\"\",1,\"{\"\"key\"\":[1,2]}\"\n\
+             ,2,text\n"
+        );
+    }
+
+    #[test]
+    fn test_parquet_batches_form_one_file() {
+        let output = Bytes::from(export(ExportFormat::Parquet));
+        let reader = ParquetRecordBatchReaderBuilder::try_new(output).unwrap();
+        // One row group per batch of rows
+        assert_eq!(reader.metadata().num_row_groups(), 2);
+        let batches = reader
+            .build()
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let column_values = |i: usize| {
+            batches
+                .iter()
+                .flat_map(|batch| {
+                    let array = batch
+                        .column(i)
+                        .as_any()
+                        .downcast_ref::<StringArray>()
+                        .unwrap()
+                        .clone();
+                    (0..array.len())
+                        .map(move |row| (!array.is_null(row)).then(|| array.value(row).to_string()))
+                })
+                .collect::<Vec<_>>()
+        };
+        let tokens = batches
+            .iter()
+            .flat_map(|batch| {
+                let array = batch
+                    .column(1)
+                    .as_any()
+                    .downcast_ref::<Int64Array>()
+                    .unwrap();
+                array.values().to_vec()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            column_values(0),
+            vec![Some("a, \"quoted\"".to_string()), None]
+        );
+        assert_eq!(tokens, vec![1, 2]);
+        assert_eq!(
+            column_values(2),
+            vec![
+                Some("{\"key\":[1,2]}".to_string()),
+                Some("text".to_string())
+            ]
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use std::sync::Arc;
 
+use prost::Message;
 use sqlx::PgPool;
 
 use crate::{
@@ -7,35 +8,49 @@ use crate::{
     cache::Cache,
     db::{project_api_keys::ProjectApiKey, DB},
     features::{is_feature_enabled, Feature},
-    opentelemetry::opentelemetry::proto::collector::trace::v1::{
-        trace_service_server::TraceService, ExportTraceServiceRequest, ExportTraceServiceResponse,
+    mq::MessageQueue,
+    opentelemetry::opentelemetry::proto::collector::{
+        logs::v1::{
+            logs_service_server::LogsService, ExportLogsServiceRequest, ExportLogsServiceResponse,
+        },
+        trace::v1::{
+            trace_service_server::TraceService, ExportTraceServiceRequest,
+            ExportTraceServiceResponse,
+        },
     },
     storage::Storage,
 };
-use lapin::Connection;
-use tonic::{Request, Response, Status};
+use tonic::{Code, Request, Response, Status};
 
-use super::{limits::get_workspace_limit_exceeded_by_project_id, producer::push_spans_to_queue};
+use super::{
+    limits::get_workspace_limit_exceeded_by_project_id,
+    logs::process_logs,
+    producer::push_spans_to_queue,
+    rate_limit::{count_log_records, count_spans, RateLimiter},
+};
 
 pub struct ProcessTracesService {
     db: Arc<DB>,
     cache: Arc<Cache>,
-    rabbitmq_connection: Option<Arc<Connection>>,
+    queue: Arc<dyn MessageQueue>,
     storage: Arc<dyn Storage>,
+    rate_limiter: Arc<RateLimiter>,
 }
 
 impl ProcessTracesService {
     pub fn new(
         db: Arc<DB>,
         cache: Arc<Cache>,
-        rabbitmq_connection: Option<Arc<Connection>>,
+        queue: Arc<dyn MessageQueue>,
         storage: Arc<dyn Storage>,
+        rate_limiter: Arc<RateLimiter>,
     ) -> Self {
         Self {
             db,
             cache,
-            rabbitmq_connection,
+            queue,
             storage,
+            rate_limiter,
         }
     }
 }
@@ -52,6 +67,10 @@ impl TraceService for ProcessTracesService {
         let project_id = api_key.project_id;
         let request = request.into_inner();
 
+        if let Err(rate_limited) = self.rate_limiter.check(&api_key, count_spans(&request)) {
+            return Err(rate_limited_status(rate_limited.retry_after_secs()));
+        }
+
         if is_feature_enabled(Feature::UsageLimit) {
             let limits_exceeded = get_workspace_limit_exceeded_by_project_id(
                 self.db.clone(),
@@ -73,7 +92,7 @@ impl TraceService for ProcessTracesService {
         let response = push_spans_to_queue(
             request,
             project_id,
-            self.rabbitmq_connection.clone(),
+            self.queue.clone(),
             self.storage.clone(),
             self.db.clone(),
             self.cache.clone(),
@@ -88,6 +107,129 @@ impl TraceService for ProcessTracesService {
     }
 }
 
+/// `google.rpc.Status`, as the details of a gRPC status
+#[derive(Clone, PartialEq, Message)]
+struct RpcStatus {
+    #[prost(int32, tag = "1")]
+    code: i32,
+    #[prost(string, tag = "2")]
+    message: String,
+    #[prost(message, repeated, tag = "3")]
+    details: Vec<ProtoAny>,
+}
+
+/// `google.protobuf.Any`
+#[derive(Clone, PartialEq, Message)]
+struct ProtoAny {
+    #[prost(string, tag = "1")]
+    type_url: String,
+    #[prost(bytes = "vec", tag = "2")]
+    value: Vec<u8>,
+}
+
+/// `google.rpc.RetryInfo`, which OTel exporters read to back off before retrying
+#[derive(Clone, PartialEq, Message)]
+struct RetryInfo {
+    #[prost(message, optional, tag = "1")]
+    retry_delay: Option<ProtoDuration>,
+}
+
+/// `google.protobuf.Duration`
+#[derive(Clone, PartialEq, Message)]
+struct ProtoDuration {
+    #[prost(int64, tag = "1")]
+    seconds: i64,
+    #[prost(int32, tag = "2")]
+    nanos: i32,
+}
+
+fn rate_limited_status(retry_after_secs: u64) -> Status {
+    let message = format!("Rate limit exceeded, retry after {retry_after_secs}s");
+    let retry_info = RetryInfo {
+        retry_delay: Some(ProtoDuration {
+            seconds: retry_after_secs as i64,
+            nanos: 0,
+        }),
+    };
+    let details = RpcStatus {
+        code: Code::ResourceExhausted as i32,
+        message: message.clone(),
+        details: vec![ProtoAny {
+            type_url: "type.googleapis.com/google.rpc.RetryInfo".to_string(),
+            value: retry_info.encode_to_vec(),
+        }],
+    };
+    Status::with_details(
+        Code::ResourceExhausted,
+        message,
+        details.encode_to_vec().into(),
+    )
+}
+
+pub struct ProcessLogsService {
+    db: Arc<DB>,
+    cache: Arc<Cache>,
+    rate_limiter: Arc<RateLimiter>,
+}
+
+impl ProcessLogsService {
+    pub fn new(db: Arc<DB>, cache: Arc<Cache>, rate_limiter: Arc<RateLimiter>) -> Self {
+        Self {
+            db,
+            cache,
+            rate_limiter,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl LogsService for ProcessLogsService {
+    async fn export(
+        &self,
+        request: Request<ExportLogsServiceRequest>,
+    ) -> Result<Response<ExportLogsServiceResponse>, Status> {
+        let api_key = authenticate_request(request.metadata(), &self.db.pool, self.cache.clone())
+            .await
+            .map_err(|_| Status::unauthenticated("Failed to authenticate request"))?;
+        let project_id = api_key.project_id;
+        let request = request.into_inner();
+
+        if let Err(rate_limited) = self
+            .rate_limiter
+            .check(&api_key, count_log_records(&request))
+        {
+            return Err(rate_limited_status(rate_limited.retry_after_secs()));
+        }
+
+        if is_feature_enabled(Feature::UsageLimit) {
+            let limits_exceeded = get_workspace_limit_exceeded_by_project_id(
+                self.db.clone(),
+                self.cache.clone(),
+                project_id,
+            )
+            .await
+            .map_err(|e| {
+                log::error!("Failed to get workspace limits: {:?}", e);
+                Status::internal("Failed to get workspace limits")
+            })?;
+
+            // Logs belong to spans, so they are not accepted once spans are not
+            if limits_exceeded.spans {
+                return Err(Status::resource_exhausted("Workspace span limit exceeded"));
+            }
+        }
+
+        let response = process_logs(request, project_id, self.db.clone())
+            .await
+            .map_err(|e| {
+                log::error!("Failed to process logs: {:?}", e);
+                Status::internal("Failed to process logs")
+            })?;
+
+        Ok(Response::new(response))
+    }
+}
+
 async fn authenticate_request(
     metadata: &tonic::metadata::MetadataMap,
     pool: &PgPool,
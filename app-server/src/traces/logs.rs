@@ -0,0 +1,37 @@
+//! This module takes log exports from OpenTelemetry and records them,
+//! so that they can be shown next to the spans they were emitted in.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use uuid::Uuid;
+
+use crate::{
+    db::{
+        logs::{record_logs, LogRecord},
+        DB,
+    },
+    opentelemetry::opentelemetry::proto::collector::logs::v1::{
+        ExportLogsServiceRequest, ExportLogsServiceResponse,
+    },
+};
+
+pub async fn process_logs(
+    request: ExportLogsServiceRequest,
+    project_id: Uuid,
+    db: Arc<DB>,
+) -> Result<ExportLogsServiceResponse> {
+    let logs = request
+        .resource_logs
+        .into_iter()
+        .flat_map(|resource_logs| resource_logs.scope_logs)
+        .flat_map(|scope_logs| scope_logs.log_records)
+        .map(LogRecord::from_otel)
+        .collect::<Vec<_>>();
+
+    record_logs(&db.pool, project_id, &logs).await?;
+
+    Ok(ExportLogsServiceResponse {
+        partial_success: None,
+    })
+}
@@ -1,13 +1,25 @@
 pub mod attributes;
+pub mod batch_writer;
 pub mod consumer;
+pub mod dead_letter;
+pub mod diff;
 pub mod evaluators;
 pub mod events;
+pub mod export;
 pub mod grpc_service;
 mod index;
 pub mod limits;
+pub mod logs;
 pub mod producer;
+pub mod rate_limit;
+pub mod redaction;
+pub mod sampling;
 pub mod span_attributes;
 pub mod spans;
+#[cfg(test)]
+pub mod test_utils;
+pub mod transcript;
+pub mod tree;
 pub mod utils;
 
 pub const OBSERVATIONS_QUEUE: &str = "observations_queue";
@@ -1,80 +1,124 @@
 //! This module takes trace exports from OpenTelemetry and pushes them
-//! to RabbitMQ for further processing.
+//! to the message queue for further processing.
 
 use std::sync::Arc;
 
 use anyhow::Result;
-use lapin::{options::BasicPublishOptions, BasicProperties, Connection};
 use uuid::Uuid;
 
 use crate::{
     api::v1::traces::RabbitMqSpanMessage,
     cache::Cache,
-    db::{events::EventObservation, spans::Span, utils::convert_any_value_to_json_value, DB},
-    features::{is_feature_enabled, Feature},
+    db::{
+        events::EventObservation, span_events::SpanEvent, spans::Span,
+        utils::convert_any_value_to_json_value, DB,
+    },
+    mq::MessageQueue,
     opentelemetry::opentelemetry::proto::collector::trace::v1::{
-        ExportTraceServiceRequest, ExportTraceServiceResponse,
+        ExportTracePartialSuccess, ExportTraceServiceRequest, ExportTraceServiceResponse,
     },
     storage::Storage,
 };
 
 use super::{
-    span_attributes::EVENT_TYPE, utils::record_span_to_db, OBSERVATIONS_EXCHANGE,
+    redaction::get_redaction_rules, span_attributes::EVENT_TYPE, OBSERVATIONS_EXCHANGE,
     OBSERVATIONS_ROUTING_KEY,
 };
 
-// TODO: Implement partial_success
+/// Spans that could not be accepted from an export request.
+///
+/// Malformed spans and spans that fail to be published are rejected one by one,
+/// so that the rest of the batch is still accepted and exporters don't retry the whole batch.
+#[derive(Default)]
+struct RejectedSpans {
+    count: i64,
+    first_error: Option<String>,
+}
+
+impl RejectedSpans {
+    fn reject(&mut self, error: String) {
+        self.count += 1;
+        if self.first_error.is_none() {
+            self.first_error = Some(error);
+        }
+    }
+
+    fn into_response(self) -> ExportTraceServiceResponse {
+        let partial_success = self
+            .first_error
+            .map(|first_error| ExportTracePartialSuccess {
+                rejected_spans: self.count,
+                error_message: format!(
+                    "{} span(s) rejected. First error: {}",
+                    self.count, first_error
+                ),
+            });
+
+        ExportTraceServiceResponse { partial_success }
+    }
+}
+
 pub async fn push_spans_to_queue(
     request: ExportTraceServiceRequest,
     project_id: Uuid,
-    rabbitmq_connection: Option<Arc<Connection>>,
+    queue: Arc<dyn MessageQueue>,
     storage: Arc<dyn Storage>,
     db: Arc<DB>,
     cache: Arc<Cache>,
 ) -> Result<ExportTraceServiceResponse> {
-    if !is_feature_enabled(Feature::FullBuild) {
-        for resource_span in request.resource_spans {
-            for scope_span in resource_span.scope_spans {
-                for otel_span in scope_span.spans {
-                    let mut span =
-                        Span::from_otel_span(otel_span.clone(), &project_id, storage.clone()).await;
-
-                    let span_usage = super::utils::get_llm_usage_for_span(
-                        &mut span.get_attributes(),
-                        db.clone(),
-                        cache.clone(),
-                    )
-                    .await;
-
-                    if let Err(e) =
-                        record_span_to_db(db.clone(), &span_usage, &project_id, &mut span).await
-                    {
-                        log::error!(
-                            "Failed to record span. span_id [{}], project_id [{}]: {:?}",
-                            span.span_id,
-                            project_id,
-                            e
-                        );
-                    }
-                }
-            }
-        }
-        return Ok(ExportTraceServiceResponse {
-            partial_success: None,
-        });
-    }
-    // Safe to unwrap because we checked is_feature_enabled above
-    let channel = rabbitmq_connection.unwrap().create_channel().await?;
+    let mut rejected_spans = RejectedSpans::default();
+    let redaction_rules = get_redaction_rules(db, cache, &project_id).await?;
 
     for resource_span in request.resource_spans {
+        let resource_attributes = resource_span
+            .resource
+            .map(|resource| {
+                resource
+                    .attributes
+                    .into_iter()
+                    .filter(|kv| kv.value.as_ref().is_some_and(|v| v.value.is_some()))
+                    .map(|kv| (kv.key, convert_any_value_to_json_value(kv.value)))
+                    .collect::<serde_json::Map<String, serde_json::Value>>()
+            })
+            .unwrap_or_default();
+
         for scope_span in resource_span.scope_spans {
-            for otel_span in scope_span.spans {
-                let span =
-                    Span::from_otel_span(otel_span.clone(), &project_id, storage.clone()).await;
+            let (scope_name, scope_version) = match scope_span.scope {
+                Some(scope) => (
+                    (!scope.name.is_empty()).then_some(scope.name),
+                    (!scope.version.is_empty()).then_some(scope.version),
+                ),
+                None => (None, None),
+            };
+
+            for mut otel_span in scope_span.spans {
+                redaction_rules.redact_otel_span(&mut otel_span);
+                let mut span =
+                    match Span::from_otel_span(otel_span.clone(), &project_id, storage.clone())
+                        .await
+                    {
+                        Ok(span) => span,
+                        Err(e) => {
+                            log::warn!("Rejecting malformed span: {:?}", e);
+                            rejected_spans.reject(e.to_string());
+                            continue;
+                        }
+                    };
+                span.resource_attributes = serde_json::Value::Object(resource_attributes.clone());
+                span.scope_name = scope_name.clone();
+                span.scope_version = scope_version.clone();
+                redaction_rules.redact_span(&mut span);
+                let span_id = span.span_id;
 
                 let mut events = vec![];
+                let mut span_events = vec![];
 
                 for event in otel_span.events {
+                    // Streamed completions are recorded on the span as a whole
+                    if event.name == "llm.content.completion.chunk" {
+                        continue;
+                    }
+
                     let event_attributes = event
                         .attributes
                         .clone()
@@ -82,19 +126,21 @@ pub async fn push_spans_to_queue(
                         .map(|kv| (kv.key, convert_any_value_to_json_value(kv.value)))
                         .collect::<serde_json::Map<String, serde_json::Value>>();
 
-                    let Some(serde_json::Value::String(event_type)) =
-                        event_attributes.get(EVENT_TYPE)
-                    else {
-                        if event.name != "llm.content.completion.chunk" {
-                            log::warn!("Unknown event type: {:?}", event);
+                    // Laminar events are sent as span events with `lmnr.event.type` set to "default",
+                    // everything else, e.g. exceptions, is kept as a regular OTel span event
+                    match event_attributes.get(EVENT_TYPE) {
+                        Some(serde_json::Value::String(event_type)) if event_type == "default" => {
+                            let mut event = EventObservation::from_otel(event, span.span_id);
+                            if let Some(value) = event.value.as_mut() {
+                                redaction_rules.redact_value(value);
+                            }
+                            events.push(event);
+                        }
+                        _ => {
+                            let mut span_event = SpanEvent::from_otel(event, span.span_id);
+                            redaction_rules.redact_value(&mut span_event.attributes);
+                            span_events.push(span_event);
                         }
-                        continue;
-                    };
-
-                    if event_type == "default" {
-                        events.push(EventObservation::from_otel(event, span.span_id));
-                    } else {
-                        log::warn!("Unknown event type: {}", event_type);
                     }
                 }
 
@@ -102,28 +148,113 @@ pub async fn push_spans_to_queue(
                     project_id,
                     span,
                     events,
+                    span_events,
                 };
 
                 let payload = serde_json::to_string(&rabbitmq_span_message).unwrap();
                 let payload = payload.as_bytes();
 
-                channel
-                    .basic_publish(
-                        OBSERVATIONS_EXCHANGE,
-                        OBSERVATIONS_ROUTING_KEY,
-                        BasicPublishOptions::default(),
-                        payload,
-                        BasicProperties::default(),
-                    )
-                    .await?
-                    .await?;
+                if let Err(e) = queue
+                    .publish(payload, OBSERVATIONS_EXCHANGE, OBSERVATIONS_ROUTING_KEY)
+                    .await
+                {
+                    log::error!(
+                        "Failed to publish span. span_id [{}], project_id [{}]: {:?}",
+                        span_id,
+                        project_id,
+                        e
+                    );
+                    rejected_spans.reject(format!("Failed to publish span {}: {}", span_id, e));
+                }
             }
         }
     }
 
-    let response = ExportTraceServiceResponse {
-        partial_success: None,
+    Ok(rejected_spans.into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{any::TypeId, collections::HashMap};
+
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::{
+        cache::cache::CacheTrait,
+        db::utils::span_id_to_uuid,
+        mq::tokio_mpsc::TokioMpscQueue,
+        opentelemetry::opentelemetry_proto_trace_v1::{
+            ResourceSpans, ScopeSpans, Span as OtelSpan,
+        },
+        storage::mock::MockStorage,
+        traces::{redaction::RedactionRules, OBSERVATIONS_QUEUE},
     };
 
-    Ok(response)
+    fn otel_span(span_id: Vec<u8>) -> OtelSpan {
+        OtelSpan {
+            trace_id: vec![1; 16],
+            span_id,
+            name: "span".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_malformed_spans_are_rejected_one_by_one() {
+        let project_id = Uuid::new_v4();
+        // Redaction rules are cached, so the database is never queried
+        let redaction_rules_cache: Arc<moka::future::Cache<String, RedactionRules>> =
+            Arc::new(moka::future::Cache::new(1));
+        redaction_rules_cache
+            .insert(project_id.to_string(), RedactionRules::default())
+            .await;
+        let mut caches: HashMap<TypeId, Arc<dyn CacheTrait>> = HashMap::new();
+        caches.insert(TypeId::of::<RedactionRules>(), redaction_rules_cache);
+        let db = DB::new(PgPool::connect_lazy("postgres://localhost/unused").unwrap());
+        let queue = Arc::new(TokioMpscQueue::new());
+        let mut receiver = queue
+            .get_receiver(
+                OBSERVATIONS_QUEUE,
+                OBSERVATIONS_EXCHANGE,
+                OBSERVATIONS_ROUTING_KEY,
+                1,
+            )
+            .await
+            .unwrap();
+
+        let request = ExportTraceServiceRequest {
+            resource_spans: vec![ResourceSpans {
+                scope_spans: vec![ScopeSpans {
+                    spans: vec![
+                        otel_span(vec![1; 4]),
+                        otel_span(vec![2; 8]),
+                        otel_span(vec![3; 16]),
+                    ],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+        let response = push_spans_to_queue(
+            request,
+            project_id,
+            queue,
+            Arc::new(MockStorage),
+            Arc::new(db),
+            Arc::new(Cache::new(caches)),
+        )
+        .await
+        .unwrap();
+
+        let partial_success = response.partial_success.unwrap();
+        assert_eq!(partial_success.rejected_spans, 2);
+        assert_eq!(
+            partial_success.error_message,
+            "2 span(s) rejected. First error: Invalid span_id: expected 8 bytes, got 4"
+        );
+        let delivery = receiver.receive().await.unwrap().unwrap();
+        let message = serde_json::from_slice::<RabbitMqSpanMessage>(&delivery.data).unwrap();
+        assert_eq!(message.span.span_id, span_id_to_uuid(&[2; 8]));
+    }
 }
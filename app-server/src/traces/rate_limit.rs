@@ -0,0 +1,151 @@
+//! Token bucket rate limits of span and log exports, configured per project API key.
+//!
+//! Each exported span or log record takes a token from the same bucket, and tokens are
+//! refilled at the rate of the key.
+//! Buckets are kept in memory, so with several instances the limit applies to each of them.
+
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+
+use crate::{
+    db::project_api_keys::ProjectApiKey,
+    opentelemetry::opentelemetry::proto::collector::{
+        logs::v1::ExportLogsServiceRequest, trace::v1::ExportTraceServiceRequest,
+    },
+};
+
+pub struct RateLimited {
+    pub retry_after: Duration,
+}
+
+impl RateLimited {
+    /// Whole seconds, as `Retry-After` does not allow fractions
+    pub fn retry_after_secs(&self) -> u64 {
+        self.retry_after.as_secs_f64().ceil().max(1.0) as u64
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// Buckets keyed by the hash of the API key
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: DashMap<String, TokenBucket>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn check(&self, api_key: &ProjectApiKey, records: usize) -> Result<(), RateLimited> {
+        let Some(rate) = api_key.rate_limit_per_second.filter(|rate| *rate > 0.0) else {
+            return Ok(());
+        };
+        let burst = api_key
+            .rate_limit_burst
+            .map_or(rate, |burst| burst as f64)
+            .max(1.0);
+        let cost = records.max(1) as f64;
+        // Requests with more records than the bucket holds go through once it is full,
+        // otherwise they would never be accepted. They are still charged in full, so the
+        // balance goes negative and the following requests wait until it is paid back.
+        let required = cost.min(burst);
+
+        let now = Instant::now();
+        let mut bucket = self
+            .buckets
+            .entry(api_key.hash.clone())
+            .or_insert(TokenBucket {
+                tokens: burst,
+                refilled_at: now,
+            });
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+        bucket.refilled_at = now;
+
+        if bucket.tokens >= required {
+            bucket.tokens -= cost;
+            Ok(())
+        } else {
+            Err(RateLimited {
+                retry_after: Duration::from_secs_f64((required - bucket.tokens) / rate),
+            })
+        }
+    }
+}
+
+pub fn count_spans(request: &ExportTraceServiceRequest) -> usize {
+    request
+        .resource_spans
+        .iter()
+        .flat_map(|resource_spans| &resource_spans.scope_spans)
+        .map(|scope_spans| scope_spans.spans.len())
+        .sum()
+}
+
+pub fn count_log_records(request: &ExportLogsServiceRequest) -> usize {
+    request
+        .resource_logs
+        .iter()
+        .flat_map(|resource_logs| &resource_logs.scope_logs)
+        .map(|scope_logs| scope_logs.log_records.len())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[test]
+    fn test_bucket_is_drained_and_refilled() {
+        let rate_limiter = RateLimiter::new();
+        let api_key = ProjectApiKey {
+            project_id: Uuid::nil(),
+            name: None,
+            hash: String::from("hash"),
+            shorthand: String::new(),
+            rate_limit_per_second: Some(10.0),
+            rate_limit_burst: Some(20),
+        };
+
+        assert!(rate_limiter.check(&api_key, 15).is_ok());
+        let rate_limited = rate_limiter.check(&api_key, 10).err().unwrap();
+        assert!(rate_limited.retry_after > Duration::from_millis(400));
+        assert_eq!(rate_limited.retry_after_secs(), 1);
+
+        // Refilled at 10 tokens per second
+        rate_limiter.buckets.get_mut("hash").unwrap().refilled_at -= Duration::from_secs(1);
+        assert!(rate_limiter.check(&api_key, 10).is_ok());
+    }
+
+    #[test]
+    fn test_large_requests_are_charged_in_full() {
+        let rate_limiter = RateLimiter::new();
+        let api_key = ProjectApiKey {
+            project_id: Uuid::nil(),
+            name: None,
+            hash: String::from("hash"),
+            shorthand: String::new(),
+            rate_limit_per_second: Some(10.0),
+            rate_limit_burst: Some(20),
+        };
+
+        // Accepted with a full bucket, which leaves a debt of 80 tokens
+        assert!(rate_limiter.check(&api_key, 100).is_ok());
+        let rate_limited = rate_limiter.check(&api_key, 1).err().unwrap();
+        assert_eq!(rate_limited.retry_after_secs(), 9);
+
+        // 100 spans are accepted at most every 10 seconds, the same as 20 spans every 2 seconds
+        rate_limiter.buckets.get_mut("hash").unwrap().refilled_at -= Duration::from_secs(9);
+        assert!(rate_limiter.check(&api_key, 100).is_err());
+        rate_limiter.buckets.get_mut("hash").unwrap().refilled_at -= Duration::from_secs(1);
+        assert!(rate_limiter.check(&api_key, 100).is_ok());
+    }
+}
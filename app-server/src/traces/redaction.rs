@@ -0,0 +1,330 @@
+//! Redacts personal data and secrets from spans before they are queued, so that nothing
+//! matched by the project's redaction rules reaches Postgres, Clickhouse or the semantic
+//! search index.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use regex::{Captures, Regex};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::{
+    cache::Cache,
+    db::{
+        self,
+        redaction_rules::{RedactionRule, RedactionRuleType},
+        spans::Span,
+        DB,
+    },
+    opentelemetry::{
+        opentelemetry_proto_common_v1::{any_value, AnyValue, KeyValue},
+        opentelemetry_proto_trace_v1::Span as OtelSpan,
+    },
+};
+
+pub const REDACTED: &str = "[REDACTED]";
+
+const EMAIL_PATTERN: &str = r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}";
+/// Requires separators between the groups, so that plain numbers, e.g. ids, are kept
+const PHONE_PATTERN: &str = r"(?:\+\d{1,3}[-. ]?)?(?:\(\d{3}\)|\b\d{3})[-. ]?\d{3}[-. ]\d{4}\b";
+/// Matches are only redacted if they pass the Luhn check
+const CARD_NUMBER_PATTERN: &str = r"\b(?:\d[ -]?){12,18}\d\b";
+
+#[derive(Clone)]
+struct RedactionPattern {
+    regex: Regex,
+    replacement: &'static str,
+    luhn_check: bool,
+}
+
+/// Compiled redaction rules of a project
+#[derive(Clone, Default)]
+pub struct RedactionRules {
+    patterns: Vec<RedactionPattern>,
+    /// Lowercased keys of attributes, and of objects within input and output,
+    /// whose values are replaced as a whole
+    attribute_keys: Vec<String>,
+}
+
+impl RedactionRules {
+    pub fn from_rules(rules: &[RedactionRule]) -> Self {
+        let mut redaction_rules = Self::default();
+        for rule in rules {
+            let (pattern, replacement, luhn_check) = match rule.rule_type {
+                RedactionRuleType::EMAIL => (EMAIL_PATTERN, "[REDACTED_EMAIL]", false),
+                RedactionRuleType::PHONE => (PHONE_PATTERN, "[REDACTED_PHONE]", false),
+                RedactionRuleType::CARD_NUMBER => {
+                    (CARD_NUMBER_PATTERN, "[REDACTED_CARD_NUMBER]", true)
+                }
+                RedactionRuleType::PATTERN => match &rule.value {
+                    Some(pattern) => (pattern.as_str(), REDACTED, false),
+                    None => continue,
+                },
+                RedactionRuleType::ATTRIBUTE => {
+                    if let Some(key) = &rule.value {
+                        redaction_rules.attribute_keys.push(key.to_lowercase());
+                    }
+                    continue;
+                }
+            };
+            // Patterns are validated when rules are created, so this only skips rules
+            // which were inserted into the database directly
+            match Regex::new(pattern) {
+                Ok(regex) => redaction_rules.patterns.push(RedactionPattern {
+                    regex,
+                    replacement,
+                    luhn_check,
+                }),
+                Err(e) => log::error!("Invalid redaction pattern, rule [{}]: {:?}", rule.id, e),
+            }
+        }
+
+        redaction_rules
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty() && self.attribute_keys.is_empty()
+    }
+
+    /// Redacts the span as it is received, before its input and output are extracted
+    /// from the attributes and events, and before any payload is uploaded to storage
+    pub fn redact_otel_span(&self, otel_span: &mut OtelSpan) {
+        if self.is_empty() {
+            return;
+        }
+        otel_span.name = self.redact_str(&otel_span.name);
+        self.redact_key_values(&mut otel_span.attributes);
+        for event in otel_span.events.iter_mut() {
+            self.redact_key_values(&mut event.attributes);
+        }
+        for link in otel_span.links.iter_mut() {
+            self.redact_key_values(&mut link.attributes);
+        }
+        if let Some(status) = otel_span.status.as_mut() {
+            status.message = self.redact_str(&status.message);
+        }
+    }
+
+    /// Also redacts keys within input and output, which are only parsed from JSON strings
+    /// once the span is converted
+    pub fn redact_span(&self, span: &mut Span) {
+        if self.is_empty() {
+            return;
+        }
+        span.name = self.redact_str(&span.name);
+        self.redact_value(&mut span.attributes);
+        self.redact_value(&mut span.resource_attributes);
+        if let Some(input) = span.input.as_mut() {
+            self.redact_value(input);
+        }
+        if let Some(output) = span.output.as_mut() {
+            self.redact_value(output);
+        }
+        if let Some(status_message) = span.status_message.as_mut() {
+            *status_message = self.redact_str(status_message);
+        }
+    }
+
+    pub fn redact_value(&self, value: &mut Value) {
+        match value {
+            Value::String(s) => *s = self.redact_str(s),
+            Value::Array(values) => values.iter_mut().for_each(|v| self.redact_value(v)),
+            Value::Object(map) => {
+                for (key, v) in map.iter_mut() {
+                    if self.is_redacted_key(key) {
+                        *v = Value::String(REDACTED.to_string());
+                    } else {
+                        self.redact_value(v);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn redact_key_values(&self, key_values: &mut [KeyValue]) {
+        for kv in key_values.iter_mut() {
+            if self.is_redacted_key(&kv.key) {
+                kv.value = Some(AnyValue {
+                    value: Some(any_value::Value::StringValue(REDACTED.to_string())),
+                });
+            } else if let Some(value) = kv.value.as_mut() {
+                self.redact_any_value(value);
+            }
+        }
+    }
+
+    fn redact_any_value(&self, value: &mut AnyValue) {
+        match value.value.as_mut() {
+            Some(any_value::Value::StringValue(s)) => *s = self.redact_str(s),
+            Some(any_value::Value::ArrayValue(array)) => array
+                .values
+                .iter_mut()
+                .for_each(|v| self.redact_any_value(v)),
+            Some(any_value::Value::KvlistValue(kvlist)) => {
+                self.redact_key_values(&mut kvlist.values)
+            }
+            _ => {}
+        }
+    }
+
+    fn redact_str(&self, s: &str) -> String {
+        self.patterns.iter().fold(s.to_string(), |s, pattern| {
+            pattern
+                .regex
+                .replace_all(&s, |caps: &Captures| {
+                    if pattern.luhn_check && !is_luhn_valid(&caps[0]) {
+                        caps[0].to_string()
+                    } else {
+                        pattern.replacement.to_string()
+                    }
+                })
+                .into_owned()
+        })
+    }
+
+    /// Keys match case-insensitively, either exactly or as a part of a flattened key,
+    /// e.g. `authorization` matches `http.request.header.authorization`
+    fn is_redacted_key(&self, key: &str) -> bool {
+        let key = key.to_lowercase();
+        self.attribute_keys.iter().any(|redacted_key| {
+            key == *redacted_key
+                || key.ends_with(&format!(".{redacted_key}"))
+                || key.starts_with(&format!("{redacted_key}."))
+        })
+    }
+}
+
+fn is_luhn_valid(number: &str) -> bool {
+    let digits = number
+        .chars()
+        .filter_map(|c| c.to_digit(10))
+        .collect::<Vec<_>>();
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| {
+            if i % 2 == 1 {
+                let doubled = d * 2;
+                if doubled > 9 {
+                    doubled - 9
+                } else {
+                    doubled
+                }
+            } else {
+                d
+            }
+        })
+        .sum();
+    sum % 10 == 0
+}
+
+pub async fn get_redaction_rules(
+    db: Arc<DB>,
+    cache: Arc<Cache>,
+    project_id: &Uuid,
+) -> Result<RedactionRules> {
+    let cache_key = project_id.to_string();
+    if let Ok(Some(rules)) = cache.get::<RedactionRules>(&cache_key).await {
+        return Ok(rules);
+    }
+
+    let rules = db::redaction_rules::get_redaction_rules(&db.pool, project_id).await?;
+    let rules = RedactionRules::from_rules(&rules);
+    let _ = cache.insert::<RedactionRules>(cache_key, &rules).await;
+
+    Ok(rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use serde_json::json;
+
+    use super::*;
+
+    fn rule(rule_type: RedactionRuleType, value: Option<&str>) -> RedactionRule {
+        RedactionRule {
+            id: Uuid::new_v4(),
+            created_at: Utc::now(),
+            project_id: Uuid::nil(),
+            rule_type,
+            value: value.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_redact_value() {
+        let rules = RedactionRules::from_rules(&[
+            rule(RedactionRuleType::EMAIL, None),
+            rule(RedactionRuleType::PHONE, None),
+            rule(RedactionRuleType::CARD_NUMBER, None),
+            rule(RedactionRuleType::PATTERN, Some(r"sk-[A-Za-z0-9]+")),
+            rule(RedactionRuleType::ATTRIBUTE, Some("Authorization")),
+        ]);
+
+        let mut value = json!({
+            "http.request.header.authorization": "Bearer token",
+            "messages": [{
+                "role": "user",
+                "content": "I'm jane@example.com, call +1 415-555-2671, card 4242 4242 4242 4242",
+            }],
+            "order_id": "1234567812345678",
+            "key": "sk-abc123",
+            "tokens": 42,
+        });
+        rules.redact_value(&mut value);
+
+        assert_eq!(
+            value,
+            json!({
+                "http.request.header.authorization": REDACTED,
+                "messages": [{
+                    "role": "user",
+                    "content": "I'm [REDACTED_EMAIL], call [REDACTED_PHONE], card [REDACTED_CARD_NUMBER]",
+                }],
+                "order_id": "1234567812345678",
+                "key": REDACTED,
+                "tokens": 42,
+            })
+        );
+    }
+
+    #[test]
+    fn test_redact_otel_span() {
+        let rules = RedactionRules::from_rules(&[
+            rule(RedactionRuleType::EMAIL, None),
+            rule(RedactionRuleType::ATTRIBUTE, Some("api_key")),
+        ]);
+        let string_value = |s: &str| {
+            Some(AnyValue {
+                value: Some(any_value::Value::StringValue(s.to_string())),
+            })
+        };
+
+        let mut otel_span = OtelSpan {
+            name: "lookup jane@example.com".to_string(),
+            attributes: vec![
+                KeyValue {
+                    key: "gen_ai.prompt.0.content".to_string(),
+                    value: string_value("Write to jane@example.com"),
+                },
+                KeyValue {
+                    key: "tool.api_key".to_string(),
+                    value: string_value("secret"),
+                },
+            ],
+            ..Default::default()
+        };
+        rules.redact_otel_span(&mut otel_span);
+
+        assert_eq!(otel_span.name, "lookup [REDACTED_EMAIL]");
+        assert_eq!(
+            otel_span.attributes[0].value,
+            string_value("Write to [REDACTED_EMAIL]")
+        );
+        assert_eq!(otel_span.attributes[1].value, string_value(REDACTED));
+    }
+}
@@ -0,0 +1,395 @@
+//! Decides which traces are recorded, following the project's sampling rules.
+//!
+//! Head sampling keeps a share of traces, decided by the trace id, so that all spans of a
+//! trace get the same decision on every instance. Tail sampling then keeps the dropped
+//! traces which turn out to be errored, slow or expensive. Spans of dropped traces are held
+//! in memory for a while, and recorded along with the rest of the trace if it is kept.
+//!
+//! Deliveries of held spans stay unacked until the decision is made, so that a restart
+//! redelivers them instead of losing them. Once a held trace expires, its spans are only
+//! written to Clickhouse, so that metrics still count them.
+//!
+//! Tail sampling decisions and held spans live in the memory of each instance. Spans of a
+//! trace which are consumed by different instances may get different decisions, and spans
+//! released before their trace expires, because too many are held, are lost for the trace.
+//! Such spans are counted and logged, so that the limits can be tuned.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use moka::{future::Cache as MokaCache, notification::RemovalCause};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::{
+    cache::Cache,
+    db::{
+        self, events::EventObservation, sampling_rules::SamplingRules, span_events::SpanEvent,
+        spans::Span, DB,
+    },
+    mq::MessageQueueAcker,
+};
+
+use super::{
+    batch_writer::{write_span_to_clickhouse, SpanWriteRequest},
+    spans::SpanUsage,
+};
+
+/// How long spans of a dropped trace are held, waiting for the trace to be kept
+const PENDING_TRACE_TTL: Duration = Duration::from_secs(60);
+const MAX_PENDING_TRACES: u64 = 10_000;
+/// Held spans keep their deliveries unacked, so the queue prefetch must leave room for them
+const MAX_HELD_SPANS: usize = 20_000;
+/// Spans released early are counted, but only logged this often
+const RELEASED_EARLY_LOG_INTERVAL: Duration = Duration::from_secs(60);
+/// Expired traces are otherwise only released as new spans arrive
+const RELEASE_EXPIRED_INTERVAL: Duration = Duration::from_secs(10);
+/// Spans which arrive after the trace is kept by tail sampling are kept as well
+const KEPT_TRACE_TTL: Duration = Duration::from_secs(600);
+
+/// Span waiting to be recorded, along with its delivery.
+/// Spans of dropped traces are held back until their trace is either kept or expires.
+pub struct PendingSpan {
+    pub project_id: Uuid,
+    pub span: Span,
+    pub span_usage: SpanUsage,
+    pub span_events: Vec<SpanEvent>,
+    pub events: Vec<EventObservation>,
+    pub acker: Arc<dyn MessageQueueAcker>,
+    /// Writer of the listener which received the span
+    pub span_writer: Option<mpsc::Sender<SpanWriteRequest>>,
+}
+
+impl PendingSpan {
+    /// The trace is dropped, so the span is only written to Clickhouse, which acks its delivery
+    async fn release(mut self) {
+        // Recorded spans get their path extended when they are written to the DB
+        let mut span_attributes = self.span.get_attributes();
+        span_attributes.extend_span_path(&self.span.name);
+        self.span.set_attributes(&span_attributes);
+        write_span_to_clickhouse(
+            &self.span_writer,
+            &self.span,
+            self.span_usage,
+            self.project_id,
+            self.acker,
+        )
+        .await;
+    }
+}
+
+#[derive(Default)]
+struct PendingTrace {
+    spans: Vec<PendingSpan>,
+    start_time: Option<DateTime<Utc>>,
+    end_time: Option<DateTime<Utc>>,
+    has_error: bool,
+    total_cost: f64,
+    /// Set once the trace is kept or expires, after which no spans may be added
+    closed: bool,
+}
+
+impl PendingTrace {
+    fn add_span_stats(&mut self, span: &Span, span_usage: &SpanUsage) {
+        self.start_time = Some(self.start_time.map_or(span.start_time, |start_time| {
+            start_time.min(span.start_time)
+        }));
+        self.end_time = Some(
+            self.end_time
+                .map_or(span.end_time, |end_time| end_time.max(span.end_time)),
+        );
+        self.has_error |= span.has_error;
+        self.total_cost += span_usage.total_cost;
+    }
+
+    fn close(&mut self) -> Vec<PendingSpan> {
+        self.closed = true;
+        std::mem::take(&mut self.spans)
+    }
+
+    fn is_kept_by(&self, rules: &SamplingRules) -> bool {
+        let duration_ms = match (self.start_time, self.end_time) {
+            (Some(start_time), Some(end_time)) => (end_time - start_time).num_milliseconds(),
+            _ => 0,
+        };
+
+        (rules.keep_errors && self.has_error)
+            || rules
+                .slow_trace_ms
+                .is_some_and(|slow_trace_ms| duration_ms >= slow_trace_ms)
+            || rules
+                .expensive_trace_cost
+                .is_some_and(|expensive_trace_cost| self.total_cost >= expensive_trace_cost)
+    }
+}
+
+pub struct TailSampler {
+    pending_traces: MokaCache<Uuid, Arc<Mutex<PendingTrace>>>,
+    kept_traces: MokaCache<Uuid, ()>,
+    held_spans: Arc<AtomicUsize>,
+    /// Spans released before their trace could be kept, because too many were held
+    released_early_spans: Arc<AtomicU64>,
+    released_early_logged_at: Mutex<Option<Instant>>,
+    /// Number of listeners which share the held spans
+    listeners: usize,
+}
+
+impl TailSampler {
+    pub fn new(listeners: usize) -> Self {
+        let held_spans = Arc::new(AtomicUsize::new(0));
+        let released_spans = held_spans.clone();
+        let released_early_spans = Arc::new(AtomicU64::new(0));
+        let evicted_spans = released_early_spans.clone();
+
+        Self {
+            pending_traces: MokaCache::builder()
+                .max_capacity(MAX_PENDING_TRACES)
+                .time_to_live(PENDING_TRACE_TTL)
+                .eviction_listener(
+                    move |trace_id, pending_trace: Arc<Mutex<PendingTrace>>, cause| {
+                        // Traces removed explicitly are kept, and their spans are taken by `take_held`
+                        if !cause.was_evicted() {
+                            return;
+                        }
+                        let spans = pending_trace.lock().unwrap().close();
+                        released_spans.fetch_sub(spans.len(), Ordering::Relaxed);
+                        if cause == RemovalCause::Size {
+                            let total = evicted_spans
+                                .fetch_add(spans.len() as u64, Ordering::Relaxed)
+                                + spans.len() as u64;
+                            log::warn!(
+                                "Too many pending traces, releasing {} spans of trace [{}] before it expires. Spans released early so far: {}",
+                                spans.len(),
+                                trace_id,
+                                total,
+                            );
+                        }
+                        tokio::spawn(release_spans(spans));
+                    },
+                )
+                .build(),
+            kept_traces: MokaCache::builder()
+                .max_capacity(MAX_PENDING_TRACES)
+                .time_to_live(KEPT_TRACE_TTL)
+                .build(),
+            held_spans,
+            released_early_spans,
+            released_early_logged_at: Mutex::new(None),
+            listeners: listeners.max(1),
+        }
+    }
+
+    /// Share of the held spans of each listener, for which it must prefetch deliveries
+    pub fn held_spans_per_listener(&self) -> usize {
+        MAX_HELD_SPANS.div_ceil(self.listeners)
+    }
+
+    /// Releases the spans of expired traces periodically. Runs forever.
+    pub async fn release_expired_traces(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(RELEASE_EXPIRED_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.pending_traces.run_pending_tasks().await;
+        }
+    }
+
+    /// Whether the span must be recorded. If it is not, it must be passed to `hold`,
+    /// so that it is recorded later in case the trace is kept by tail sampling.
+    pub async fn sample(&self, rules: &SamplingRules, span: &Span, span_usage: &SpanUsage) -> bool {
+        if is_head_sampled(rules.head_sample_rate, &span.trace_id)
+            || self.kept_traces.contains_key(&span.trace_id)
+        {
+            return true;
+        }
+
+        let pending_trace = self.pending_trace(span.trace_id).await;
+        let is_kept = {
+            let mut pending_trace = pending_trace.lock().unwrap();
+            pending_trace.add_span_stats(span, span_usage);
+            pending_trace.is_kept_by(rules)
+        };
+        if is_kept {
+            self.kept_traces.insert(span.trace_id, ()).await;
+        }
+
+        is_kept
+    }
+
+    /// Holds the span until its trace is kept or expires. If too many spans are held already,
+    /// the span is released right away, as if its trace was dropped.
+    ///
+    /// Returns the span back if its trace was kept in the meantime, e.g. by a span processed
+    /// by another listener, in which case it must be recorded like the other held spans.
+    pub async fn hold(&self, pending_span: PendingSpan) -> Option<PendingSpan> {
+        if self.held_spans.fetch_add(1, Ordering::Relaxed) >= MAX_HELD_SPANS {
+            self.held_spans.fetch_sub(1, Ordering::Relaxed);
+            let total = self.released_early_spans.fetch_add(1, Ordering::Relaxed) + 1;
+            if self.should_log_released_early() {
+                log::warn!(
+                    "Too many held spans, releasing spans without waiting for their traces. Spans released early so far: {}",
+                    total,
+                );
+            }
+            pending_span.release().await;
+            return None;
+        }
+
+        let trace_id = pending_span.span.trace_id;
+        let pending_span = {
+            let pending_trace = self.pending_trace(trace_id).await;
+            let mut pending_trace = pending_trace.lock().unwrap();
+            if !pending_trace.closed {
+                pending_trace.spans.push(pending_span);
+                return None;
+            }
+            pending_span
+        };
+
+        self.held_spans.fetch_sub(1, Ordering::Relaxed);
+        if self.kept_traces.contains_key(&trace_id) {
+            return Some(pending_span);
+        }
+        pending_span.release().await;
+        None
+    }
+
+    /// Takes the spans held before the trace was kept
+    pub async fn take_held(&self, trace_id: &Uuid) -> Vec<PendingSpan> {
+        let spans = match self.pending_traces.remove(trace_id).await {
+            Some(pending_trace) => pending_trace.lock().unwrap().close(),
+            None => Vec::new(),
+        };
+        self.held_spans.fetch_sub(spans.len(), Ordering::Relaxed);
+        spans
+    }
+
+    /// Whether the spans released early were not logged for `RELEASED_EARLY_LOG_INTERVAL`
+    fn should_log_released_early(&self) -> bool {
+        let mut logged_at = self.released_early_logged_at.lock().unwrap();
+        let now = Instant::now();
+        if logged_at.is_some_and(|logged_at| now - logged_at < RELEASED_EARLY_LOG_INTERVAL) {
+            return false;
+        }
+        *logged_at = Some(now);
+        true
+    }
+
+    async fn pending_trace(&self, trace_id: Uuid) -> Arc<Mutex<PendingTrace>> {
+        self.pending_traces
+            .get_with(trace_id, async {
+                Arc::new(Mutex::new(PendingTrace::default()))
+            })
+            .await
+    }
+}
+
+async fn release_spans(spans: Vec<PendingSpan>) {
+    for span in spans {
+        span.release().await;
+    }
+}
+
+/// The rightmost 7 bytes of W3C trace ids are random, and so are they in v4 UUIDs,
+/// unlike the variant bits before them
+fn is_head_sampled(head_sample_rate: f64, trace_id: &Uuid) -> bool {
+    if head_sample_rate >= 1.0 {
+        return true;
+    }
+    let mut bytes = [0; 8];
+    bytes[1..].copy_from_slice(&trace_id.as_bytes()[9..]);
+    (u64::from_be_bytes(bytes) as f64 / (1u64 << 56) as f64) < head_sample_rate
+}
+
+pub async fn get_sampling_rules(
+    db: Arc<DB>,
+    cache: Arc<Cache>,
+    project_id: &Uuid,
+) -> Result<SamplingRules> {
+    let cache_key = project_id.to_string();
+    if let Ok(Some(rules)) = cache.get::<SamplingRules>(&cache_key).await {
+        return Ok(rules);
+    }
+
+    let rules = db::sampling_rules::get_sampling_rules(&db.pool, project_id)
+        .await?
+        .unwrap_or_default();
+    let _ = cache.insert::<SamplingRules>(cache_key, &rules).await;
+
+    Ok(rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traces::test_utils::span;
+
+    fn usage(total_cost: f64) -> SpanUsage {
+        SpanUsage {
+            input_tokens: 0,
+            output_tokens: 0,
+            total_tokens: 0,
+            input_cost: 0.0,
+            output_cost: 0.0,
+            total_cost,
+            request_model: None,
+            response_model: None,
+            provider_name: None,
+        }
+    }
+
+    #[test]
+    fn test_head_sampling_keeps_share_of_trace_ids() {
+        let low_trace_id = Uuid::from_u128((1 << 56) / 10);
+        let high_trace_id = Uuid::from_u128((1 << 56) / 10 * 9);
+
+        assert!(is_head_sampled(0.5, &low_trace_id));
+        assert!(!is_head_sampled(0.5, &high_trace_id));
+        assert!(!is_head_sampled(0.0, &low_trace_id));
+        assert!(is_head_sampled(1.0, &high_trace_id));
+
+        let kept = (0..10_000)
+            .filter(|_| is_head_sampled(0.25, &Uuid::new_v4()))
+            .count();
+        assert!((2_000..3_000).contains(&kept));
+    }
+
+    #[test]
+    fn test_pending_trace_is_kept_by_rules() {
+        let rules = SamplingRules {
+            head_sample_rate: 0.0,
+            keep_errors: true,
+            slow_trace_ms: Some(1000),
+            expensive_trace_cost: Some(0.5),
+        };
+
+        let mut trace = PendingTrace::default();
+        trace.add_span_stats(&span("root", None, 0, 400), &usage(0.2));
+        assert!(!trace.is_kept_by(&rules));
+
+        // Slow once the spans together take long enough
+        trace.add_span_stats(&span("late", None, 900, 1200), &usage(0.0));
+        assert!(trace.is_kept_by(&rules));
+
+        let mut trace = PendingTrace::default();
+        trace.add_span_stats(&span("llm", None, 0, 10), &usage(0.3));
+        trace.add_span_stats(&span("llm", None, 10, 20), &usage(0.3));
+        assert!(trace.is_kept_by(&rules));
+
+        let mut trace = PendingTrace::default();
+        let mut errored = span("errored", None, 0, 10);
+        errored.has_error = true;
+        trace.add_span_stats(&errored, &usage(0.0));
+        assert!(trace.is_kept_by(&rules));
+        assert!(!trace.is_kept_by(&SamplingRules {
+            keep_errors: false,
+            ..rules
+        }));
+    }
+}
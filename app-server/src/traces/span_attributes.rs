@@ -31,3 +31,14 @@ pub const SPAN_PATH: &str = "lmnr.span.path";
 pub const EVENT_TYPE: &str = "lmnr.event.type";
 pub const EVENT_VALUE: &str = "lmnr.event.value";
 pub const LLM_NODE_RENDERED_PROMPT: &str = "lmnr.span.prompt";
+
+// OTel resource attributes, see:
+// https://github.com/open-telemetry/semantic-conventions/blob/main/docs/resource/README.md
+pub const SERVICE_NAME: &str = "service.name";
+pub const DEPLOYMENT_ENVIRONMENT: &str = "deployment.environment";
+pub const HOST_NAME: &str = "host.name";
+pub const TELEMETRY_SDK_VERSION: &str = "telemetry.sdk.version";
+
+// OTel span event names, see:
+// https://github.com/open-telemetry/semantic-conventions/blob/main/docs/exceptions/exceptions-spans.md
+pub const EXCEPTION_EVENT_NAME: &str = "exception";
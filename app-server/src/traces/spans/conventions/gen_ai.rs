@@ -0,0 +1,173 @@
+//! OTel GenAI semantic conventions, which record messages as span events, see:
+//! https://github.com/open-telemetry/semantic-conventions/blob/main/docs/gen-ai/gen-ai-events.md
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde_json::{json, Map, Value};
+use uuid::Uuid;
+
+use crate::{
+    db::utils::convert_any_value_to_json_value,
+    language_model::{ChatMessageContent, ChatMessageContentPart},
+    opentelemetry::opentelemetry_proto_trace_v1::span::Event as OtelEvent,
+    storage::Storage,
+    traces::span_attributes::GEN_AI_SYSTEM,
+};
+
+use super::{
+    completion_output, insert_if_absent, parse_json_string, AttributeConvention, SpanPayload,
+    ToolCall,
+};
+
+/// Replaces `gen_ai.system` in newer versions of the conventions
+const GEN_AI_PROVIDER_NAME: &str = "gen_ai.provider.name";
+const CHOICE_EVENT_NAME: &str = "gen_ai.choice";
+/// Events of earlier versions, which carry all messages at once
+const PROMPT_EVENT_NAME: &str = "gen_ai.content.prompt";
+const COMPLETION_EVENT_NAME: &str = "gen_ai.content.completion";
+
+pub struct GenAiEvents;
+
+#[async_trait]
+impl AttributeConvention for GenAiEvents {
+    fn matches(&self, span: &SpanPayload<'_>) -> bool {
+        span.events.iter().any(|event| {
+            message_role(&event.name).is_some()
+                || event.name == CHOICE_EVENT_NAME
+                || event.name == PROMPT_EVENT_NAME
+                || event.name == COMPLETION_EVENT_NAME
+        })
+    }
+
+    fn normalize_attributes(&self, attributes: &mut Map<String, Value>) {
+        let provider = attributes.get(GEN_AI_PROVIDER_NAME).cloned();
+        insert_if_absent(attributes, GEN_AI_SYSTEM, provider);
+    }
+
+    async fn input(
+        &self,
+        span: &SpanPayload<'_>,
+        _project_id: &Uuid,
+        _storage: Arc<dyn Storage>,
+    ) -> Option<Value> {
+        if let Some(event) = span.events.iter().find(|e| e.name == PROMPT_EVENT_NAME) {
+            return event_attributes(event)
+                .get("gen_ai.prompt")
+                .map(parse_json_string);
+        }
+
+        let messages = span
+            .events
+            .iter()
+            .filter_map(|event| {
+                let role = message_role(&event.name)?;
+                let attributes = event_attributes(event);
+                let mut message = json!({
+                    "role": attributes
+                        .get("role")
+                        .and_then(|role| role.as_str())
+                        .unwrap_or(role),
+                    "content": attributes
+                        .get("content")
+                        .map(message_content)
+                        .unwrap_or(Value::String(String::new())),
+                });
+                for key in ["tool_calls", "id"] {
+                    if let Some(value) = attributes.get(key) {
+                        message[key] = parse_json_string(value);
+                    }
+                }
+                Some(message)
+            })
+            .collect::<Vec<_>>();
+
+        (!messages.is_empty()).then_some(Value::Array(messages))
+    }
+
+    fn output(&self, span: &SpanPayload<'_>) -> Option<Value> {
+        if let Some(event) = span.events.iter().find(|e| e.name == COMPLETION_EVENT_NAME) {
+            return event_attributes(event)
+                .get("gen_ai.completion")
+                .map(parse_json_string);
+        }
+
+        let choice = span
+            .events
+            .iter()
+            .filter(|event| event.name == CHOICE_EVENT_NAME)
+            .map(event_attributes)
+            .min_by_key(|attributes| {
+                attributes
+                    .get("index")
+                    .and_then(|index| index.as_i64())
+                    .unwrap_or(0)
+            })?;
+        // The message is either nested under `message` or flattened into the event
+        let message = match choice.get("message").map(parse_json_string) {
+            Some(Value::Object(message)) => message,
+            _ => choice,
+        };
+
+        let text = message
+            .get("content")
+            .and_then(|content| content.as_str())
+            .map(String::from);
+        let tool_calls = match message.get("tool_calls").map(parse_json_string) {
+            Some(Value::Array(tool_calls)) => tool_calls
+                .iter()
+                .filter_map(|tool_call| {
+                    let function = tool_call.get("function")?;
+                    Some(ToolCall::new(
+                        function.get("name")?.as_str()?.to_string(),
+                        tool_call
+                            .get("id")
+                            .and_then(|id| id.as_str())
+                            .map(String::from),
+                        function.get("arguments"),
+                    ))
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        completion_output(text, tool_calls)
+    }
+}
+
+/// Role of the message recorded by an event named `gen_ai.<role>.message`
+fn message_role(event_name: &str) -> Option<&str> {
+    event_name
+        .strip_prefix("gen_ai.")?
+        .strip_suffix(".message")
+        .filter(|role| ["system", "user", "assistant", "tool"].contains(role))
+}
+
+fn event_attributes(event: &OtelEvent) -> Map<String, Value> {
+    event
+        .attributes
+        .iter()
+        .filter(|kv| kv.value.as_ref().is_some_and(|v| v.value.is_some()))
+        .map(|kv| {
+            (
+                kv.key.clone(),
+                convert_any_value_to_json_value(kv.value.clone()),
+            )
+        })
+        .collect()
+}
+
+/// Content is either text, or a list of parts in the same shape as chat message parts
+fn message_content(content: &Value) -> Value {
+    match parse_json_string(content) {
+        Value::Array(parts) => {
+            match serde_json::from_value::<Vec<ChatMessageContentPart>>(Value::Array(parts.clone()))
+            {
+                Ok(parts) => json!(ChatMessageContent::ContentPartList(parts)),
+                Err(_) => Value::Array(parts),
+            }
+        }
+        Value::String(s) => Value::String(s),
+        other => Value::String(other.to_string()),
+    }
+}
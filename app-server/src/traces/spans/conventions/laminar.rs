@@ -0,0 +1,50 @@
+//! Laminar SDK's own attributes, recorded by `observe` decorated functions
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::storage::Storage;
+
+use super::{AttributeConvention, SpanPayload};
+
+const INPUT_ATTRIBUTE_NAME: &str = "lmnr.span.input";
+const OUTPUT_ATTRIBUTE_NAME: &str = "lmnr.span.output";
+
+pub struct Laminar;
+
+#[async_trait]
+impl AttributeConvention for Laminar {
+    fn matches(&self, span: &SpanPayload<'_>) -> bool {
+        span.attributes.contains_key(INPUT_ATTRIBUTE_NAME)
+            || span.attributes.contains_key(OUTPUT_ATTRIBUTE_NAME)
+    }
+
+    fn is_payload_attribute(&self, attribute: &str) -> bool {
+        attribute == INPUT_ATTRIBUTE_NAME || attribute == OUTPUT_ATTRIBUTE_NAME
+    }
+
+    async fn input(
+        &self,
+        span: &SpanPayload<'_>,
+        _project_id: &Uuid,
+        _storage: Arc<dyn Storage>,
+    ) -> Option<Value> {
+        parse_string_attribute(span.attributes.get(INPUT_ATTRIBUTE_NAME))
+    }
+
+    fn output(&self, span: &SpanPayload<'_>) -> Option<Value> {
+        parse_string_attribute(span.attributes.get(OUTPUT_ATTRIBUTE_NAME))
+    }
+}
+
+fn parse_string_attribute(value: Option<&Value>) -> Option<Value> {
+    match value {
+        Some(Value::String(s)) => {
+            Some(serde_json::from_str::<Value>(s).unwrap_or(Value::String(s.clone())))
+        }
+        _ => None,
+    }
+}
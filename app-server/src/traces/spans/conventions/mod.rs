@@ -0,0 +1,355 @@
+//! Instrumentation libraries record LLM calls following different semantic conventions.
+//!
+//! Each convention knows where its spans keep input, output, model and token usage. Model
+//! and usage are copied into the `gen_ai.*` attributes which span type, usage and cost are
+//! computed from, so the rest of the pipeline does not need to know about the conventions.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+use crate::{
+    db::spans::SpanType, opentelemetry::opentelemetry_proto_trace_v1::span::Event as OtelEvent,
+    storage::Storage,
+};
+
+mod gen_ai;
+mod laminar;
+mod openinference;
+mod openllmetry;
+mod vercel_ai;
+
+/// Parts of an OTel span which input and output are read from
+pub struct SpanPayload<'a> {
+    pub span_type: &'a SpanType,
+    pub attributes: &'a Map<String, Value>,
+    pub events: &'a [OtelEvent],
+}
+
+#[async_trait]
+pub trait AttributeConvention: Sync + Send {
+    /// Whether input and output of the span are recorded with this convention
+    fn matches(&self, span: &SpanPayload<'_>) -> bool;
+
+    /// Copies model, provider and token usage into the `gen_ai.*` attributes,
+    /// without overwriting the ones which are already set.
+    ///
+    /// Called for every span before its type is resolved.
+    fn normalize_attributes(&self, _attributes: &mut Map<String, Value>) {}
+
+    /// Whether the attribute is stored as span input or output, and so must not be duplicated
+    fn is_payload_attribute(&self, _attribute: &str) -> bool {
+        false
+    }
+
+    async fn input(
+        &self,
+        span: &SpanPayload<'_>,
+        project_id: &Uuid,
+        storage: Arc<dyn Storage>,
+    ) -> Option<Value>;
+
+    fn output(&self, span: &SpanPayload<'_>) -> Option<Value>;
+}
+
+/// Ordered by priority, input and output are read with the first convention which matches
+pub fn conventions() -> [&'static dyn AttributeConvention; 5] {
+    [
+        &openllmetry::OpenLLMetry,
+        &vercel_ai::VercelAi,
+        &laminar::Laminar,
+        &openinference::OpenInference,
+        &gen_ai::GenAiEvents,
+    ]
+}
+
+pub fn normalize_attributes(attributes: &mut Map<String, Value>) {
+    for convention in conventions() {
+        convention.normalize_attributes(attributes);
+    }
+}
+
+pub fn is_payload_attribute(attribute: &str) -> bool {
+    conventions()
+        .iter()
+        .any(|convention| convention.is_payload_attribute(attribute))
+}
+
+/// Returns input and output of the span, read with the first matching convention
+pub async fn span_input_output(
+    span: &SpanPayload<'_>,
+    project_id: &Uuid,
+    storage: Arc<dyn Storage>,
+) -> (Option<Value>, Option<Value>) {
+    match conventions()
+        .into_iter()
+        .find(|convention| convention.matches(span))
+    {
+        Some(convention) => (
+            convention.input(span, project_id, storage).await,
+            convention.output(span),
+        ),
+        None => (None, None),
+    }
+}
+
+/// Inserts the value under `key` unless it is already set
+fn insert_if_absent(attributes: &mut Map<String, Value>, key: &str, value: Option<Value>) {
+    if let Some(value) = value {
+        if !attributes.contains_key(key) {
+            attributes.insert(key.to_string(), value);
+        }
+    }
+}
+
+/// Parses JSON strings, keeping the string itself if it is not valid JSON
+fn parse_json_string(value: &Value) -> Value {
+    match value {
+        Value::String(s) => serde_json::from_str::<Value>(s).unwrap_or(value.clone()),
+        _ => value.clone(),
+    }
+}
+
+#[derive(Serialize)]
+struct ToolCall {
+    name: String,
+    id: Option<String>,
+    arguments: Option<Value>,
+    #[serde(rename = "type")]
+    content_block_type: String,
+}
+
+impl ToolCall {
+    fn new(name: String, id: Option<String>, arguments: Option<&Value>) -> Self {
+        Self {
+            name,
+            id,
+            arguments: arguments.map(parse_json_string),
+            content_block_type: "tool_call".to_string(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TextBlock {
+    content: String,
+    #[serde(rename = "type")]
+    content_block_type: String,
+}
+
+/// Output of a completion: plain text, or a list of content blocks if the model called tools
+fn completion_output(text: Option<String>, tool_calls: Vec<ToolCall>) -> Option<Value> {
+    if tool_calls.is_empty() {
+        return text.map(Value::String);
+    }
+
+    let mut blocks = Vec::new();
+    if let Some(text) = text {
+        blocks.push(
+            serde_json::to_value(TextBlock {
+                content: text,
+                content_block_type: "text".to_string(),
+            })
+            .unwrap(),
+        );
+    }
+    blocks.extend(
+        tool_calls
+            .into_iter()
+            .map(|tool_call| serde_json::to_value(tool_call).unwrap()),
+    );
+    Some(Value::Array(blocks))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::{
+        opentelemetry::opentelemetry_proto_common_v1::{any_value, AnyValue, KeyValue},
+        storage::mock::MockStorage,
+    };
+
+    use super::*;
+
+    async fn input_output(
+        span_type: SpanType,
+        attributes: Value,
+        events: &[OtelEvent],
+    ) -> (Option<Value>, Option<Value>) {
+        let Value::Object(attributes) = attributes else {
+            panic!("attributes must be an object");
+        };
+        let span = SpanPayload {
+            span_type: &span_type,
+            attributes: &attributes,
+            events,
+        };
+        span_input_output(&span, &Uuid::nil(), Arc::new(MockStorage)).await
+    }
+
+    fn event(name: &str, attributes: &[(&str, &str)]) -> OtelEvent {
+        OtelEvent {
+            name: name.to_string(),
+            attributes: attributes
+                .iter()
+                .map(|(key, value)| KeyValue {
+                    key: key.to_string(),
+                    value: Some(AnyValue {
+                        value: Some(any_value::Value::StringValue(value.to_string())),
+                    }),
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_openllmetry() {
+        let (input, output) = input_output(
+            SpanType::LLM,
+            json!({
+                "gen_ai.prompt.0.role": "system",
+                "gen_ai.prompt.0.content": "Be brief",
+                "gen_ai.prompt.1.content": "Hi",
+                "gen_ai.completion.0.content": "Hello",
+            }),
+            &[],
+        )
+        .await;
+
+        assert_eq!(
+            input,
+            Some(json!([
+                {"role": "system", "content": "Be brief"},
+                {"role": "user", "content": "Hi"},
+            ]))
+        );
+        assert_eq!(output, Some(json!("Hello")));
+    }
+
+    #[tokio::test]
+    async fn test_openinference() {
+        let (input, output) = input_output(
+            SpanType::DEFAULT,
+            json!({
+                "llm.input_messages.0.message.role": "user",
+                "llm.input_messages.0.message.content": "Find rust docs",
+                "llm.output_messages.0.message.content": "Searching",
+                "llm.output_messages.0.message.tool_calls.0.tool_call.id": "call_1",
+                "llm.output_messages.0.message.tool_calls.0.tool_call.function.name": "search",
+                "llm.output_messages.0.message.tool_calls.0.tool_call.function.arguments": "{\"q\": \"rust\"}",
+            }),
+            &[],
+        )
+        .await;
+
+        assert_eq!(
+            input,
+            Some(json!([{"role": "user", "content": "Find rust docs"}]))
+        );
+        assert_eq!(
+            output,
+            Some(json!([
+                {"content": "Searching", "type": "text"},
+                {"name": "search", "id": "call_1", "arguments": {"q": "rust"}, "type": "tool_call"},
+            ]))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_vercel_ai() {
+        let (input, output) = input_output(
+            SpanType::LLM,
+            json!({
+                "ai.prompt.messages": "[{\"role\": \"user\", \"content\": \"Hi\"}]",
+                "ai.response.text": "Hello",
+            }),
+            &[],
+        )
+        .await;
+
+        assert_eq!(input, Some(json!([{"role": "user", "content": "Hi"}])));
+        assert_eq!(output, Some(json!("Hello")));
+    }
+
+    #[tokio::test]
+    async fn test_gen_ai_events() {
+        let events = [
+            event("gen_ai.system.message", &[("content", "Be brief")]),
+            event("gen_ai.user.message", &[("content", "Hi")]),
+            event(
+                "gen_ai.choice",
+                &[("index", "0"), ("message", "{\"content\": \"Hello\"}")],
+            ),
+        ];
+        let (input, output) = input_output(SpanType::DEFAULT, json!({}), &events).await;
+
+        assert_eq!(
+            input,
+            Some(json!([
+                {"role": "system", "content": "Be brief"},
+                {"role": "user", "content": "Hi"},
+            ]))
+        );
+        assert_eq!(output, Some(json!("Hello")));
+    }
+
+    #[tokio::test]
+    async fn test_laminar() {
+        let (input, output) = input_output(
+            SpanType::DEFAULT,
+            json!({
+                "lmnr.span.input": "{\"query\": \"rust\"}",
+                "lmnr.span.output": "not json",
+            }),
+            &[],
+        )
+        .await;
+
+        assert_eq!(input, Some(json!({"query": "rust"})));
+        assert_eq!(output, Some(json!("not json")));
+    }
+
+    #[tokio::test]
+    async fn test_first_matching_convention_is_used() {
+        // OpenLLMetry comes before Laminar
+        let (input, _) = input_output(
+            SpanType::LLM,
+            json!({
+                "gen_ai.prompt.0.content": "Hi",
+                "lmnr.span.input": "\"laminar\"",
+            }),
+            &[],
+        )
+        .await;
+        assert_eq!(input, Some(json!([{"role": "user", "content": "Hi"}])));
+
+        // Laminar comes before OpenInference and GenAI events
+        let (input, _) = input_output(
+            SpanType::DEFAULT,
+            json!({
+                "input.value": "openinference",
+                "lmnr.span.input": "\"laminar\"",
+            }),
+            &[event("gen_ai.user.message", &[("content", "Hi")])],
+        )
+        .await;
+        assert_eq!(input, Some(json!("laminar")));
+
+        // OpenLLMetry only applies to LLM spans
+        let (input, _) = input_output(
+            SpanType::DEFAULT,
+            json!({
+                "gen_ai.prompt.0.content": "Hi",
+                "input.value": "openinference",
+            }),
+            &[],
+        )
+        .await;
+        assert_eq!(input, Some(json!("openinference")));
+    }
+}
@@ -0,0 +1,191 @@
+//! Arize's OpenInference, which records messages as flattened attributes, see:
+//! https://github.com/Arize-ai/openinference/blob/main/spec/semantic_conventions.md
+
+use std::sync::{Arc, LazyLock};
+
+use async_trait::async_trait;
+use regex::Regex;
+use serde_json::{json, Map, Value};
+use uuid::Uuid;
+
+use crate::{
+    language_model::{
+        ChatMessage, ChatMessageContent, ChatMessageContentPart, ChatMessageImageUrl,
+        ChatMessageText,
+    },
+    storage::Storage,
+    traces::span_attributes::{
+        GEN_AI_INPUT_TOKENS, GEN_AI_OUTPUT_TOKENS, GEN_AI_REQUEST_MODEL, GEN_AI_SYSTEM,
+        GEN_AI_TOTAL_TOKENS, SPAN_TYPE,
+    },
+};
+
+use super::{
+    completion_output, insert_if_absent, parse_json_string, AttributeConvention, SpanPayload,
+    ToolCall,
+};
+
+const SPAN_KIND: &str = "openinference.span.kind";
+const LLM_SPAN_KIND: &str = "LLM";
+const MODEL_NAME: &str = "llm.model_name";
+const PROVIDER: &str = "llm.provider";
+const SYSTEM: &str = "llm.system";
+const PROMPT_TOKENS: &str = "llm.token_count.prompt";
+const COMPLETION_TOKENS: &str = "llm.token_count.completion";
+const TOTAL_TOKENS: &str = "llm.token_count.total";
+const INPUT_VALUE: &str = "input.value";
+const OUTPUT_VALUE: &str = "output.value";
+const INPUT_MESSAGES: &str = "llm.input_messages";
+const OUTPUT_MESSAGES: &str = "llm.output_messages";
+
+static MESSAGE_ATTRIBUTE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^llm\.(input|output)_messages\.\d+\.message\.").unwrap());
+
+pub struct OpenInference;
+
+#[async_trait]
+impl AttributeConvention for OpenInference {
+    fn matches(&self, span: &SpanPayload<'_>) -> bool {
+        span.attributes.contains_key(SPAN_KIND)
+            || span.attributes.contains_key(INPUT_VALUE)
+            || span.attributes.contains_key(OUTPUT_VALUE)
+            || has_message(span.attributes, INPUT_MESSAGES, 0)
+    }
+
+    fn normalize_attributes(&self, attributes: &mut Map<String, Value>) {
+        let mapped_attributes = [
+            (GEN_AI_INPUT_TOKENS, PROMPT_TOKENS),
+            (GEN_AI_OUTPUT_TOKENS, COMPLETION_TOKENS),
+            (GEN_AI_TOTAL_TOKENS, TOTAL_TOKENS),
+            (GEN_AI_REQUEST_MODEL, MODEL_NAME),
+        ];
+        for (gen_ai_attribute, attribute) in mapped_attributes {
+            let value = attributes.get(attribute).cloned();
+            insert_if_absent(attributes, gen_ai_attribute, value);
+        }
+
+        if attributes.get(SPAN_KIND).and_then(|kind| kind.as_str()) == Some(LLM_SPAN_KIND) {
+            let provider = attributes.get(PROVIDER).or(attributes.get(SYSTEM)).cloned();
+            insert_if_absent(attributes, GEN_AI_SYSTEM, provider);
+            insert_if_absent(attributes, SPAN_TYPE, Some(json!(LLM_SPAN_KIND)));
+        }
+    }
+
+    fn is_payload_attribute(&self, attribute: &str) -> bool {
+        if attribute == INPUT_VALUE || attribute == OUTPUT_VALUE {
+            return true;
+        }
+        MESSAGE_ATTRIBUTE.is_match(attribute)
+    }
+
+    async fn input(
+        &self,
+        span: &SpanPayload<'_>,
+        _project_id: &Uuid,
+        _storage: Arc<dyn Storage>,
+    ) -> Option<Value> {
+        let attributes = span.attributes;
+        if !has_message(attributes, INPUT_MESSAGES, 0) {
+            return attributes.get(INPUT_VALUE).map(parse_json_string);
+        }
+
+        let mut input_messages: Vec<ChatMessage> = vec![];
+        let mut i = 0;
+        while has_message(attributes, INPUT_MESSAGES, i) {
+            let prefix = format!("{INPUT_MESSAGES}.{i}.message");
+            let role = attributes
+                .get(format!("{prefix}.role").as_str())
+                .and_then(|role| role.as_str())
+                .unwrap_or("user")
+                .to_string();
+            input_messages.push(ChatMessage {
+                role,
+                content: message_content(attributes, &prefix),
+            });
+            i += 1;
+        }
+
+        Some(json!(input_messages))
+    }
+
+    fn output(&self, span: &SpanPayload<'_>) -> Option<Value> {
+        let attributes = span.attributes;
+        if !has_message(attributes, OUTPUT_MESSAGES, 0) {
+            return attributes.get(OUTPUT_VALUE).map(parse_json_string);
+        }
+
+        let prefix = format!("{OUTPUT_MESSAGES}.0.message");
+        let text = attributes
+            .get(format!("{prefix}.content").as_str())
+            .and_then(|content| content.as_str())
+            .map(String::from);
+
+        let mut tool_calls = Vec::new();
+        let mut i = 0;
+        while let Some(Value::String(tool_call_name)) =
+            attributes.get(format!("{prefix}.tool_calls.{i}.tool_call.function.name").as_str())
+        {
+            let tool_call_id = attributes
+                .get(format!("{prefix}.tool_calls.{i}.tool_call.id").as_str())
+                .and_then(|id| id.as_str())
+                .map(String::from);
+            let tool_call_arguments = attributes
+                .get(format!("{prefix}.tool_calls.{i}.tool_call.function.arguments").as_str());
+            tool_calls.push(ToolCall::new(
+                tool_call_name.clone(),
+                tool_call_id,
+                tool_call_arguments,
+            ));
+            i += 1;
+        }
+
+        completion_output(text, tool_calls)
+    }
+}
+
+fn has_message(attributes: &Map<String, Value>, messages_prefix: &str, index: usize) -> bool {
+    let prefix = format!("{messages_prefix}.{index}.message.");
+    attributes.keys().any(|key| key.starts_with(&prefix))
+}
+
+/// Reads either plain `content`, or the list of `contents` parts of a message
+fn message_content(attributes: &Map<String, Value>, prefix: &str) -> ChatMessageContent {
+    if let Some(Value::String(content)) = attributes.get(format!("{prefix}.content").as_str()) {
+        return ChatMessageContent::Text(content.clone());
+    }
+
+    let mut parts = Vec::new();
+    let mut j = 0;
+    while let Some(Value::String(part_type)) =
+        attributes.get(format!("{prefix}.contents.{j}.message_content.type").as_str())
+    {
+        let part_prefix = format!("{prefix}.contents.{j}.message_content");
+        let part = match part_type.as_str() {
+            "image" => attributes
+                .get(format!("{part_prefix}.image.image.url").as_str())
+                .and_then(|url| url.as_str())
+                .map(|url| {
+                    ChatMessageContentPart::ImageUrl(ChatMessageImageUrl {
+                        url: url.to_string(),
+                        detail: None,
+                    })
+                }),
+            _ => attributes
+                .get(format!("{part_prefix}.text").as_str())
+                .and_then(|text| text.as_str())
+                .map(|text| {
+                    ChatMessageContentPart::Text(ChatMessageText {
+                        text: text.to_string(),
+                    })
+                }),
+        };
+        parts.extend(part);
+        j += 1;
+    }
+
+    if parts.is_empty() {
+        ChatMessageContent::Text(String::new())
+    } else {
+        ChatMessageContent::ContentPartList(parts)
+    }
+}
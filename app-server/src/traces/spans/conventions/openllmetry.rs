@@ -0,0 +1,123 @@
+//! Traceloop's OpenLLMetry, which records messages as indexed attributes, see:
+//! https://github.com/traceloop/openllmetry/blob/main/packages/opentelemetry-semantic-conventions-ai/opentelemetry/semconv_ai/__init__.py
+
+use std::sync::{Arc, LazyLock};
+
+use async_trait::async_trait;
+use regex::Regex;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::{
+    db::spans::SpanType,
+    language_model::{
+        ChatMessage, ChatMessageContent, ChatMessageContentPart,
+        InstrumentationChatMessageContentPart,
+    },
+    storage::Storage,
+};
+
+use super::{completion_output, AttributeConvention, SpanPayload, ToolCall};
+
+static PAYLOAD_ATTRIBUTE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"gen_ai\.(prompt|completion)\.\d+\.(content|role)").unwrap());
+
+pub struct OpenLLMetry;
+
+#[async_trait]
+impl AttributeConvention for OpenLLMetry {
+    fn matches(&self, span: &SpanPayload<'_>) -> bool {
+        *span.span_type == SpanType::LLM && span.attributes.contains_key("gen_ai.prompt.0.content")
+    }
+
+    fn is_payload_attribute(&self, attribute: &str) -> bool {
+        PAYLOAD_ATTRIBUTE.is_match(attribute)
+    }
+
+    async fn input(
+        &self,
+        span: &SpanPayload<'_>,
+        project_id: &Uuid,
+        storage: Arc<dyn Storage>,
+    ) -> Option<Value> {
+        let attributes = span.attributes;
+        let mut input_messages: Vec<ChatMessage> = vec![];
+
+        let mut i = 0;
+        while attributes
+            .get(format!("gen_ai.prompt.{}.content", i).as_str())
+            .is_some()
+        {
+            let content = if let Some(Value::String(s)) =
+                attributes.get(format!("gen_ai.prompt.{}.content", i).as_str())
+            {
+                s.clone()
+            } else {
+                "".to_string()
+            };
+
+            let role = if let Some(Value::String(s)) =
+                attributes.get(format!("gen_ai.prompt.{i}.role").as_str())
+            {
+                s.clone()
+            } else {
+                "user".to_string()
+            };
+
+            input_messages.push(ChatMessage {
+                role,
+                content: match serde_json::from_str::<Vec<InstrumentationChatMessageContentPart>>(
+                    &content,
+                ) {
+                    Ok(otel_parts) => {
+                        let mut parts = Vec::new();
+                        for part in otel_parts {
+                            parts.push(
+                                ChatMessageContentPart::from_instrumentation_content_part(
+                                    part,
+                                    project_id,
+                                    storage.clone(),
+                                )
+                                .await,
+                            );
+                        }
+                        ChatMessageContent::ContentPartList(parts)
+                    }
+                    Err(_) => ChatMessageContent::Text(content.clone()),
+                },
+            });
+            i += 1;
+        }
+
+        Some(json!(input_messages))
+    }
+
+    fn output(&self, span: &SpanPayload<'_>) -> Option<Value> {
+        let attributes = span.attributes;
+        let text = attributes
+            .get("gen_ai.completion.0.content")
+            .and_then(|content| content.as_str())
+            .map(String::from);
+
+        let mut tool_calls = Vec::new();
+        let mut i = 0;
+        while let Some(Value::String(tool_call_name)) =
+            attributes.get(format!("gen_ai.completion.0.tool_calls.{i}.name").as_str())
+        {
+            let tool_call_id = attributes
+                .get(format!("gen_ai.completion.0.tool_calls.{i}.id").as_str())
+                .and_then(|id| id.as_str())
+                .map(String::from);
+            let tool_call_arguments =
+                attributes.get(format!("gen_ai.completion.0.tool_calls.{i}.arguments").as_str());
+            tool_calls.push(ToolCall::new(
+                tool_call_name.clone(),
+                tool_call_id,
+                tool_call_arguments,
+            ));
+            i += 1;
+        }
+
+        completion_output(text, tool_calls)
+    }
+}
@@ -0,0 +1,43 @@
+//! Vercel's AI SDK auto-instrumentation, see:
+//! https://sdk.vercel.ai/docs/ai-sdk-core/telemetry
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::{db::spans::SpanType, language_model::ChatMessage, storage::Storage};
+
+use super::{AttributeConvention, SpanPayload};
+
+const PROMPT_MESSAGES: &str = "ai.prompt.messages";
+const RESPONSE_TEXT: &str = "ai.response.text";
+
+pub struct VercelAi;
+
+#[async_trait]
+impl AttributeConvention for VercelAi {
+    fn matches(&self, span: &SpanPayload<'_>) -> bool {
+        *span.span_type == SpanType::LLM && span.attributes.contains_key(PROMPT_MESSAGES)
+    }
+
+    async fn input(
+        &self,
+        span: &SpanPayload<'_>,
+        _project_id: &Uuid,
+        _storage: Arc<dyn Storage>,
+    ) -> Option<Value> {
+        let messages = span.attributes.get(PROMPT_MESSAGES)?.as_str()?;
+        serde_json::from_str::<Vec<ChatMessage>>(messages)
+            .ok()
+            .map(|input_messages| json!(input_messages))
+    }
+
+    fn output(&self, span: &SpanPayload<'_>) -> Option<Value> {
+        match span.attributes.get(RESPONSE_TEXT) {
+            Some(Value::String(s)) => Some(Value::String(s.clone())),
+            _ => None,
+        }
+    }
+}
@@ -1,35 +1,29 @@
 use std::{collections::HashMap, sync::Arc};
 
+use anyhow::Result;
 use chrono::{TimeZone, Utc};
-use regex::Regex;
-use serde::Serialize;
 use serde_json::{json, Value};
 use uuid::Uuid;
 
 use crate::{
     db::{
-        spans::{Span, SpanType},
+        spans::{Span, SpanStatus, SpanType},
         trace::{CurrentTraceAndSpan, TraceType, DEFAULT_VERSION},
         utils::{convert_any_value_to_json_value, span_id_to_uuid},
     },
-    language_model::{
-        ChatMessage, ChatMessageContent, ChatMessageContentPart,
-        InstrumentationChatMessageContentPart,
-    },
-    opentelemetry::opentelemetry_proto_trace_v1::Span as OtelSpan,
+    opentelemetry::opentelemetry_proto_trace_v1::{status::StatusCode, Span as OtelSpan},
     pipeline::{nodes::Message, trace::MetaLog},
     storage::Storage,
 };
 
 use super::span_attributes::{
-    ASSOCIATION_PROPERTIES_PREFIX, GEN_AI_COMPLETION_TOKENS, GEN_AI_INPUT_COST,
-    GEN_AI_INPUT_TOKENS, GEN_AI_OUTPUT_COST, GEN_AI_OUTPUT_TOKENS, GEN_AI_PROMPT_TOKENS,
-    GEN_AI_REQUEST_MODEL, GEN_AI_RESPONSE_MODEL, GEN_AI_SYSTEM, GEN_AI_TOTAL_COST,
-    GEN_AI_TOTAL_TOKENS, LLM_NODE_RENDERED_PROMPT, SPAN_PATH, SPAN_TYPE,
+    ASSOCIATION_PROPERTIES_PREFIX, EXCEPTION_EVENT_NAME, GEN_AI_COMPLETION_TOKENS,
+    GEN_AI_INPUT_COST, GEN_AI_INPUT_TOKENS, GEN_AI_OUTPUT_COST, GEN_AI_OUTPUT_TOKENS,
+    GEN_AI_PROMPT_TOKENS, GEN_AI_REQUEST_MODEL, GEN_AI_RESPONSE_MODEL, GEN_AI_SYSTEM,
+    GEN_AI_TOTAL_COST, GEN_AI_TOTAL_TOKENS, LLM_NODE_RENDERED_PROMPT, SPAN_PATH, SPAN_TYPE,
 };
 
-const INPUT_ATTRIBUTE_NAME: &str = "lmnr.span.input";
-const OUTPUT_ATTRIBUTE_NAME: &str = "lmnr.span.output";
+mod conventions;
 
 pub struct SpanAttributes {
     pub attributes: HashMap<String, Value>,
@@ -94,6 +88,13 @@ impl SpanAttributes {
         }
     }
 
+    pub fn total_cost(&self) -> f64 {
+        self.attributes
+            .get(GEN_AI_TOTAL_COST)
+            .and_then(|cost| cost.as_f64())
+            .unwrap_or(0.0)
+    }
+
     pub fn request_model(&self) -> Option<String> {
         match self.attributes.get(GEN_AI_REQUEST_MODEL) {
             Some(Value::String(s)) => Some(s.clone()),
@@ -213,8 +214,27 @@ impl Span {
         otel_span: OtelSpan,
         project_id: &Uuid,
         storage: Arc<dyn Storage>,
-    ) -> Self {
-        let trace_id = Uuid::from_slice(&otel_span.trace_id).unwrap();
+    ) -> Result<Self> {
+        if otel_span.trace_id.len() != 16 {
+            return Err(anyhow::anyhow!(
+                "Invalid trace_id: expected 16 bytes, got {}",
+                otel_span.trace_id.len()
+            ));
+        }
+        if otel_span.span_id.len() != 8 {
+            return Err(anyhow::anyhow!(
+                "Invalid span_id: expected 8 bytes, got {}",
+                otel_span.span_id.len()
+            ));
+        }
+        if !otel_span.parent_span_id.is_empty() && otel_span.parent_span_id.len() != 8 {
+            return Err(anyhow::anyhow!(
+                "Invalid parent_span_id: expected 8 bytes, got {}",
+                otel_span.parent_span_id.len()
+            ));
+        }
+
+        let trace_id = Uuid::from_slice(&otel_span.trace_id)?;
 
         let span_id = span_id_to_uuid(&otel_span.span_id);
 
@@ -224,11 +244,28 @@ impl Span {
             Some(span_id_to_uuid(&otel_span.parent_span_id))
         };
 
-        let attributes = otel_span
+        let mut attributes = otel_span
             .attributes
             .into_iter()
             .map(|k| (k.key, convert_any_value_to_json_value(k.value)))
             .collect::<serde_json::Map<String, serde_json::Value>>();
+        conventions::normalize_attributes(&mut attributes);
+
+        let (status, status_message) = match otel_span.status {
+            Some(status) => (
+                match StatusCode::try_from(status.code) {
+                    Ok(StatusCode::Ok) => SpanStatus::OK,
+                    Ok(StatusCode::Error) => SpanStatus::ERROR,
+                    _ => SpanStatus::UNSET,
+                },
+                (!status.message.is_empty()).then_some(status.message),
+            ),
+            None => (SpanStatus::UNSET, None),
+        };
+        let has_exception = otel_span
+            .events
+            .iter()
+            .any(|event| event.name == EXCEPTION_EVENT_NAME);
 
         let mut span = Span {
             version: String::from(DEFAULT_VERSION),
@@ -240,62 +277,33 @@ impl Span {
                 attributes
                     .clone()
                     .into_iter()
-                    .filter_map(|(k, v)| {
-                        if should_keep_attribute(k.as_str()) {
-                            Some((k, v))
-                        } else {
-                            None
-                        }
-                    })
+                    .filter(|(k, _)| !conventions::is_payload_attribute(k))
                     .collect(),
             ),
             start_time: Utc.timestamp_nanos(otel_span.start_time_unix_nano as i64),
             end_time: Utc.timestamp_nanos(otel_span.end_time_unix_nano as i64),
+            has_error: status == SpanStatus::ERROR || has_exception,
+            status,
+            status_message,
             ..Default::default()
         };
 
         span.span_type = span.get_attributes().span_type();
 
-        // to handle Traceloop's prompt/completion messages
-        if span.span_type == SpanType::LLM && attributes.get("gen_ai.prompt.0.content").is_some() {
-            let input_messages =
-                input_chat_messages_from_prompt_content(&attributes, project_id, storage).await;
-
-            span.input = Some(json!(input_messages));
-            span.output = output_from_completion_content(&attributes);
-        } else if span.span_type == SpanType::LLM && attributes.get("ai.prompt.messages").is_some()
-        {
-            // handling the Vercel's AI SDK auto-instrumentation
-            if let Ok(input_messages) = serde_json::from_str::<Vec<ChatMessage>>(
-                attributes
-                    .get("ai.prompt.messages")
-                    .unwrap()
-                    .as_str()
-                    .unwrap(),
-            ) {
-                span.input = Some(json!(input_messages));
-            }
-
-            if let Some(serde_json::Value::String(s)) = attributes.get("ai.response.text") {
-                span.output = Some(serde_json::Value::String(s.clone()));
-            }
-        } else {
-            if let Some(serde_json::Value::String(s)) = attributes.get(INPUT_ATTRIBUTE_NAME) {
-                span.input = Some(
-                    serde_json::from_str::<Value>(s)
-                        .unwrap_or(serde_json::Value::String(s.clone())),
-                );
-            }
-
-            if let Some(serde_json::Value::String(s)) = attributes.get(OUTPUT_ATTRIBUTE_NAME) {
-                span.output = Some(
-                    serde_json::from_str::<Value>(s)
-                        .unwrap_or(serde_json::Value::String(s.clone())),
-                );
-            }
-        }
-
-        span
+        let (input, output) = conventions::span_input_output(
+            &conventions::SpanPayload {
+                span_type: &span.span_type,
+                attributes: &attributes,
+                events: &otel_span.events,
+            },
+            project_id,
+            storage,
+        )
+        .await;
+        span.input = input;
+        span.output = output;
+
+        Ok(span)
     }
 
     pub fn create_parent_span_in_run_trace(
@@ -354,6 +362,12 @@ impl Span {
             span_type: SpanType::PIPELINE,
             events: None,
             labels: None,
+            has_error: false,
+            status: SpanStatus::UNSET,
+            status_message: None,
+            resource_attributes: json!({}),
+            scope_name: None,
+            scope_version: None,
         }
     }
 
@@ -413,6 +427,12 @@ impl Span {
                     },
                     events: None,
                     labels: None,
+                    has_error: false,
+                    status: SpanStatus::UNSET,
+                    status_message: None,
+                    resource_attributes: json!({}),
+                    scope_name: None,
+                    scope_version: None,
                 };
                 Some(span)
             })
@@ -449,17 +469,7 @@ fn span_attributes_from_meta_log(meta_log: Option<MetaLog>, span_path: String) -
     serde_json::to_value(attributes).unwrap()
 }
 
-fn should_keep_attribute(attribute: &str) -> bool {
-    // do not duplicate function input/output as they are stored in DEFAULT span's input/output
-    if attribute == INPUT_ATTRIBUTE_NAME || attribute == OUTPUT_ATTRIBUTE_NAME {
-        return false;
-    }
-
-    // remove gen_ai.prompt/completion attributes as they are stored in LLM span's input/output
-    let pattern = Regex::new(r"gen_ai\.(prompt|completion)\.\d+\.(content|role)").unwrap();
-    return !pattern.is_match(attribute);
-}
-
+#[derive(Clone)]
 pub struct SpanUsage {
     pub input_tokens: i64,
     pub output_tokens: i64,
@@ -471,133 +481,3 @@ pub struct SpanUsage {
     pub response_model: Option<String>,
     pub provider_name: Option<String>,
 }
-
-async fn input_chat_messages_from_prompt_content(
-    attributes: &serde_json::Map<String, serde_json::Value>,
-    project_id: &Uuid,
-    storage: Arc<dyn Storage>,
-) -> Vec<ChatMessage> {
-    let mut input_messages: Vec<ChatMessage> = vec![];
-
-    let mut i = 0;
-    while attributes
-        .get(format!("gen_ai.prompt.{}.content", i).as_str())
-        .is_some()
-    {
-        let content = if let Some(serde_json::Value::String(s)) =
-            attributes.get(format!("gen_ai.prompt.{}.content", i).as_str())
-        {
-            s.clone()
-        } else {
-            "".to_string()
-        };
-
-        let role = if let Some(serde_json::Value::String(s)) =
-            attributes.get(format!("gen_ai.prompt.{i}.role").as_str())
-        {
-            s.clone()
-        } else {
-            "user".to_string()
-        };
-
-        input_messages.push(ChatMessage {
-            role,
-            content: match serde_json::from_str::<Vec<InstrumentationChatMessageContentPart>>(
-                &content,
-            ) {
-                Ok(otel_parts) => {
-                    let mut parts = Vec::new();
-                    for part in otel_parts {
-                        parts.push(
-                            ChatMessageContentPart::from_instrumentation_content_part(
-                                part,
-                                project_id,
-                                storage.clone(),
-                            )
-                            .await,
-                        );
-                    }
-                    ChatMessageContent::ContentPartList(parts)
-                }
-                Err(_) => ChatMessageContent::Text(content.clone()),
-            },
-        });
-        i += 1;
-    }
-
-    input_messages
-}
-
-#[derive(Serialize)]
-struct ToolCall {
-    name: String,
-    id: Option<String>,
-    arguments: Option<serde_json::Value>,
-    #[serde(rename = "type")]
-    content_block_type: String,
-}
-
-#[derive(Serialize)]
-struct TextBlock {
-    content: String,
-    #[serde(rename = "type")]
-    content_block_type: String,
-}
-
-fn output_from_completion_content(
-    attributes: &serde_json::Map<String, serde_json::Value>,
-) -> Option<serde_json::Value> {
-    let text_msg = attributes.get("gen_ai.completion.0.content");
-
-    let mut tool_calls = Vec::new();
-    let mut i = 0;
-    while let Some(serde_json::Value::String(tool_call_name)) =
-        attributes.get(format!("gen_ai.completion.0.tool_calls.{i}.name").as_str())
-    {
-        let tool_call_id = attributes
-            .get(format!("gen_ai.completion.0.tool_calls.{i}.id").as_str())
-            .and_then(|id| id.as_str())
-            .map(String::from);
-        let tool_call_arguments_raw =
-            attributes.get(format!("gen_ai.completion.0.tool_calls.{i}.arguments").as_str());
-        let tool_call_arguments = match tool_call_arguments_raw {
-            Some(serde_json::Value::String(s)) => {
-                let parsed = serde_json::from_str::<HashMap<String, Value>>(s);
-                if let Ok(parsed) = parsed {
-                    serde_json::to_value(parsed).ok()
-                } else {
-                    Some(serde_json::Value::String(s.clone()))
-                }
-            }
-            _ => tool_call_arguments_raw.cloned(),
-        };
-        let tool_call = ToolCall {
-            name: tool_call_name.clone(),
-            id: tool_call_id,
-            arguments: tool_call_arguments,
-            content_block_type: "tool_call".to_string(),
-        };
-        tool_calls.push(serde_json::to_value(tool_call).unwrap());
-        i += 1;
-    }
-
-    if tool_calls.is_empty() {
-        if let Some(Value::String(s)) = text_msg {
-            Some(serde_json::Value::String(s.clone()))
-        } else {
-            None
-        }
-    } else {
-        let mut out_vec = if let Some(Value::String(s)) = text_msg {
-            let text_block = TextBlock {
-                content: s.clone(),
-                content_block_type: "text".to_string(),
-            };
-            vec![serde_json::to_value(text_block).unwrap()]
-        } else {
-            vec![]
-        };
-        out_vec.extend(tool_calls);
-        Some(Value::Array(out_vec))
-    }
-}
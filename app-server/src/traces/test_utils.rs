@@ -0,0 +1,18 @@
+//! Spans for tests, with times in milliseconds since the start of the trace
+
+use chrono::{DateTime, TimeDelta, Utc};
+use uuid::Uuid;
+
+use crate::db::spans::Span;
+
+pub fn span(name: &str, parent: Option<&Span>, start_ms: i64, end_ms: i64) -> Span {
+    let trace_start = DateTime::<Utc>::UNIX_EPOCH;
+    Span {
+        span_id: Uuid::new_v4(),
+        parent_span_id: parent.map(|parent| parent.span_id),
+        name: name.to_string(),
+        start_time: trace_start + TimeDelta::milliseconds(start_ms),
+        end_time: trace_start + TimeDelta::milliseconds(end_ms),
+        ..Default::default()
+    }
+}
@@ -0,0 +1,206 @@
+//! Flattens the LLM calls of a session into a single chat transcript.
+//!
+//! Each call usually sends the whole conversation so far, so the messages which were already
+//! sent or answered by the previous call are skipped, and only the new ones are added.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::db::spans::SessionLlmSpan;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TranscriptMessage {
+    pub trace_id: Uuid,
+    /// LLM span which the message was sent or received in
+    pub span_id: Uuid,
+    pub timestamp: DateTime<Utc>,
+    pub role: String,
+    pub content: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Value>,
+}
+
+impl TranscriptMessage {
+    fn new(span: &SessionLlmSpan, timestamp: DateTime<Utc>, turn: &ChatTurn) -> Self {
+        Self {
+            trace_id: span.trace_id,
+            span_id: span.span_id,
+            timestamp,
+            role: turn.role.clone(),
+            content: turn.content.clone(),
+            tool_calls: turn.tool_calls.clone(),
+        }
+    }
+}
+
+#[derive(PartialEq)]
+struct ChatTurn {
+    role: String,
+    content: Value,
+    tool_calls: Option<Value>,
+}
+
+impl ChatTurn {
+    /// Chat messages have a role, anything else is treated as the content of a message
+    fn from_value(value: Value, default_role: &str) -> Self {
+        match value {
+            Value::Object(mut message) if message.contains_key("role") => Self {
+                role: message
+                    .get("role")
+                    .and_then(|role| role.as_str())
+                    .unwrap_or(default_role)
+                    .to_string(),
+                content: message.remove("content").unwrap_or(Value::Null),
+                tool_calls: message.remove("tool_calls"),
+            },
+            content => Self {
+                role: default_role.to_string(),
+                content,
+                tool_calls: None,
+            },
+        }
+    }
+}
+
+fn input_turns(input: Option<Value>) -> Vec<ChatTurn> {
+    match input {
+        Some(Value::Array(messages)) if messages.iter().all(|m| m.get("role").is_some()) => {
+            messages
+                .into_iter()
+                .map(|message| ChatTurn::from_value(message, "user"))
+                .collect()
+        }
+        Some(Value::Null) | None => Vec::new(),
+        Some(input) => vec![ChatTurn::from_value(input, "user")],
+    }
+}
+
+pub fn build_transcript(mut llm_spans: Vec<SessionLlmSpan>) -> Vec<TranscriptMessage> {
+    // Traces of a session may overlap or be recorded out of order, so calls are replayed
+    // in the order they were made rather than by trace
+    llm_spans.sort_by_key(|span| span.start_time);
+    let mut transcript = Vec::new();
+    // Messages of the previous call, followed by its response
+    let mut conversation: Vec<ChatTurn> = Vec::new();
+
+    for mut span in llm_spans {
+        let mut turns = input_turns(span.input.take());
+        let known_turns = conversation
+            .iter()
+            .zip(&turns)
+            .take_while(|(known, turn)| known == turn)
+            .count();
+        for turn in &turns[known_turns..] {
+            transcript.push(TranscriptMessage::new(&span, span.start_time, turn));
+        }
+
+        if let Some(output) = span.output.take().filter(|output| !output.is_null()) {
+            let turn = ChatTurn::from_value(output, "assistant");
+            transcript.push(TranscriptMessage::new(&span, span.end_time, &turn));
+            turns.push(turn);
+        }
+        conversation = turns;
+    }
+
+    transcript
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::traces::test_utils::span;
+
+    use super::*;
+
+    fn llm_span(start_ms: i64, input: Value, output: Value) -> SessionLlmSpan {
+        let span = span("openai.chat", None, start_ms, start_ms + 100);
+        SessionLlmSpan {
+            span_id: span.span_id,
+            trace_id: Uuid::new_v4(),
+            start_time: span.start_time,
+            end_time: span.end_time,
+            input: Some(input),
+            output: Some(output),
+        }
+    }
+
+    fn messages(transcript: &[TranscriptMessage]) -> Vec<(&str, &str)> {
+        transcript
+            .iter()
+            .map(|message| (message.role.as_str(), message.content.as_str().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_repeated_history_is_skipped() {
+        let transcript = build_transcript(vec![
+            llm_span(
+                0,
+                json!([
+                    {"role": "system", "content": "Be brief"},
+                    {"role": "user", "content": "Hi"},
+                ]),
+                json!("Hello"),
+            ),
+            llm_span(
+                1000,
+                json!([
+                    {"role": "system", "content": "Be brief"},
+                    {"role": "user", "content": "Hi"},
+                    {"role": "assistant", "content": "Hello"},
+                    {"role": "user", "content": "Bye"},
+                ]),
+                json!("Goodbye"),
+            ),
+        ]);
+
+        assert_eq!(
+            messages(&transcript),
+            vec![
+                ("system", "Be brief"),
+                ("user", "Hi"),
+                ("assistant", "Hello"),
+                ("user", "Bye"),
+                ("assistant", "Goodbye"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_out_of_order_traces() {
+        // The second trace was received, and so listed, before the first one
+        let transcript = build_transcript(vec![
+            llm_span(
+                1000,
+                json!([
+                    {"role": "user", "content": "Hi"},
+                    {"role": "assistant", "content": "Hello"},
+                    {"role": "user", "content": "Bye"},
+                ]),
+                json!("Goodbye"),
+            ),
+            llm_span(
+                0,
+                json!([{"role": "user", "content": "Hi"}]),
+                json!("Hello"),
+            ),
+        ]);
+
+        assert_eq!(
+            messages(&transcript),
+            vec![
+                ("user", "Hi"),
+                ("assistant", "Hello"),
+                ("user", "Bye"),
+                ("assistant", "Goodbye"),
+            ]
+        );
+        assert!(transcript
+            .windows(2)
+            .all(|pair| pair[0].timestamp <= pair[1].timestamp));
+    }
+}
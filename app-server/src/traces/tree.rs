@@ -0,0 +1,217 @@
+//! Nests the spans of a trace into a tree, and finds where the time of the trace went.
+//!
+//! The self time of a span is the part of its duration not covered by any of its children,
+//! and the child time is the rest. The critical path is the chain of spans which determines
+//! the end-to-end latency: going back from the end of a span, it follows the child which
+//! finished last, then the child which finished last before that one started, and so on.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::db::spans::{Span, SpanType};
+
+use super::utils::seconds_between;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpanTreeNode {
+    pub span_id: Uuid,
+    pub name: String,
+    pub span_type: SpanType,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    /// In seconds
+    pub self_time: f64,
+    /// In seconds, time covered by at least one child
+    pub child_time: f64,
+    pub on_critical_path: bool,
+    pub children: Vec<SpanTreeNode>,
+}
+
+/// Spans must be ordered by start time. Spans whose parent is not in the trace are roots.
+pub fn build_span_tree(spans: &[Span]) -> Vec<SpanTreeNode> {
+    let index_by_id = spans
+        .iter()
+        .enumerate()
+        .map(|(i, span)| (span.span_id, i))
+        .collect::<HashMap<_, _>>();
+    let mut roots = Vec::new();
+    let mut children = vec![Vec::new(); spans.len()];
+    for (i, span) in spans.iter().enumerate() {
+        match span
+            .parent_span_id
+            .and_then(|parent_span_id| index_by_id.get(&parent_span_id))
+        {
+            Some(&parent) if parent != i => children[parent].push(i),
+            _ => roots.push(i),
+        }
+    }
+
+    let mut on_critical_path = vec![false; spans.len()];
+    if let Some(end_time) = roots.iter().map(|&i| spans[i].end_time).max() {
+        mark_critical_path(spans, &children, &roots, end_time, &mut on_critical_path);
+    }
+
+    roots
+        .into_iter()
+        .map(|i| build_node(spans, &children, &on_critical_path, i))
+        .collect()
+}
+
+fn mark_critical_path(
+    spans: &[Span],
+    children: &[Vec<usize>],
+    candidates: &[usize],
+    end_time: DateTime<Utc>,
+    on_critical_path: &mut [bool],
+) {
+    let mut cursor = end_time;
+    // Children may end after their parent, e.g. because of clock skew between services,
+    // so their end is clipped to the cursor
+    while let Some((i, clipped_end_time)) = candidates
+        .iter()
+        .filter(|&&i| spans[i].start_time < cursor)
+        .map(|&i| (i, spans[i].end_time.min(cursor)))
+        .max_by_key(|&(_, clipped_end_time)| clipped_end_time)
+    {
+        on_critical_path[i] = true;
+        mark_critical_path(
+            spans,
+            children,
+            &children[i],
+            clipped_end_time,
+            on_critical_path,
+        );
+        cursor = spans[i].start_time;
+    }
+}
+
+fn build_node(
+    spans: &[Span],
+    children: &[Vec<usize>],
+    on_critical_path: &[bool],
+    i: usize,
+) -> SpanTreeNode {
+    let span = &spans[i];
+    let duration = seconds_between(span.start_time, span.end_time).max(0.0);
+    let child_time =
+        covered_time(span, children[i].iter().map(|&child| &spans[child])).min(duration);
+
+    SpanTreeNode {
+        span_id: span.span_id,
+        name: span.name.clone(),
+        span_type: span.span_type.clone(),
+        start_time: span.start_time,
+        end_time: span.end_time,
+        self_time: duration - child_time,
+        child_time,
+        on_critical_path: on_critical_path[i],
+        children: children[i]
+            .iter()
+            .map(|&child| build_node(spans, children, on_critical_path, child))
+            .collect(),
+    }
+}
+
+/// Time within the span covered by the union of the children, which may run in parallel
+fn covered_time<'a>(span: &Span, children: impl Iterator<Item = &'a Span>) -> f64 {
+    let mut covered_time = 0.0;
+    let mut covered_until = span.start_time;
+    // Children are ordered by start time
+    for child in children {
+        let start_time = child.start_time.max(covered_until);
+        let end_time = child.end_time.min(span.end_time);
+        if end_time > start_time {
+            covered_time += seconds_between(start_time, end_time);
+            covered_until = end_time;
+        }
+    }
+    covered_time
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::traces::test_utils::span;
+
+    use super::*;
+
+    fn critical_path(node: &SpanTreeNode) -> Vec<(&str, bool)> {
+        node.children
+            .iter()
+            .map(|child| (child.name.as_str(), child.on_critical_path))
+            .collect()
+    }
+
+    #[test]
+    fn test_self_time_and_critical_path() {
+        let agent = span("agent", None, 0, 1000);
+        let retrieve = span("retrieve", Some(&agent), 0, 400);
+        // Runs in parallel with retrieve, and finishes before it
+        let search = span("search", Some(&agent), 100, 300);
+        let llm = span("llm", Some(&agent), 500, 900);
+        let spans = vec![agent, retrieve, search, llm];
+
+        let tree = build_span_tree(&spans);
+
+        assert_eq!(tree.len(), 1);
+        let agent = &tree[0];
+        assert!((agent.child_time - 0.8).abs() < 1e-9);
+        assert!((agent.self_time - 0.2).abs() < 1e-9);
+        assert_eq!(
+            critical_path(agent),
+            vec![("retrieve", true), ("search", false), ("llm", true)]
+        );
+    }
+
+    #[test]
+    fn test_spans_with_missing_parent_are_roots() {
+        // The parent of the orphan span was never recorded, e.g. because it was sampled out
+        let missing_parent = span("missing", None, 0, 1000);
+        let agent = span("agent", None, 0, 400);
+        let tool = span("tool", Some(&agent), 100, 300);
+        let orphan = span("orphan", Some(&missing_parent), 500, 900);
+        let orphan_child = span("orphan_child", Some(&orphan), 600, 700);
+        let spans = vec![agent, tool, orphan, orphan_child];
+
+        let tree = build_span_tree(&spans);
+
+        let roots = tree
+            .iter()
+            .map(|root| {
+                (
+                    root.name.as_str(),
+                    root.on_critical_path,
+                    root.children.len(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(roots, vec![("agent", true, 1), ("orphan", true, 1)]);
+        assert_eq!(tree[1].children[0].name, "orphan_child");
+        assert!((tree[1].self_time - 0.3).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_overlapping_children_on_critical_path() {
+        let agent = span("agent", None, 0, 1000);
+        let first = span("first", Some(&agent), 0, 600);
+        // Contained in the first child, so it never determines the latency
+        let nested = span("nested", Some(&agent), 100, 300);
+        // Starts before the first child ends, and ends after the parent, e.g. due to clock skew
+        let second = span("second", Some(&agent), 400, 1100);
+        let spans = vec![agent, first, nested, second];
+
+        let tree = build_span_tree(&spans);
+
+        let agent = &tree[0];
+        assert_eq!(
+            critical_path(agent),
+            vec![("first", true), ("nested", false), ("second", true)]
+        );
+        // Overlapping time is only counted once, and is clipped to the parent
+        assert!((agent.child_time - 1.0).abs() < 1e-9);
+        assert!(agent.self_time.abs() < 1e-9);
+    }
+}
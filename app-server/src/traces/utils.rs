@@ -1,5 +1,6 @@
 use std::sync::Arc;
 
+use chrono::{DateTime, Utc};
 use serde_json::Value;
 use uuid::Uuid;
 
@@ -18,6 +19,10 @@ use super::{
     spans::{SpanAttributes, SpanUsage},
 };
 
+pub fn seconds_between(start_time: DateTime<Utc>, end_time: DateTime<Utc>) -> f64 {
+    (end_time - start_time).num_microseconds().unwrap_or(0) as f64 / 1_000_000.0
+}
+
 pub fn json_value_to_string(v: Value) -> String {
     match v {
         Value::String(s) => s,
@@ -83,12 +88,16 @@ pub async fn get_llm_usage_for_span(
     }
 }
 
+/// Records the span and adds it to its trace. Returns whether the span is recorded for the first
+/// time, as opposed to recorded again because its delivery was retried.
+///
+/// The trace totals are only updated for new spans, so that retries do not count them twice.
 pub async fn record_span_to_db(
     db: Arc<DB>,
     span_usage: &SpanUsage,
     project_id: &Uuid,
     span: &mut Span,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<bool> {
     let mut trace_attributes = TraceAttributes::new(span.trace_id);
 
     trace_attributes.update_start_time(span.start_time);
@@ -99,32 +108,32 @@ pub async fn record_span_to_db(
     trace_attributes.update_user_id(span_attributes.user_id());
     trace_attributes.update_session_id(span_attributes.session_id());
     trace_attributes.update_trace_type(span_attributes.trace_type());
+    trace_attributes.update_has_error(span.has_error);
 
+    // Applied separately, as the only trace attributes which are not idempotent
+    let mut trace_usage = TraceAttributes::new(span.trace_id);
     if span.span_type == SpanType::LLM {
-        trace_attributes.add_input_cost(span_usage.input_cost);
-        trace_attributes.add_output_cost(span_usage.output_cost);
-        trace_attributes.add_total_cost(span_usage.total_cost);
+        trace_usage.add_input_cost(span_usage.input_cost);
+        trace_usage.add_output_cost(span_usage.output_cost);
+        trace_usage.add_total_cost(span_usage.total_cost);
 
-        trace_attributes.add_input_tokens(span_usage.input_tokens);
-        trace_attributes.add_output_tokens(span_usage.output_tokens);
-        trace_attributes.add_total_tokens(span_usage.total_tokens);
+        trace_usage.add_input_tokens(span_usage.input_tokens);
+        trace_usage.add_output_tokens(span_usage.output_tokens);
+        trace_usage.add_total_tokens(span_usage.total_tokens);
         span_attributes.set_usage(&span_usage);
     }
 
     span_attributes.extend_span_path(&span.name);
     span.set_attributes(&span_attributes);
 
-    let update_attrs_res =
-        trace::update_trace_attributes(&db.pool, project_id, &trace_attributes).await;
-    if let Err(e) = update_attrs_res {
-        log::error!(
-            "Failed to update trace attributes [{}]: {:?}",
-            span.span_id,
-            e
-        );
+    // The trace is created before the span, which references it
+    let mut transaction = db.pool.begin().await?;
+    trace::update_trace_attributes(&mut *transaction, project_id, &trace_attributes).await?;
+    let is_new = db::spans::record_span(&mut *transaction, &span).await?;
+    if is_new && span.span_type == SpanType::LLM {
+        trace::update_trace_attributes(&mut *transaction, project_id, &trace_usage).await?;
     }
+    transaction.commit().await?;
 
-    db::spans::record_span(&db.pool, &span).await?;
-
-    Ok(())
+    Ok(is_new)
 }
@@ -0,0 +1,14 @@
+CREATE TABLE metrics (
+    project_id UUID,
+    name String,
+    description String,
+    unit String,
+    metric_type Enum8('GAUGE' = 0, 'SUM', 'HISTOGRAM', 'EXPONENTIAL_HISTOGRAM', 'SUMMARY'),
+    timestamp DateTime64(9, 'UTC'),
+    value Float64,
+    count UInt64,
+    attributes String
+)
+ENGINE = MergeTree()
+ORDER BY (project_id, name, timestamp)
+SETTINGS index_granularity = 8192;
@@ -0,0 +1,4 @@
+ALTER TABLE spans
+    ADD COLUMN status_code UInt8 DEFAULT 0,
+    ADD COLUMN status_message String DEFAULT '',
+    ADD COLUMN has_error Bool DEFAULT false;
@@ -0,0 +1,4 @@
+ALTER TABLE spans
+    ADD COLUMN resource_attributes String DEFAULT '{}',
+    ADD COLUMN scope_name String DEFAULT '',
+    ADD COLUMN scope_version String DEFAULT '';
@@ -0,0 +1,4 @@
+-- Needed to delete evaluation scores older than the project's retention period.
+-- Existing scores get the time of the migration.
+ALTER TABLE evaluation_scores ADD COLUMN timestamp DateTime64(9, 'UTC') DEFAULT now64(9, 'UTC');
+ALTER TABLE evaluation_scores MATERIALIZE COLUMN timestamp;
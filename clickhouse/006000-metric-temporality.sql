@@ -0,0 +1,4 @@
+-- Temporality the points were exported with, cumulative values are converted to deltas
+ALTER TABLE metrics
+    ADD COLUMN temporality Enum8('UNSPECIFIED' = 0, 'DELTA', 'CUMULATIVE') DEFAULT 'UNSPECIFIED',
+    ADD COLUMN is_monotonic Bool DEFAULT false;
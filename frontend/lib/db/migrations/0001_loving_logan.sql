@@ -0,0 +1,21 @@
+CREATE TABLE IF NOT EXISTS "logs" (
+	"id" uuid PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
+	"created_at" timestamp with time zone DEFAULT now() NOT NULL,
+	"project_id" uuid NOT NULL,
+	"trace_id" uuid,
+	"span_id" uuid,
+	"timestamp" timestamp with time zone NOT NULL,
+	"severity_number" integer DEFAULT 0 NOT NULL,
+	"severity_text" text,
+	"body" jsonb,
+	"attributes" jsonb DEFAULT '{}'::jsonb NOT NULL
+);
+--> statement-breakpoint
+DO $$ BEGIN
+ ALTER TABLE "logs" ADD CONSTRAINT "logs_project_id_fkey" FOREIGN KEY ("project_id") REFERENCES "public"."projects"("id") ON DELETE cascade ON UPDATE cascade;
+EXCEPTION
+ WHEN duplicate_object THEN null;
+END $$;
+--> statement-breakpoint
+CREATE INDEX IF NOT EXISTS "logs_span_id_idx" ON "logs" USING btree ("span_id");--> statement-breakpoint
+CREATE INDEX IF NOT EXISTS "logs_trace_id_idx" ON "logs" USING btree ("trace_id");
\ No newline at end of file
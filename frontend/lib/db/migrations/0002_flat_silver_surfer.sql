@@ -0,0 +1,18 @@
+CREATE TABLE IF NOT EXISTS "span_events" (
+	"id" uuid PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
+	"created_at" timestamp with time zone DEFAULT now() NOT NULL,
+	"project_id" uuid NOT NULL,
+	"span_id" uuid NOT NULL,
+	"timestamp" timestamp with time zone NOT NULL,
+	"name" text NOT NULL,
+	"attributes" jsonb DEFAULT '{}'::jsonb NOT NULL
+);
+--> statement-breakpoint
+ALTER TABLE "spans" ADD COLUMN "has_error" boolean DEFAULT false NOT NULL;--> statement-breakpoint
+DO $$ BEGIN
+ ALTER TABLE "span_events" ADD CONSTRAINT "span_events_project_id_fkey" FOREIGN KEY ("project_id") REFERENCES "public"."projects"("id") ON DELETE cascade ON UPDATE cascade;
+EXCEPTION
+ WHEN duplicate_object THEN null;
+END $$;
+--> statement-breakpoint
+CREATE INDEX IF NOT EXISTS "span_events_span_id_idx" ON "span_events" USING btree ("span_id");
\ No newline at end of file
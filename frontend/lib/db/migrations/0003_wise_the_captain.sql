@@ -0,0 +1,9 @@
+DO $$ BEGIN
+ CREATE TYPE "public"."span_status" AS ENUM('UNSET', 'OK', 'ERROR');
+EXCEPTION
+ WHEN duplicate_object THEN null;
+END $$;
+--> statement-breakpoint
+ALTER TABLE "spans" ADD COLUMN "status" "span_status" DEFAULT 'UNSET' NOT NULL;--> statement-breakpoint
+ALTER TABLE "spans" ADD COLUMN "status_message" text;--> statement-breakpoint
+ALTER TABLE "traces" ADD COLUMN "has_error" boolean DEFAULT false NOT NULL;
\ No newline at end of file
@@ -0,0 +1,3 @@
+ALTER TABLE "spans" ADD COLUMN "resource_attributes" jsonb DEFAULT '{}'::jsonb NOT NULL;--> statement-breakpoint
+ALTER TABLE "spans" ADD COLUMN "scope_name" text;--> statement-breakpoint
+ALTER TABLE "spans" ADD COLUMN "scope_version" text;
\ No newline at end of file
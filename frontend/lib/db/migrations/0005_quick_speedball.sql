@@ -0,0 +1,5 @@
+-- Filled by app-server when spans are recorded, from text truncated to fit in a tsvector
+ALTER TABLE "spans" ADD COLUMN "search_vector" tsvector;--> statement-breakpoint
+-- Blocks writes to spans while the index is built. On large databases, build it beforehand
+-- with CREATE INDEX CONCURRENTLY under the same name, and this statement is skipped.
+CREATE INDEX IF NOT EXISTS "spans_search_vector_idx" ON "spans" USING gin ("search_vector");
\ No newline at end of file
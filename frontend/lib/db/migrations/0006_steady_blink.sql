@@ -0,0 +1 @@
+ALTER TABLE "projects" ADD COLUMN "retention_days" integer;
\ No newline at end of file
@@ -0,0 +1,16 @@
+CREATE TYPE "public"."redaction_rule_type" AS ENUM('EMAIL', 'PHONE', 'CARD_NUMBER', 'PATTERN', 'ATTRIBUTE');--> statement-breakpoint
+CREATE TABLE IF NOT EXISTS "redaction_rules" (
+	"id" uuid PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
+	"created_at" timestamp with time zone DEFAULT now() NOT NULL,
+	"project_id" uuid NOT NULL,
+	"rule_type" "redaction_rule_type" NOT NULL,
+	"value" text
+);
+--> statement-breakpoint
+DO $$ BEGIN
+ ALTER TABLE "redaction_rules" ADD CONSTRAINT "redaction_rules_project_id_fkey" FOREIGN KEY ("project_id") REFERENCES "public"."projects"("id") ON DELETE cascade ON UPDATE cascade;
+EXCEPTION
+ WHEN duplicate_object THEN null;
+END $$;
+--> statement-breakpoint
+CREATE INDEX IF NOT EXISTS "redaction_rules_project_id_idx" ON "redaction_rules" USING btree ("project_id");
\ No newline at end of file
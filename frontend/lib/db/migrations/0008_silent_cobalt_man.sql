@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS "sampling_rules" (
+	"project_id" uuid PRIMARY KEY NOT NULL,
+	"created_at" timestamp with time zone DEFAULT now() NOT NULL,
+	"head_sample_rate" double precision DEFAULT '1' NOT NULL,
+	"keep_errors" boolean DEFAULT true NOT NULL,
+	"slow_trace_ms" bigint,
+	"expensive_trace_cost" double precision
+);
+--> statement-breakpoint
+DO $$ BEGIN
+ ALTER TABLE "sampling_rules" ADD CONSTRAINT "sampling_rules_project_id_fkey" FOREIGN KEY ("project_id") REFERENCES "public"."projects"("id") ON DELETE cascade ON UPDATE cascade;
+EXCEPTION
+ WHEN duplicate_object THEN null;
+END $$;
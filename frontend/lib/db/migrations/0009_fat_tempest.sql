@@ -0,0 +1,2 @@
+ALTER TABLE "project_api_keys" ADD COLUMN "rate_limit_per_second" double precision;--> statement-breakpoint
+ALTER TABLE "project_api_keys" ADD COLUMN "rate_limit_burst" integer;
\ No newline at end of file
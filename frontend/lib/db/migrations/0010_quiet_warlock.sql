@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS "dead_lettered_spans" (
+	"id" uuid PRIMARY KEY NOT NULL,
+	"dead_lettered_at" timestamp with time zone DEFAULT now() NOT NULL,
+	"retry_count" bigint DEFAULT '0' NOT NULL,
+	"error" text,
+	"payload" text NOT NULL
+);
+--> statement-breakpoint
+CREATE INDEX IF NOT EXISTS "dead_lettered_spans_dead_lettered_at_id_idx" ON "dead_lettered_spans" USING btree ("dead_lettered_at","id");
\ No newline at end of file
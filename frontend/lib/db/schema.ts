@@ -1,11 +1,19 @@
-import { pgTable, foreignKey, unique, uuid, timestamp, index, text, jsonb, bigint, boolean, doublePrecision, integer, pgEnum } from "drizzle-orm/pg-core";
+import { pgTable, foreignKey, unique, uuid, timestamp, index, text, jsonb, bigint, boolean, doublePrecision, integer, pgEnum, customType } from "drizzle-orm/pg-core";
 import { sql } from "drizzle-orm";
 
+const tsvector = customType<{ data: string }>({
+  dataType() {
+    return "tsvector";
+  },
+});
+
 export const eventSource = pgEnum("event_source", ['AUTO', 'MANUAL', 'CODE']);
 export const eventType = pgEnum("event_type", ['BOOLEAN', 'STRING', 'NUMBER']);
 export const labelJobStatus = pgEnum("label_job_status", ['RUNNING', 'DONE']);
 export const labelSource = pgEnum("label_source", ['MANUAL', 'AUTO']);
 export const labelType = pgEnum("label_type", ['BOOLEAN', 'CATEGORICAL']);
+export const redactionRuleType = pgEnum("redaction_rule_type", ['EMAIL', 'PHONE', 'CARD_NUMBER', 'PATTERN', 'ATTRIBUTE']);
+export const spanStatus = pgEnum("span_status", ['UNSET', 'OK', 'ERROR']);
 export const spanType = pgEnum("span_type", ['DEFAULT', 'LLM', 'PIPELINE', 'EXECUTOR', 'EVALUATOR', 'EVALUATION']);
 export const traceType = pgEnum("trace_type", ['DEFAULT', 'EVENT', 'EVALUATION']);
 export const workspaceRole = pgEnum("workspace_role", ['member', 'owner']);
@@ -54,6 +62,7 @@ export const traces = pgTable("traces", {
   outputTokenCount: bigint("output_token_count", { mode: "number" }).default(sql`'0'`).notNull(),
   inputCost: doublePrecision("input_cost").default(sql`'0'`).notNull(),
   outputCost: doublePrecision("output_cost").default(sql`'0'`).notNull(),
+  hasError: boolean("has_error").default(false).notNull(),
 },
 (table) => ({
   projectIdIdx: index("traces_project_id_idx").using("btree", table.projectId.asc().nullsLast()),
@@ -103,9 +112,17 @@ export const spans = pgTable("spans", {
   version: text().notNull(),
   inputPreview: text("input_preview"),
   outputPreview: text("output_preview"),
+  hasError: boolean("has_error").default(false).notNull(),
+  status: spanStatus().default('UNSET').notNull(),
+  statusMessage: text("status_message"),
+  resourceAttributes: jsonb("resource_attributes").default({}).notNull(),
+  scopeName: text("scope_name"),
+  scopeVersion: text("scope_version"),
+  searchVector: tsvector("search_vector"),
 },
 (table) => ({
   spanPathIdx: index("span_path_idx").using("btree", sql`(attributes -> 'lmnr.span.path'::text)`),
+  searchVectorIdx: index("spans_search_vector_idx").using("gin", table.searchVector),
   startTimeEndTimeIdx: index("spans_start_time_end_time_idx").using("btree", table.startTime.asc().nullsLast(), table.endTime.asc().nullsLast()),
   traceIdIdx: index("spans_trace_id_idx").using("btree", table.traceId.asc().nullsLast()),
   newSpansTraceIdFkey: foreignKey({
@@ -135,6 +152,46 @@ export const events = pgTable("events", {
   }).onUpdate("cascade").onDelete("cascade"),
 }));
 
+export const logs = pgTable("logs", {
+  id: uuid().defaultRandom().primaryKey().notNull(),
+  createdAt: timestamp("created_at", { withTimezone: true, mode: 'string' }).defaultNow().notNull(),
+  projectId: uuid("project_id").notNull(),
+  traceId: uuid("trace_id"),
+  spanId: uuid("span_id"),
+  timestamp: timestamp({ withTimezone: true, mode: 'string' }).notNull(),
+  severityNumber: integer("severity_number").default(0).notNull(),
+  severityText: text("severity_text"),
+  body: jsonb(),
+  attributes: jsonb().default({}).notNull(),
+},
+(table) => ({
+  spanIdIdx: index("logs_span_id_idx").using("btree", table.spanId.asc().nullsLast()),
+  traceIdIdx: index("logs_trace_id_idx").using("btree", table.traceId.asc().nullsLast()),
+  logsProjectIdFkey: foreignKey({
+    columns: [table.projectId],
+    foreignColumns: [projects.id],
+    name: "logs_project_id_fkey"
+  }).onUpdate("cascade").onDelete("cascade"),
+}));
+
+export const spanEvents = pgTable("span_events", {
+  id: uuid().defaultRandom().primaryKey().notNull(),
+  createdAt: timestamp("created_at", { withTimezone: true, mode: 'string' }).defaultNow().notNull(),
+  projectId: uuid("project_id").notNull(),
+  spanId: uuid("span_id").notNull(),
+  timestamp: timestamp({ withTimezone: true, mode: 'string' }).notNull(),
+  name: text().notNull(),
+  attributes: jsonb().default({}).notNull(),
+},
+(table) => ({
+  spanIdIdx: index("span_events_span_id_idx").using("btree", table.spanId.asc().nullsLast()),
+  spanEventsProjectIdFkey: foreignKey({
+    columns: [table.projectId],
+    foreignColumns: [projects.id],
+    name: "span_events_project_id_fkey"
+  }).onUpdate("cascade").onDelete("cascade"),
+}));
+
 export const providerApiKeys = pgTable("provider_api_keys", {
   id: uuid().defaultRandom().primaryKey().notNull(),
   createdAt: timestamp("created_at", { withTimezone: true, mode: 'string' }).defaultNow().notNull(),
@@ -151,6 +208,51 @@ export const providerApiKeys = pgTable("provider_api_keys", {
   }).onUpdate("cascade").onDelete("cascade"),
 }));
 
+export const redactionRules = pgTable("redaction_rules", {
+  id: uuid().defaultRandom().primaryKey().notNull(),
+  createdAt: timestamp("created_at", { withTimezone: true, mode: 'string' }).defaultNow().notNull(),
+  projectId: uuid("project_id").notNull(),
+  ruleType: redactionRuleType("rule_type").notNull(),
+  value: text(),
+},
+(table) => ({
+  projectIdIdx: index("redaction_rules_project_id_idx").using("btree", table.projectId.asc().nullsLast()),
+  redactionRulesProjectIdFkey: foreignKey({
+    columns: [table.projectId],
+    foreignColumns: [projects.id],
+    name: "redaction_rules_project_id_fkey"
+  }).onUpdate("cascade").onDelete("cascade"),
+}));
+
+export const samplingRules = pgTable("sampling_rules", {
+  projectId: uuid("project_id").primaryKey().notNull(),
+  createdAt: timestamp("created_at", { withTimezone: true, mode: 'string' }).defaultNow().notNull(),
+  headSampleRate: doublePrecision("head_sample_rate").default(sql`'1'`).notNull(),
+  keepErrors: boolean("keep_errors").default(true).notNull(),
+  // You can use { mode: "bigint" } if numbers are exceeding js number limitations
+  slowTraceMs: bigint("slow_trace_ms", { mode: "number" }),
+  expensiveTraceCost: doublePrecision("expensive_trace_cost"),
+},
+(table) => ({
+  samplingRulesProjectIdFkey: foreignKey({
+    columns: [table.projectId],
+    foreignColumns: [projects.id],
+    name: "sampling_rules_project_id_fkey"
+  }).onUpdate("cascade").onDelete("cascade"),
+}));
+
+export const deadLetteredSpans = pgTable("dead_lettered_spans", {
+  id: uuid().primaryKey().notNull(),
+  deadLetteredAt: timestamp("dead_lettered_at", { withTimezone: true, mode: 'string' }).defaultNow().notNull(),
+  // You can use { mode: "bigint" } if numbers are exceeding js number limitations
+  retryCount: bigint("retry_count", { mode: "number" }).default(sql`'0'`).notNull(),
+  error: text(),
+  payload: text().notNull(),
+},
+(table) => ({
+  deadLetteredAtIdIdx: index("dead_lettered_spans_dead_lettered_at_id_idx").using("btree", table.deadLetteredAt.asc().nullsLast(), table.id.asc().nullsLast()),
+}));
+
 export const workspaceUsage = pgTable("workspace_usage", {
   workspaceId: uuid("workspace_id").defaultRandom().primaryKey().notNull(),
   // You can use { mode: "bigint" } if numbers are exceeding js number limitations
@@ -365,6 +467,7 @@ export const projects = pgTable("projects", {
   createdAt: timestamp("created_at", { withTimezone: true, mode: 'string' }).defaultNow().notNull(),
   name: text().notNull(),
   workspaceId: uuid("workspace_id").notNull(),
+  retentionDays: integer("retention_days"),
 },
 (table) => ({
   workspaceIdIdx: index("projects_workspace_id_idx").using("btree", table.workspaceId.asc().nullsLast()),
@@ -383,6 +486,8 @@ export const projectApiKeys = pgTable("project_api_keys", {
   shorthand: text().default('').notNull(),
   hash: text().default('').notNull(),
   id: uuid().defaultRandom().primaryKey().notNull(),
+  rateLimitPerSecond: doublePrecision("rate_limit_per_second"),
+  rateLimitBurst: integer("rate_limit_burst"),
 },
 (table) => ({
   publicProjectApiKeysProjectIdFkey: foreignKey({